@@ -0,0 +1,31 @@
+//! Skeleton for forwarding chat traffic somewhere else. Replace `forward`
+//! with a call to whatever you're bridging to; here it prints JSON lines.
+//!
+//! cargo run --example bridge_bot -- ws://127.0.0.1:8080 token-charlie
+
+use push::bot::{Bot, BotEvent};
+use push::protocol::ServerMessage;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let url = args.next().unwrap_or_else(|| "ws://127.0.0.1:8080".into());
+    let token = args.next().unwrap_or_else(|| "token-charlie".into());
+
+    let mut bot = Bot::connect(&url, &token).await?;
+
+    while let Some(event) = bot.next_event().await {
+        if let BotEvent::Message(msg) = event
+            && let Err(e) = forward(&msg).await
+        {
+            eprintln!("forward failed: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn forward(msg: &ServerMessage) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string(msg)?);
+    Ok(())
+}
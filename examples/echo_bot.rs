@@ -0,0 +1,33 @@
+//! Echoes DMs back to their sender and answers `!ping` in the main chat.
+//!
+//! cargo run --example echo_bot -- ws://127.0.0.1:8080 token-charlie
+
+use push::bot::{Bot, BotEvent};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let url = args.next().unwrap_or_else(|| "ws://127.0.0.1:8080".into());
+    let token = args.next().unwrap_or_else(|| "token-charlie".into());
+
+    let mut bot = Bot::connect(&url, &token).await?;
+
+    while let Some(event) = bot.next_event().await {
+        match event {
+            BotEvent::Connected => println!("connected"),
+            BotEvent::Disconnected => println!("disconnected"),
+            BotEvent::Reconnecting { attempt, delay } => {
+                println!("reconnecting in {:?} (attempt {})", delay, attempt)
+            }
+            BotEvent::Message(msg) if msg.to.is_some() => {
+                bot.reply(&msg, &msg.content)?;
+            }
+            BotEvent::Message(msg) if msg.content.trim() == "!ping" => {
+                bot.reply(&msg, &format!("pong, {}", msg.from))?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,858 @@
+use futures_util::{SinkExt, StreamExt};
+use push::client::{
+    AuthMode, ConnectError, INITIAL_BACKOFF, WsStream, connect, jittered, next_backoff,
+};
+use push::protocol::{
+    ChatMessage, ClientEvent, ClientFrame, ServerEvent, ServerFrame, ServerMessage, Severity,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+const USAGE: &str =
+    "Usage: push_client <ws-url> <token> [--auth message|header] [--prefix <command-prefix>]";
+
+struct Options {
+    url: String,
+    token: String,
+    auth: AuthMode,
+    // Starts a command; doubled, it sends the rest of the line literally
+    prefix: String,
+}
+
+enum Input {
+    Send(ClientEvent),
+    Quit,
+}
+
+enum SessionEnd {
+    Quit,
+    Dropped,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // --- STDIN READER ---
+    let (line_tx, mut line_rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut backoff = INITIAL_BACKOFF;
+    let mut remembered = Remembered::default();
+    loop {
+        let result = match remembered.session_token.take() {
+            Some(token) => match connect(&options.url, &token, options.auth).await {
+                // Expired or already used: fall back to the original token
+                Err(ConnectError::AuthFailed(_)) => {
+                    connect(&options.url, &options.token, options.auth).await
+                }
+                result => result,
+            },
+            None => connect(&options.url, &options.token, options.auth).await,
+        };
+        let delay = match result {
+            Ok(ws) => {
+                println!("{} connected to {}", clock(), options.url);
+                backoff = INITIAL_BACKOFF;
+                match run_session(ws, &mut line_rx, &options.prefix, &mut remembered).await {
+                    SessionEnd::Quit => return Ok(()),
+                    SessionEnd::Dropped => println!("{} connection lost", clock()),
+                }
+                backoff
+            }
+            Err(e) => match e.retry_delay(backoff) {
+                Some(delay) => {
+                    println!("{} {}", clock(), e);
+                    delay
+                }
+                None => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+        };
+
+        let delay = jittered(delay);
+        println!("{} reconnecting in {:.1}s", clock(), delay.as_secs_f32());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+        backoff = next_backoff(backoff);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let url = args.next()?;
+    let token = args.next()?;
+    let mut auth = AuthMode::Message;
+    let mut prefix = "/".to_string();
+
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--auth", Some(mode)) if mode == "message" => auth = AuthMode::Message,
+            ("--auth", Some(mode)) if mode == "header" => auth = AuthMode::Header,
+            ("--prefix", Some(value)) if !value.trim().is_empty() => prefix = value,
+            _ => return None,
+        }
+    }
+
+    Some(Options {
+        url,
+        token,
+        auth,
+        prefix,
+    })
+}
+
+// --- SESSION ---
+// Kept across reconnects
+#[derive(Default)]
+struct Remembered {
+    // Latest reconnect token from the server; single use, so it's taken
+    session_token: Option<String>,
+    // The MOTD is only shown again when it changes
+    motd_hash: Option<String>,
+}
+
+async fn run_session(
+    ws: WsStream,
+    line_rx: &mut mpsc::UnboundedReceiver<String>,
+    prefix: &str,
+    remembered: &mut Remembered,
+) -> SessionEnd {
+    let (mut write, mut read) = ws.split();
+
+    loop {
+        tokio::select! {
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<ServerFrame>(text.as_ref()) {
+                        Ok(ServerFrame::TokenRefresh { token, .. }) => {
+                            remembered.session_token = Some(token)
+                        }
+                        Ok(ServerFrame::PresenceProbe) => {
+                            let json = serde_json::to_string(&ClientFrame::ProbeReply).unwrap();
+                            if write.send(Message::Text(json.into())).await.is_err() {
+                                return SessionEnd::Dropped;
+                            }
+                        }
+                        Ok(ServerFrame::Motd { hash, .. })
+                            if remembered.motd_hash.as_ref() == Some(&hash) => {}
+                        Ok(ServerFrame::Motd { hash, .. }) => {
+                            remembered.motd_hash = Some(hash);
+                            print_incoming(text.as_ref());
+                        }
+                        _ => print_incoming(text.as_ref()),
+                    }
+                }
+                Some(Ok(Message::Close(Some(frame)))) if !frame.reason.is_empty() => {
+                    println!("{} closed by server: {}", clock(), frame.reason);
+                    return SessionEnd::Dropped;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return SessionEnd::Dropped,
+                Some(Ok(_)) => {}
+            },
+            line = line_rx.recv() => {
+                let Some(line) = line else {
+                    // stdin closed
+                    let _ = write.send(Message::Close(None)).await;
+                    return SessionEnd::Quit;
+                };
+                let outgoing = match parse_input(&line, prefix) {
+                    Some(Input::Send(outgoing)) => outgoing,
+                    Some(Input::Quit) => {
+                        let _ = write.send(Message::Close(None)).await;
+                        return SessionEnd::Quit;
+                    }
+                    None => continue,
+                };
+                let json = serde_json::to_string(&outgoing).unwrap();
+                if write.send(Message::Text(json.into())).await.is_err() {
+                    return SessionEnd::Dropped;
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                let _ = write.send(Message::Close(None)).await;
+                return SessionEnd::Quit;
+            }
+        }
+    }
+}
+
+// Lines starting with the prefix are commands; a doubled prefix sends the
+// rest literally, so `//shrug` goes out as `/shrug`. `@user text` is a DM.
+fn parse_input(line: &str, prefix: &str) -> Option<Input> {
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    if let Some(rest) = line.strip_prefix(prefix) {
+        if rest.starts_with(prefix) {
+            return Some(Input::Send(ClientEvent::Message(ChatMessage {
+                to: None,
+                content: rest.to_string(),
+                content_type: None,
+                members: None,
+                conversation_id: None,
+                signature: None,
+                client_id: None,
+            })));
+        }
+        return parse_command(rest, prefix);
+    }
+
+    if let Some(rest) = line.strip_prefix('@') {
+        return match rest.split_once(' ') {
+            Some((target, content)) if !target.is_empty() => {
+                Some(Input::Send(ClientEvent::Message(ChatMessage {
+                    to: Some(target.to_string()),
+                    content: content.to_string(),
+                    content_type: None,
+                    members: None,
+                    conversation_id: None,
+                    signature: None,
+                    client_id: None,
+                })))
+            }
+            _ => {
+                eprintln!("Invalid DM format. Use: @username message");
+                None
+            }
+        };
+    }
+
+    Some(Input::Send(ClientEvent::Message(ChatMessage {
+        to: None,
+        content: line.to_string(),
+        content_type: None,
+        members: None,
+        conversation_id: None,
+        signature: None,
+        client_id: None,
+    })))
+}
+
+// `quit`, `history user [n]`, `kick user [reason]`,
+// `kickmany user,user,... [reason]`, `banmany user,user,... [reason]`,
+// `mute user duration`, `unmute user`, `shadowban user`, `unshadowban user`,
+// `shadowmute user`, `unshadowmute user`, `announce text`,
+// `metrics`, `audit [user]`, `presence <unix-time>`,
+// `setmotd [--broadcast] [text]`, `schedule duration [@user] text`,
+// `scheduled`, `unschedule id`, `quiet start end [tz] [--mentions]`,
+// `contact user [--notify]`, `uncontact user`, `contacts`,
+// `group user,user,... text`, `time`, `keywords [word ...]`,
+// `ban user [duration] [reason]`, `unban user`, `bans` and
+// `deactivate user [--anonymize]`
+fn parse_command(command: &str, prefix: &str) -> Option<Input> {
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    let args = args.trim();
+
+    let frame = match name {
+        "quit" => return Some(Input::Quit),
+        "history" => {
+            let mut parts = args.split_whitespace();
+            let with = parts.next()?.to_string();
+            let limit = parts.next().and_then(|n| n.parse().ok());
+            ClientFrame::FetchDmHistory { with, limit }
+        }
+        "kick" => {
+            let (user, reason) = match args.split_once(' ') {
+                Some((user, reason)) => (user, Some(reason.trim().to_string())),
+                None => (args, None),
+            };
+            if user.is_empty() {
+                return None;
+            }
+            ClientFrame::Kick {
+                user: user.to_string(),
+                reason,
+            }
+        }
+        "kickmany" | "banmany" => {
+            let (users, reason) = match args.split_once(' ') {
+                Some((users, reason)) => (users, Some(reason.trim().to_string())),
+                None => (args, None),
+            };
+            let users: Vec<String> = users
+                .split(',')
+                .filter(|user| !user.is_empty())
+                .map(str::to_string)
+                .collect();
+            if users.is_empty() {
+                return None;
+            }
+            if name == "banmany" {
+                ClientFrame::BanMany { users, reason }
+            } else {
+                ClientFrame::KickMany { users, reason }
+            }
+        }
+        "mute" => {
+            let mut parts = args.split_whitespace();
+            let user = parts.next()?.to_string();
+            let Some(seconds) = parts.next().and_then(parse_duration) else {
+                eprintln!(
+                    "Usage: {}mute <user> <duration, e.g. 90s, 10m, 2h, 1d>",
+                    prefix
+                );
+                return None;
+            };
+            ClientFrame::Mute { user, seconds }
+        }
+        "unmute" if !args.is_empty() => ClientFrame::Unmute {
+            user: args.to_string(),
+        },
+        "ban" => {
+            let mut parts = args.splitn(2, ' ');
+            let user = parts.next().filter(|user| !user.is_empty())?.to_string();
+            let rest = parts.next().unwrap_or("").trim();
+            let (duration, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+            let (seconds, reason) = match parse_duration(duration) {
+                Some(seconds) => (Some(seconds), reason.trim()),
+                None => (None, rest),
+            };
+            ClientFrame::Ban {
+                user: Some(user),
+                token: None,
+                seconds,
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+            }
+        }
+        "unban" if !args.is_empty() => ClientFrame::Unban {
+            user: Some(args.to_string()),
+            token: None,
+        },
+        "bans" => ClientFrame::ListBans,
+        "deactivate" => {
+            let mut parts = args.split_whitespace();
+            let user = parts.next()?.to_string();
+            ClientFrame::Deactivate {
+                user,
+                anonymize: parts.next() == Some("--anonymize"),
+            }
+        }
+        "shadowban" if !args.is_empty() => ClientFrame::ShadowBan {
+            user: args.to_string(),
+        },
+        "unshadowban" if !args.is_empty() => ClientFrame::ShadowUnban {
+            user: args.to_string(),
+        },
+        "shadowmute" if !args.is_empty() => ClientFrame::ShadowMute {
+            user: args.to_string(),
+        },
+        "unshadowmute" if !args.is_empty() => ClientFrame::ShadowUnmute {
+            user: args.to_string(),
+        },
+        "announce" if !args.is_empty() => ClientFrame::Announce {
+            text: args.to_string(),
+        },
+        "metrics" => ClientFrame::SubscribeMetrics,
+        "setmotd" => {
+            let (broadcast, text) = match args.strip_prefix("--broadcast") {
+                Some(text) => (true, text.trim()),
+                None => (false, args),
+            };
+            ClientFrame::SetMotd {
+                text: text.to_string(),
+                broadcast,
+            }
+        }
+        "presence" => match args.parse() {
+            Ok(timestamp) => ClientFrame::PresenceAt { timestamp },
+            Err(_) => {
+                eprintln!("Usage: {}presence <unix-time>", prefix);
+                return None;
+            }
+        },
+        "schedule" => {
+            let (delay, text) = args.split_once(' ').unwrap_or((args, ""));
+            let (to, content) = match text.trim().strip_prefix('@') {
+                Some(rest) => match rest.split_once(' ') {
+                    Some((target, content)) => (Some(target.to_string()), content),
+                    None => (None, ""),
+                },
+                None => (None, text.trim()),
+            };
+            let Some(seconds) = parse_duration(delay).filter(|_| !content.is_empty()) else {
+                eprintln!("Usage: {}schedule <duration> [@user] <message>", prefix);
+                return None;
+            };
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            ClientFrame::Schedule {
+                message: ChatMessage {
+                    to,
+                    content: content.to_string(),
+                    content_type: None,
+                    members: None,
+                    conversation_id: None,
+                    signature: None,
+                    client_id: None,
+                },
+                deliver_at: now + seconds,
+            }
+        }
+        "scheduled" => ClientFrame::ListScheduled,
+        "contact" => {
+            let mut parts = args.split_whitespace();
+            let user = parts.next()?.to_string();
+            ClientFrame::AddContact {
+                user,
+                notify_on_online: parts.next() == Some("--notify"),
+            }
+        }
+        "uncontact" if !args.is_empty() => ClientFrame::RemoveContact {
+            user: args.to_string(),
+        },
+        "contacts" => ClientFrame::ListContacts,
+        "keywords" => ClientFrame::SubscribeKeywords {
+            words: args.split_whitespace().map(str::to_string).collect(),
+        },
+        "time" => ClientFrame::Time {
+            client_time_ms: now_ms(),
+        },
+        "group" => {
+            let Some((users, content)) = args.split_once(' ') else {
+                eprintln!("Usage: {}group <user,user,...> <message>", prefix);
+                return None;
+            };
+            return Some(Input::Send(ClientEvent::Message(ChatMessage {
+                to: None,
+                content: content.trim().to_string(),
+                content_type: None,
+                members: Some(users.split(',').map(str::to_string).collect()),
+                conversation_id: None,
+                signature: None,
+                client_id: None,
+            })));
+        }
+        "quiet" => {
+            let mentions = args.split_whitespace().any(|arg| arg == "--mentions");
+            let mut parts = args.split_whitespace().filter(|arg| *arg != "--mentions");
+            let (Some(start), Some(end)) = (parts.next(), parts.next()) else {
+                eprintln!(
+                    "Usage: {}quiet <HH:MM> <HH:MM> [UTC|+02:00] [--mentions]",
+                    prefix
+                );
+                return None;
+            };
+            ClientFrame::SetQuietHours {
+                start: start.to_string(),
+                end: end.to_string(),
+                tz: parts.next().unwrap_or("UTC").to_string(),
+                mentions,
+            }
+        }
+        "unschedule" if !args.is_empty() => ClientFrame::CancelScheduled {
+            id: args.to_string(),
+        },
+        "audit" => ClientFrame::Audit {
+            since: None,
+            until: None,
+            target: (!args.is_empty()).then(|| args.to_string()),
+            offset: 0,
+            limit: None,
+        },
+        _ => {
+            eprintln!(
+                "Unknown command {}{}; use {}{}{} to send it as text",
+                prefix, name, prefix, prefix, name
+            );
+            return None;
+        }
+    };
+
+    Some(Input::Send(ClientEvent::Frame(frame)))
+}
+
+// `90`, `90s`, `10m`, `2h` or `1d`, in seconds
+fn parse_duration(text: &str) -> Option<u64> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+// --- OUTPUT ---
+const RESET: &str = "\x1b[0m";
+const SYSTEM_COLOR: &str = "\x1b[2;33m";
+const WARNING_COLOR: &str = "\x1b[1;33m";
+const ERROR_COLOR: &str = "\x1b[1;31m";
+const SENDER_COLORS: [&str; 6] = [
+    "\x1b[31m", "\x1b[32m", "\x1b[34m", "\x1b[35m", "\x1b[36m", "\x1b[91m",
+];
+
+fn print_incoming(text: &str) {
+    let Ok(event) = serde_json::from_str::<ServerEvent>(text) else {
+        println!("{} {}", clock(), text);
+        return;
+    };
+
+    match event {
+        ServerEvent::Frame(ServerFrame::Error { code, message }) => {
+            println!(
+                "{} {}! {} ({}){}",
+                clock(),
+                ERROR_COLOR,
+                message,
+                code,
+                RESET
+            );
+        }
+        ServerEvent::Frame(ServerFrame::System {
+            severity,
+            text,
+            context,
+        }) => {
+            let color = match severity {
+                Severity::Info => SYSTEM_COLOR,
+                Severity::Warning => WARNING_COLOR,
+                Severity::Error => ERROR_COLOR,
+            };
+            let announcement = context.is_some_and(|c| c["announcement"] == true);
+            if announcement {
+                println!("{} {}** {} **{}", clock(), WARNING_COLOR, text, RESET);
+            } else {
+                println!("{} {}* {}{}", clock(), color, text, RESET);
+            }
+        }
+        ServerEvent::Frame(ServerFrame::DmHistory { with, messages }) => {
+            println!(
+                "{} {}* last {} messages with {}{}",
+                clock(),
+                SYSTEM_COLOR,
+                messages.len(),
+                with,
+                RESET
+            );
+            for msg in &messages {
+                print_message(msg);
+            }
+        }
+        ServerEvent::Frame(ServerFrame::Metrics {
+            connected,
+            msgs_per_sec,
+            msgs_total,
+            muted,
+            ..
+        }) => {
+            println!(
+                "{} {}* {} connected, {:.1} msg/s, {} total, {} muted{}",
+                clock(),
+                SYSTEM_COLOR,
+                connected,
+                msgs_per_sec,
+                msgs_total,
+                muted,
+                RESET
+            );
+        }
+        ServerEvent::Frame(ServerFrame::AuditLog {
+            entries,
+            next_offset,
+        }) => {
+            println!(
+                "{} {}* {} audit entries{}{}",
+                clock(),
+                SYSTEM_COLOR,
+                entries.len(),
+                if next_offset.is_some() {
+                    ", more available"
+                } else {
+                    ""
+                },
+                RESET
+            );
+            for entry in &entries {
+                println!(
+                    "  {} {} {} {} {}{}",
+                    time_of_day(entry.timestamp),
+                    entry.actor,
+                    entry.action,
+                    entry.target.as_deref().unwrap_or("-"),
+                    entry.outcome,
+                    entry
+                        .reason
+                        .as_ref()
+                        .map(|reason| format!(" ({})", reason))
+                        .unwrap_or_default()
+                );
+            }
+        }
+        ServerEvent::Frame(
+            ServerFrame::KickResults { results } | ServerFrame::BanResults { results },
+        ) => {
+            let mut results: Vec<_> = results.into_iter().collect();
+            results.sort();
+            for (user, result) in results {
+                println!(
+                    "{} {}* {}: {}{}",
+                    clock(),
+                    SYSTEM_COLOR,
+                    user,
+                    result,
+                    RESET
+                );
+            }
+        }
+        ServerEvent::Frame(ServerFrame::PresenceAt { timestamp, online }) => {
+            println!(
+                "{} {}* online at {}: {}{}",
+                clock(),
+                SYSTEM_COLOR,
+                time_of_day(timestamp),
+                if online.is_empty() {
+                    "nobody".to_string()
+                } else {
+                    online.join(", ")
+                },
+                RESET
+            );
+        }
+        ServerEvent::Frame(ServerFrame::Scheduled { id, deliver_at }) => {
+            println!(
+                "{} {}* scheduled for {} ({}){}",
+                clock(),
+                SYSTEM_COLOR,
+                time_of_day(deliver_at),
+                id,
+                RESET
+            );
+        }
+        ServerEvent::Frame(ServerFrame::ScheduledList { messages }) => {
+            println!(
+                "{} {}* {} scheduled messages{}",
+                clock(),
+                SYSTEM_COLOR,
+                messages.len(),
+                RESET
+            );
+            for scheduled in &messages {
+                println!(
+                    "  {} {}{} ({})",
+                    time_of_day(scheduled.deliver_at),
+                    scheduled
+                        .to
+                        .as_ref()
+                        .map(|to| format!("@{} ", to))
+                        .unwrap_or_default(),
+                    scheduled.content,
+                    scheduled.id
+                );
+            }
+        }
+        ServerEvent::Frame(ServerFrame::Bans { bans }) => {
+            println!(
+                "{} {}* {} active bans{}",
+                clock(),
+                SYSTEM_COLOR,
+                bans.len(),
+                RESET
+            );
+            for ban in &bans {
+                println!(
+                    "  {} by {}{}{}",
+                    ban.user.as_deref().unwrap_or("(token)"),
+                    ban.by,
+                    ban.until
+                        .map(|until| format!(" until {}", time_of_day(until)))
+                        .unwrap_or_default(),
+                    ban.reason
+                        .as_deref()
+                        .map(|reason| format!(": {}", reason))
+                        .unwrap_or_default(),
+                );
+            }
+        }
+        ServerEvent::Frame(ServerFrame::Contacts { contacts }) => {
+            let contacts: Vec<String> = contacts
+                .iter()
+                .map(|contact| {
+                    format!(
+                        "{}{}",
+                        contact.user,
+                        if contact.online { " (online)" } else { "" }
+                    )
+                })
+                .collect();
+            println!(
+                "{} {}* contacts: {}{}",
+                clock(),
+                SYSTEM_COLOR,
+                if contacts.is_empty() {
+                    "none".to_string()
+                } else {
+                    contacts.join(", ")
+                },
+                RESET
+            );
+        }
+        ServerEvent::Frame(ServerFrame::Conversation {
+            conversation_id,
+            members,
+        }) => {
+            println!(
+                "{} {}* group {} with {}{}",
+                clock(),
+                SYSTEM_COLOR,
+                conversation_id,
+                members.join(", "),
+                RESET
+            );
+        }
+        ServerEvent::Frame(ServerFrame::Time {
+            client_time_ms,
+            server_time_ms,
+        }) => {
+            let round_trip = now_ms().saturating_sub(client_time_ms);
+            let offset = server_time_ms as i64 - (client_time_ms + round_trip / 2) as i64;
+            println!(
+                "{} {}* server clock is {:+}ms from ours (round trip {}ms){}",
+                clock(),
+                SYSTEM_COLOR,
+                offset,
+                round_trip,
+                RESET
+            );
+        }
+        ServerEvent::Frame(ServerFrame::Motd { text, .. }) => {
+            for line in text.lines() {
+                println!("{} {}| {}{}", clock(), WARNING_COLOR, line, RESET);
+            }
+        }
+        // Picked up by the session loop, nothing to show
+        ServerEvent::Frame(ServerFrame::TokenRefresh { .. } | ServerFrame::PresenceProbe) => {}
+        // Never asked for
+        ServerEvent::Frame(ServerFrame::ServerHeartbeat { .. }) => {}
+        ServerEvent::Message(msg) => print_message(&msg),
+    }
+}
+
+fn print_message(msg: &ServerMessage) {
+    let scope = match (&msg.members, &msg.to) {
+        (Some(members), _) => format!("[{}] ", members.join(", ")),
+        (None, Some(_)) => "[DM] ".to_string(),
+        (None, None) => String::new(),
+    };
+    let verified = if msg.verified { " ✓" } else { "" };
+    println!(
+        "{} {}{}{}{}{}: {}",
+        clock(),
+        scope,
+        sender_color(&msg.from),
+        msg.from,
+        RESET,
+        verified,
+        msg.content
+    );
+    if let Some(translated) = &msg.translated {
+        println!("         {}  {}{}", SYSTEM_COLOR, translated, RESET);
+    }
+}
+
+// Stable per-sender color so a conversation is easy to follow
+fn sender_color(name: &str) -> &'static str {
+    let hash = name.bytes().fold(0usize, |acc, b| {
+        acc.wrapping_mul(31).wrapping_add(b as usize)
+    });
+    SENDER_COLORS[hash % SENDER_COLORS.len()]
+}
+
+// HH:MM:SS in UTC
+fn clock() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    time_of_day(secs)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// HH:MM:SS (UTC) of a unix timestamp
+fn time_of_day(secs: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        (secs / 3600) % 24,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The content of a plain chat message, or `None` for anything else
+    fn chat(input: Option<Input>) -> Option<String> {
+        match input {
+            Some(Input::Send(ClientEvent::Message(msg))) => Some(msg.content),
+            _ => None,
+        }
+    }
+
+    fn is_kick(input: Option<Input>, target: &str) -> bool {
+        matches!(
+            input,
+            Some(Input::Send(ClientEvent::Frame(ClientFrame::Kick { user, .. }))) if user == target
+        )
+    }
+
+    #[test]
+    fn doubled_prefix_sends_literal_text() {
+        assert_eq!(chat(parse_input("//shrug", "/")).as_deref(), Some("/shrug"));
+        assert_eq!(chat(parse_input("//", "/")).as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn single_prefix_is_a_command() {
+        assert!(is_kick(parse_input("/kick bob spam", "/"), "bob"));
+        assert!(matches!(parse_input("/quit", "/"), Some(Input::Quit)));
+        // Unknown commands are reported locally, never sent as chat
+        assert!(parse_input("/shrug", "/").is_none());
+    }
+
+    #[test]
+    fn custom_prefix_frees_the_slash() {
+        assert!(is_kick(parse_input("!kick bob", "!"), "bob"));
+        assert_eq!(
+            chat(parse_input("!!kick bob", "!")).as_deref(),
+            Some("!kick bob")
+        );
+        assert_eq!(
+            chat(parse_input("/kick bob", "!")).as_deref(),
+            Some("/kick bob")
+        );
+    }
+}
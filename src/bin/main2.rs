@@ -1,31 +1,37 @@
 use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
+use push::protocol::{ChatMessage, ServerFrame, ServerMessage, Severity};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::{mpsc, Mutex};
 use tokio_tungstenite::accept_hdr_async;
 use tokio_tungstenite::tungstenite::{
-    handshake::server::{Request, Response},
+    handshake::server::{Callback, ErrorResponse, Request, Response},
     http::{Response as HttpResponse, StatusCode},
     Message,
 };
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ChatMessage {
-    to: Option<String>,
-    content: String,
-}
+type Clients = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<String>>>>;
 
-#[derive(Serialize, Debug)]
-struct ServerMessage {
-    from: String,
-    to: Option<String>,
-    content: String,
+// Keeps the username from the handshake headers, or refuses the upgrade. A
+// `Callback` impl rather than a closure, since its large error type is
+// fixed by the trait.
+struct CaptureUsername(Arc<std::sync::Mutex<Option<String>>>);
+
+impl Callback for CaptureUsername {
+    fn on_request(self, req: &Request, res: Response) -> Result<Response, ErrorResponse> {
+        if let Some(username) = extract_username(req) {
+            *self.0.lock().unwrap() = Some(username);
+            Ok(res)
+        } else {
+            Err(HttpResponse::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Some("Unauthorized".to_string()))
+                .unwrap())
+        }
+    }
 }
 
-type Clients = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<String>>>>;
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let listener = TcpListener::bind("127.0.0.1:8080").await?;
@@ -38,20 +44,12 @@ async fn main() -> anyhow::Result<()> {
 
         tokio::spawn(async move {
             // Store username during handshake (CORRECT WAY)
-            let username_holder = Arc::new(Mutex::new(None::<String>));
+            // A std mutex: the callback runs inside the runtime, where
+            // `blocking_lock` would panic
+            let username_holder = Arc::new(std::sync::Mutex::new(None::<String>));
             let username_holder_cb = username_holder.clone();
 
-            let callback = move |req: &Request, _res: Response| {
-                if let Some(username) = extract_username(req) {
-                    *username_holder_cb.blocking_lock() = Some(username);
-                    Ok(Response::new(()))
-                } else {
-                    Err(HttpResponse::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(Some("Unauthorized".to_string()))
-                        .unwrap())
-                }
-            };
+            let callback = CaptureUsername(username_holder_cb);
 
             let ws_stream = match accept_hdr_async(stream, callback).await {
                 Ok(ws) => ws,
@@ -63,7 +61,7 @@ async fn main() -> anyhow::Result<()> {
 
             let username = username_holder
                 .lock()
-                .await
+                .unwrap()
                 .clone()
                 .unwrap_or_else(|| "anonymous".into());
 
@@ -72,26 +70,23 @@ async fn main() -> anyhow::Result<()> {
             let (mut write, mut read) = ws_stream.split();
             let (tx, mut rx) = mpsc::unbounded_channel();
 
-            clients.lock().await.insert(username.clone(), tx);
+            clients.lock().await.insert(username.clone(), tx.clone());
 
-            broadcast_system(&clients, &format!("{} joined the chat", username)).await;
+            broadcast_presence(&clients, &username, true).await;
 
             // Writer task
-            let writer_clients = clients.clone();
-            let writer_username = username.clone();
-            let writer = tokio::spawn(async move {
+            let mut writer = tokio::spawn(async move {
                 while let Some(msg) = rx.recv().await {
                     if write.send(Message::Text(msg.into())).await.is_err() {
                         break;
                     }
                 }
-                writer_clients.lock().await.remove(&writer_username);
             });
 
             // Reader task
             let reader_clients = clients.clone();
             let reader_username = username.clone();
-            let reader = tokio::spawn(async move {
+            let mut reader = tokio::spawn(async move {
                 while let Some(Ok(msg)) = read.next().await {
                     if let Message::Text(text) = msg {
                         match serde_json::from_str::<ChatMessage>(text.as_ref()) {
@@ -100,6 +95,14 @@ async fn main() -> anyhow::Result<()> {
                                     from: reader_username.clone(),
                                     to: parsed.to,
                                     content: parsed.content,
+                                    content_type: parsed.content_type,
+                                    scheduled: false,
+                                    suppress_notification: false,
+                                    conversation_id: None,
+                                    members: None,
+                                    translated: None,
+                                    verified: false,
+                                    client_id: None,
                                 };
                                 route_message(&reader_clients, message).await;
                             }
@@ -111,15 +114,49 @@ async fn main() -> anyhow::Result<()> {
                 }
             });
 
-            let _ = tokio::join!(writer, reader);
+            // Whichever side stops first ends the connection
+            let writer_done = tokio::select! {
+                _ = &mut writer => {
+                    reader.abort();
+                    true
+                }
+                _ = &mut reader => false,
+            };
 
-            broadcast_system(&clients, &format!("{} left the chat", username)).await;
+            // With the map entry and `tx` gone the channel closes, so the
+            // writer flushes what's queued and stops
+            let removed = remove_client(&clients, &username, &tx).await;
+            drop(tx);
+            if !writer_done {
+                let _ = writer.await;
+            }
+            if removed {
+                broadcast_presence(&clients, &username, false).await;
+            }
         });
     }
 
     Ok(())
 }
 
+// Only removes the entry if it still belongs to this connection, so a
+// stale connection closing late can't evict the one that replaced it
+async fn remove_client(
+    clients: &Clients,
+    username: &str,
+    tx: &mpsc::UnboundedSender<String>,
+) -> bool {
+    let mut clients_guard = clients.lock().await;
+    if !clients_guard
+        .get(username)
+        .is_some_and(|current| current.same_channel(tx))
+    {
+        return false;
+    }
+    clients_guard.remove(username);
+    true
+}
+
 async fn route_message(clients: &Clients, msg: ServerMessage) {
     let clients_guard = clients.lock().await;
 
@@ -140,19 +177,40 @@ async fn route_message(clients: &Clients, msg: ServerMessage) {
     }
 }
 
-async fn broadcast_system(clients: &Clients, text: &str) {
-    let msg = ServerMessage {
-        from: "SYSTEM".into(),
-        to: None,
-        content: text.into(),
+async fn broadcast_system(
+    clients: &Clients,
+    severity: Severity,
+    text: &str,
+    context: Option<serde_json::Value>,
+) {
+    let frame = ServerFrame::System {
+        severity,
+        text: text.into(),
+        context,
     };
 
-    let json = serde_json::to_string(&msg).unwrap();
+    let json = serde_json::to_string(&frame).unwrap();
     for tx in clients.lock().await.values() {
         let _ = tx.send(json.clone());
     }
 }
 
+async fn broadcast_presence(clients: &Clients, username: &str, joined: bool) {
+    let (event, verb) = if joined {
+        ("join", "joined")
+    } else {
+        ("leave", "left")
+    };
+
+    broadcast_system(
+        clients,
+        Severity::Info,
+        &format!("{} {} the chat", username, verb),
+        Some(serde_json::json!({ "event": event, "user": username })),
+    )
+    .await;
+}
+
 fn extract_username(req: &Request) -> Option<String> {
     let auth = req.headers().get("Authorization")?.to_str().ok()?;
     if !auth.starts_with("Bearer ") {
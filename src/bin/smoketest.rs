@@ -0,0 +1,261 @@
+use futures_util::{SinkExt, StreamExt};
+use push::client::{AuthMode, ConnectError, WsStream, connect};
+use push::protocol::{ChatMessage, ServerEvent, ServerFrame};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+const USAGE: &str = "Usage: push_smoketest <ws-url> <token-a> <token-b> [--auth message|header]";
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+const BAD_TOKEN: &str = "smoketest-invalid-token";
+
+struct Options {
+    url: String,
+    token_a: String,
+    token_b: String,
+    auth: AuthMode,
+}
+
+#[derive(Serialize)]
+struct Step {
+    name: &'static str,
+    ok: bool,
+    ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct Report {
+    ok: bool,
+    steps: Vec<Step>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    round_trip_ms: Option<f64>,
+}
+
+impl Report {
+    // Records the outcome; `None` stops the run at the first failure
+    fn step<T>(
+        &mut self,
+        name: &'static str,
+        started: Instant,
+        result: Result<T, String>,
+    ) -> Option<T> {
+        let ms = started.elapsed().as_millis();
+        match result {
+            Ok(value) => {
+                self.steps.push(Step {
+                    name,
+                    ok: true,
+                    ms,
+                    error: None,
+                });
+                Some(value)
+            }
+            Err(error) => {
+                self.steps.push(Step {
+                    name,
+                    ok: false,
+                    ms,
+                    error: Some(error),
+                });
+                None
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut report = Report::default();
+    report.ok = run(&options, &mut report).await.is_some();
+
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    std::process::exit(if report.ok { 0 } else { 1 });
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let url = args.next()?;
+    let token_a = args.next()?;
+    let token_b = args.next()?;
+    let mut auth = AuthMode::Message;
+
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next().as_deref()) {
+            ("--auth", Some("message")) => auth = AuthMode::Message,
+            ("--auth", Some("header")) => auth = AuthMode::Header,
+            _ => return None,
+        }
+    }
+
+    Some(Options {
+        url,
+        token_a,
+        token_b,
+        auth,
+    })
+}
+
+// --- CHECKS ---
+async fn run(options: &Options, report: &mut Report) -> Option<()> {
+    let started = Instant::now();
+    let rejected = match timeout(STEP_TIMEOUT, connect(&options.url, BAD_TOKEN, options.auth)).await
+    {
+        Ok(Err(ConnectError::AuthFailed(_))) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Ok(Ok(_)) => Err("invalid token was accepted".into()),
+        Err(_) => Err("timed out".into()),
+    };
+    report.step("bad_token_rejected", started, rejected)?;
+
+    // Names come from the server: everyone sees their own join notice first
+    let started = Instant::now();
+    let mut a = report.step("connect_a", started, open(options, &options.token_a).await)?;
+    let joined = wait_for(&mut a, |event| presence(event, "join")).await;
+    let name_a = report.step(
+        "own_join_notice",
+        started,
+        joined.map(|event| presence_user(&event)),
+    )?;
+
+    let started = Instant::now();
+    let mut b = report.step("connect_b", started, open(options, &options.token_b).await)?;
+
+    let started = Instant::now();
+    let joined = wait_for(&mut a, |event| {
+        presence(event, "join") && presence_user(event) != name_a
+    })
+    .await;
+    let name_b = report.step(
+        "join_notice",
+        started,
+        joined.map(|event| presence_user(&event)),
+    )?;
+
+    let started = Instant::now();
+    let result = exchange(&mut a, &mut b, None).await.and_then(|from| {
+        if from == name_a {
+            Ok(())
+        } else {
+            Err(format!("sender shown as {}, expected {}", from, name_a))
+        }
+    });
+    report.step("broadcast_a_to_b", started, result)?;
+
+    let started = Instant::now();
+    let result = exchange(&mut b, &mut a, None).await;
+    report.step("broadcast_b_to_a", started, result)?;
+
+    let round_trip = Instant::now();
+    let started = Instant::now();
+    let result = exchange(&mut a, &mut b, Some(&name_b)).await;
+    report.step("dm_a_to_b", started, result)?;
+
+    let started = Instant::now();
+    let result = exchange(&mut b, &mut a, Some(&name_a)).await;
+    report.step("dm_b_to_a", started, result)?;
+    report.round_trip_ms = Some(round_trip.elapsed().as_secs_f64() * 1000.0);
+
+    let started = Instant::now();
+    let _ = b.close(None).await;
+    let left = wait_for(&mut a, |event| {
+        presence(event, "leave") && presence_user(event) == name_b
+    })
+    .await;
+    report.step("leave_notice", started, left.map(|_| ()))?;
+
+    let _ = a.close(None).await;
+    Some(())
+}
+
+async fn open(options: &Options, token: &str) -> Result<WsStream, String> {
+    match timeout(STEP_TIMEOUT, connect(&options.url, token, options.auth)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("timed out".into()),
+    }
+}
+
+// Sends a unique message from `from` and waits for `to` to receive it.
+// Returns the sender's name as the receiver saw it.
+async fn exchange(
+    from: &mut WsStream,
+    to: &mut WsStream,
+    dm_to: Option<&str>,
+) -> Result<String, String> {
+    let nonce = format!("smoketest {}", uuid::Uuid::new_v4());
+    let msg = ChatMessage {
+        to: dm_to.map(str::to_string),
+        content: nonce.clone(),
+        content_type: None,
+        members: None,
+        conversation_id: None,
+        signature: None,
+        client_id: None,
+    };
+    from.send(Message::Text(serde_json::to_string(&msg).unwrap().into()))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let received = wait_for(to, |event| {
+        matches!(event, ServerEvent::Message(m) if m.content == nonce && m.to.is_some() == dm_to.is_some())
+    })
+    .await?;
+
+    match received {
+        ServerEvent::Message(m) => Ok(m.from),
+        ServerEvent::Frame(_) => unreachable!(),
+    }
+}
+
+async fn wait_for(
+    ws: &mut WsStream,
+    mut matches: impl FnMut(&ServerEvent) -> bool,
+) -> Result<ServerEvent, String> {
+    let wait = async {
+        while let Some(msg) = ws.next().await {
+            match msg {
+                Ok(Message::Text(text)) => {
+                    if let Ok(event) = serde_json::from_str::<ServerEvent>(text.as_ref())
+                        && matches(&event)
+                    {
+                        return Ok(event);
+                    }
+                }
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        Err("connection closed".to_string())
+    };
+
+    timeout(STEP_TIMEOUT, wait)
+        .await
+        .unwrap_or_else(|_| Err("timed out".into()))
+}
+
+fn presence(event: &ServerEvent, kind: &str) -> bool {
+    matches!(
+        event,
+        ServerEvent::Frame(ServerFrame::System { context: Some(context), .. })
+            if context["event"] == kind
+    )
+}
+
+fn presence_user(event: &ServerEvent) -> String {
+    match event {
+        ServerEvent::Frame(ServerFrame::System {
+            context: Some(context),
+            ..
+        }) => context["user"].as_str().unwrap_or_default().to_string(),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,404 @@
+//! A small framework for writing bots against a push server.
+//!
+//! [`Bot::connect`] authenticates, then keeps the connection alive in a
+//! background task, reconnecting and re-authenticating with backoff when it
+//! drops. Incoming traffic is exposed as a stream of [`BotEvent`]s.
+//!
+//! Messages sent while disconnected wait in a bounded outbox and go out in
+//! order once the connection is back.
+
+use crate::client::{self, AuthMode, ConnectError, INITIAL_BACKOFF, jittered, next_backoff};
+use crate::protocol::{ChatMessage, ClientFrame, ServerEvent, ServerFrame, ServerMessage};
+use futures_util::{SinkExt, Stream, StreamExt};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+
+#[derive(Debug)]
+pub enum BotEvent {
+    /// (Re)connected and authenticated.
+    Connected,
+    /// The connection dropped; the bot is reconnecting.
+    Disconnected,
+    /// About to wait `delay` before reconnect attempt number `attempt`.
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    Message(ServerMessage),
+    Frame(ServerFrame),
+}
+
+/// Messages that can wait in the outbox while disconnected.
+pub const OUTBOX_CAPACITY: usize = 256;
+
+#[derive(Debug)]
+pub enum SendError {
+    /// The bot's connection task has stopped for good.
+    Closed,
+    /// Too many messages are waiting for the connection to come back.
+    OutboxFull,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Closed => write!(f, "bot connection closed"),
+            SendError::OutboxFull => write!(f, "bot outbox is full"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+pub struct Bot {
+    outgoing: mpsc::Sender<ChatMessage>,
+    events: mpsc::UnboundedReceiver<BotEvent>,
+}
+
+impl Bot {
+    /// Connects using first-message authentication.
+    pub async fn connect(url: &str, token: &str) -> Result<Bot, ConnectError> {
+        Bot::connect_with(url, token, AuthMode::Message).await
+    }
+
+    /// Connects with the given auth mode. The first attempt must succeed;
+    /// later drops are retried in the background.
+    pub async fn connect_with(url: &str, token: &str, auth: AuthMode) -> Result<Bot, ConnectError> {
+        let ws = client::connect(url, token, auth).await?;
+        let (url, token) = (url.to_string(), token.to_string());
+        Ok(Bot::start(ws, move || {
+            let (url, token) = (url.clone(), token.clone());
+            async move { client::connect(&url, &token, auth).await }
+        }))
+    }
+
+    // Runs the bot on an authenticated connection, calling `reconnect` for
+    // a new one whenever it drops. Tests pass in-memory connections.
+    fn start<S, C, F>(ws: WebSocketStream<S>, reconnect: C) -> Bot
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        C: Fn() -> F + Send + 'static,
+        F: Future<Output = Result<WebSocketStream<S>, ConnectError>> + Send + 'static,
+    {
+        let (outgoing, outgoing_rx) = mpsc::channel(OUTBOX_CAPACITY);
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let _ = events_tx.send(BotEvent::Connected);
+
+        tokio::spawn(run(ws, reconnect, outgoing_rx, events_tx));
+
+        Bot { outgoing, events }
+    }
+
+    pub async fn next_event(&mut self) -> Option<BotEvent> {
+        self.events.recv().await
+    }
+
+    /// Answers in kind: a DM gets a DM back, a group message goes to the
+    /// group and a broadcast gets a broadcast.
+    pub fn reply(&self, msg: &ServerMessage, text: &str) -> Result<(), SendError> {
+        let to = msg.to.as_ref().map(|_| msg.from.clone());
+        self.send(ChatMessage {
+            to,
+            content: text.to_string(),
+            content_type: None,
+            members: None,
+            conversation_id: msg.conversation_id.clone(),
+            signature: None,
+            client_id: None,
+        })
+    }
+
+    pub fn send_dm(&self, user: &str, text: &str) -> Result<(), SendError> {
+        self.send(ChatMessage {
+            to: Some(user.to_string()),
+            content: text.to_string(),
+            content_type: None,
+            members: None,
+            conversation_id: None,
+            signature: None,
+            client_id: None,
+        })
+    }
+
+    pub fn broadcast(&self, text: &str) -> Result<(), SendError> {
+        self.send(ChatMessage {
+            to: None,
+            content: text.to_string(),
+            content_type: None,
+            members: None,
+            conversation_id: None,
+            signature: None,
+            client_id: None,
+        })
+    }
+
+    fn send(&self, msg: ChatMessage) -> Result<(), SendError> {
+        self.outgoing.try_send(msg).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => SendError::OutboxFull,
+            mpsc::error::TrySendError::Closed(_) => SendError::Closed,
+        })
+    }
+}
+
+impl Stream for Bot {
+    type Item = BotEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<BotEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+// --- CONNECTION TASK ---
+async fn run<S, C, F>(
+    mut ws: WebSocketStream<S>,
+    reconnect: C,
+    mut outgoing: mpsc::Receiver<ChatMessage>,
+    events: mpsc::UnboundedSender<BotEvent>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: Fn() -> F,
+    F: Future<Output = Result<WebSocketStream<S>, ConnectError>>,
+{
+    // A message whose write failed when the connection dropped
+    let mut unsent = None;
+
+    loop {
+        if !session(ws, &mut unsent, &mut outgoing, &events).await {
+            return;
+        }
+        if events.send(BotEvent::Disconnected).is_err() {
+            return;
+        }
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        ws = loop {
+            attempt += 1;
+            let delay = jittered(backoff);
+            if events
+                .send(BotEvent::Reconnecting { attempt, delay })
+                .is_err()
+            {
+                return;
+            }
+            tokio::time::sleep(delay).await;
+            match reconnect().await {
+                Ok(ws) => break ws,
+                Err(e) => match e.retry_delay(backoff) {
+                    Some(delay) => backoff = next_backoff(delay),
+                    // The token stopped working; nothing left to do
+                    None => return,
+                },
+            }
+        };
+
+        if events.send(BotEvent::Connected).is_err() {
+            return;
+        }
+    }
+}
+
+// Returns false once the bot handle is gone and the task should stop
+async fn session<S: AsyncRead + AsyncWrite + Unpin>(
+    ws: WebSocketStream<S>,
+    unsent: &mut Option<ChatMessage>,
+    outgoing: &mut mpsc::Receiver<ChatMessage>,
+    events: &mpsc::UnboundedSender<BotEvent>,
+) -> bool {
+    let (mut write, mut read) = ws.split();
+
+    if let Some(msg) = unsent.take() {
+        let json = serde_json::to_string(&msg).unwrap();
+        if write.send(Message::Text(json.into())).await.is_err() {
+            *unsent = Some(msg);
+            return true;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            msg = read.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return true,
+                    Some(Ok(_)) => continue,
+                };
+                let event = match serde_json::from_str::<ServerEvent>(text.as_ref()) {
+                    // Answered here; handlers never see probes
+                    Ok(ServerEvent::Frame(ServerFrame::PresenceProbe)) => {
+                        let json = serde_json::to_string(&ClientFrame::ProbeReply).unwrap();
+                        if write.send(Message::Text(json.into())).await.is_err() {
+                            return true;
+                        }
+                        continue;
+                    }
+                    Ok(ServerEvent::Message(msg)) => BotEvent::Message(msg),
+                    Ok(ServerEvent::Frame(frame)) => BotEvent::Frame(frame),
+                    Err(_) => continue,
+                };
+                if events.send(event).is_err() {
+                    let _ = write.send(Message::Close(None)).await;
+                    return false;
+                }
+            }
+            msg = outgoing.recv() => {
+                let Some(msg) = msg else {
+                    let _ = write.send(Message::Close(None)).await;
+                    return false;
+                };
+                let json = serde_json::to_string(&msg).unwrap();
+                if write.send(Message::Text(json.into())).await.is_err() {
+                    *unsent = Some(msg);
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use tokio::io::DuplexStream;
+    use tokio_tungstenite::{accept_async, client_async};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // The server's end of an in-memory connection
+    struct FakeServer {
+        ws: WebSocketStream<DuplexStream>,
+    }
+
+    impl FakeServer {
+        async fn send(&mut self, frame: Value) {
+            let text = frame.to_string();
+            self.ws.send(Message::Text(text.into())).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> Value {
+            loop {
+                let msg = tokio::time::timeout(TIMEOUT, self.ws.next()).await;
+                match msg.expect("bot sent nothing") {
+                    Some(Ok(Message::Text(text))) => {
+                        return serde_json::from_str(text.as_ref()).unwrap();
+                    }
+                    Some(Ok(_)) => {}
+                    other => panic!("bot connection ended: {:?}", other),
+                }
+            }
+        }
+    }
+
+    // A WebSocket over an in-memory pipe, taken to be authenticated
+    async fn pair() -> (WebSocketStream<DuplexStream>, FakeServer) {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            client_async("ws://push.test/", client_io),
+            accept_async(server_io)
+        );
+        (
+            client.unwrap().0,
+            FakeServer {
+                ws: server.unwrap(),
+            },
+        )
+    }
+
+    // A bot on an in-memory connection; each reconnect's server end comes
+    // out of the returned channel
+    async fn harness() -> (Bot, FakeServer, mpsc::UnboundedReceiver<FakeServer>) {
+        let (ws, server) = pair().await;
+        let (reconnects_tx, reconnects) = mpsc::unbounded_channel();
+        let bot = Bot::start(ws, move || {
+            let reconnects_tx = reconnects_tx.clone();
+            async move {
+                let (ws, server) = pair().await;
+                let _ = reconnects_tx.send(server);
+                Ok(ws)
+            }
+        });
+        (bot, server, reconnects)
+    }
+
+    async fn next(bot: &mut Bot) -> BotEvent {
+        let event = tokio::time::timeout(TIMEOUT, bot.next_event()).await;
+        event.expect("no bot event").expect("bot stopped")
+    }
+
+    async fn next_message(bot: &mut Bot) -> ServerMessage {
+        loop {
+            if let BotEvent::Message(msg) = next(bot).await {
+                return msg;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_in_kind() {
+        let (mut bot, mut server, _reconnects) = harness().await;
+        assert!(matches!(next(&mut bot).await, BotEvent::Connected));
+
+        server
+            .send(json!({ "from": "alice", "to": "bot", "content": "hi" }))
+            .await;
+        let msg = next_message(&mut bot).await;
+        bot.reply(&msg, "hi yourself").unwrap();
+        let reply = server.recv().await;
+        assert_eq!(reply["to"], "alice");
+        assert_eq!(reply["content"], "hi yourself");
+
+        server
+            .send(json!({ "from": "alice", "to": null, "content": "!ping" }))
+            .await;
+        let msg = next_message(&mut bot).await;
+        bot.reply(&msg, "pong").unwrap();
+        let reply = server.recv().await;
+        assert_eq!(reply["to"], Value::Null);
+        assert_eq!(reply["content"], "pong");
+    }
+
+    #[tokio::test]
+    async fn answers_presence_probes_itself() {
+        let (mut bot, mut server, _reconnects) = harness().await;
+        assert!(matches!(next(&mut bot).await, BotEvent::Connected));
+
+        server.send(json!({ "type": "presence_probe" })).await;
+        assert_eq!(server.recv().await, json!({ "type": "probe_reply" }));
+
+        server
+            .send(json!({ "type": "system", "severity": "info", "text": "hello" }))
+            .await;
+        match next(&mut bot).await {
+            BotEvent::Frame(ServerFrame::System { text, .. }) => assert_eq!(text, "hello"),
+            other => panic!("expected the notice, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_sends_what_waited_in_the_outbox() {
+        let (mut bot, server, mut reconnects) = harness().await;
+        assert!(matches!(next(&mut bot).await, BotEvent::Connected));
+
+        drop(server);
+        assert!(matches!(next(&mut bot).await, BotEvent::Disconnected));
+        bot.send_dm("alice", "sent while away").unwrap();
+        assert!(matches!(
+            next(&mut bot).await,
+            BotEvent::Reconnecting { attempt: 1, .. }
+        ));
+        assert!(matches!(next(&mut bot).await, BotEvent::Connected));
+
+        let mut server = reconnects.recv().await.unwrap();
+        let msg = server.recv().await;
+        assert_eq!(msg["to"], "alice");
+        assert_eq!(msg["content"], "sent while away");
+    }
+}
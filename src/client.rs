@@ -0,0 +1,131 @@
+//! Connecting and authenticating against a push server, shared by the
+//! terminal client and the bot framework.
+
+use crate::protocol::{AuthMessage, AuthReply};
+use futures_util::{SinkExt, StreamExt};
+use std::fmt;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Doubles `backoff`, capped at [`MAX_BACKOFF`].
+pub fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+/// Stretches `delay` by a random amount of up to half again, so clients
+/// dropped at the same moment don't all come back at once.
+pub fn jittered(delay: Duration) -> Duration {
+    delay + delay.mul_f64(rand::random::<f64>() / 2.0)
+}
+
+/// How the token is presented: `push` reads it from the first message,
+/// `push_2` from the `Authorization` header.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AuthMode {
+    Message,
+    Header,
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The token was rejected; retrying won't help.
+    AuthFailed(String),
+    /// The server is at capacity and asked us to wait this long.
+    ServerFull(u64),
+    /// This client is too old for the server; the message says where to get
+    /// a newer one, if the server knows.
+    UpgradeRequired(String),
+    Other(String),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::AuthFailed(message) => write!(f, "authentication failed: {}", message),
+            ConnectError::ServerFull(ms) => write!(f, "server is full, retry in {}ms", ms),
+            ConnectError::UpgradeRequired(message) => write!(f, "upgrade required: {}", message),
+            ConnectError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl ConnectError {
+    /// How long to wait before the next attempt, or `None` if it's pointless.
+    pub fn retry_delay(&self, backoff: Duration) -> Option<Duration> {
+        match self {
+            ConnectError::AuthFailed(_) | ConnectError::UpgradeRequired(_) => None,
+            ConnectError::ServerFull(ms) => Some(Duration::from_millis(*ms).max(backoff)),
+            ConnectError::Other(_) => Some(backoff),
+        }
+    }
+}
+
+/// Opens the WebSocket and completes authentication.
+pub async fn connect(url: &str, token: &str, auth: AuthMode) -> Result<WsStream, ConnectError> {
+    let mut request = url
+        .into_client_request()
+        .map_err(|e| ConnectError::Other(e.to_string()))?;
+
+    if auth == AuthMode::Header {
+        let bearer = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|e| ConnectError::Other(e.to_string()))?;
+        request.headers_mut().insert("Authorization", bearer);
+    }
+
+    let mut ws = match tokio_tungstenite::connect_async(request).await {
+        Ok((ws, _)) => ws,
+        Err(WsError::Http(response)) if response.status() == StatusCode::UNAUTHORIZED => {
+            return Err(ConnectError::AuthFailed("Unauthorized".into()));
+        }
+        Err(e) => return Err(ConnectError::Other(e.to_string())),
+    };
+
+    if auth == AuthMode::Header {
+        return Ok(ws);
+    }
+
+    let auth = AuthMessage {
+        token: token.to_string(),
+        console: false,
+        capabilities: None,
+        lang: None,
+        client_name: Some(env!("CARGO_PKG_NAME").into()),
+        client_version: Some(env!("CARGO_PKG_VERSION").into()),
+    };
+    ws.send(Message::Text(serde_json::to_string(&auth).unwrap().into()))
+        .await
+        .map_err(|e| ConnectError::Other(e.to_string()))?;
+
+    match ws.next().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str::<AuthReply>(text.as_ref()) {
+            Ok(AuthReply::AuthSuccess { .. }) => Ok(ws),
+            Ok(AuthReply::AuthFailed { message } | AuthReply::AuthRequired { message }) => {
+                Err(ConnectError::AuthFailed(message))
+            }
+            Ok(AuthReply::ServerFull { retry_after_ms, .. }) => {
+                Err(ConnectError::ServerFull(retry_after_ms))
+            }
+            Ok(AuthReply::UpgradeRequired {
+                message,
+                download_url,
+                ..
+            }) => Err(ConnectError::UpgradeRequired(match download_url {
+                Some(url) => format!("{} ({})", message, url),
+                None => message,
+            })),
+            Err(e) => Err(ConnectError::Other(format!("unexpected auth reply: {}", e))),
+        },
+        _ => Err(ConnectError::Other("connection closed during auth".into())),
+    }
+}
@@ -0,0 +1,6 @@
+//! Shared pieces of the push chat server and its clients.
+
+pub mod bot;
+pub mod client;
+pub mod protocol;
+pub mod server;
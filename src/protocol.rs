@@ -0,0 +1,527 @@
+//! Wire types exchanged over the WebSocket, shared by the server binaries
+//! and the client so the two sides can't drift apart.
+//!
+//! Timestamps are UTC by the server's clock: unix seconds unless the field
+//! name ends in `_ms`. Clients can measure their skew with
+//! [`ClientFrame::Time`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Assumed when a message carries no `content_type`.
+pub const DEFAULT_CONTENT_TYPE: &str = "text/plain";
+
+/// Sent by clients: a broadcast when `to` is `None`, otherwise a DM. With
+/// `members` or `conversation_id` it goes to a private group instead.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatMessage {
+    pub to: Option<String>,
+    pub content: String,
+    /// How clients should render `content`, e.g. `text/markdown`; the server
+    /// only checks it against its allowlist.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Starts a private group with these users, or reuses the one with the
+    /// same members; the sender is always included. Answered with
+    /// [`ServerFrame::Conversation`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<String>>,
+    /// Sends to a group the sender is in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+    /// Hex HMAC-SHA1 of `content` with the sender's signing key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Sender-chosen id; resending a message with the same id shortly after
+    /// it got through is answered with a `duplicate` notice, not delivered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+}
+
+/// A chat message delivered to clients.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerMessage {
+    pub from: String,
+    pub to: Option<String>,
+    pub content: String,
+    /// Relayed from [`ChatMessage::content_type`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Sent by the server on the sender's behalf at a time they picked.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub scheduled: bool,
+    /// Delivered during the recipient's quiet hours; show it without
+    /// alerting.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suppress_notification: bool,
+    /// Set on group messages, which have no `to`; reply with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+    /// Everyone in the group, sender included, sorted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<String>>,
+    /// `content` in the recipient's language, when the server has a
+    /// translator and the sender writes in another one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translated: Option<String>,
+    /// The server checked the sender's signature over `content`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verified: bool,
+    /// The sender's [`ChatMessage::client_id`]; never sent on.
+    #[serde(skip)]
+    pub client_id: Option<String>,
+}
+
+/// Typed requests from authenticated clients.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    /// Recent messages of the DM thread between the sender and `with`.
+    FetchDmHistory {
+        with: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    /// Admin only: disconnect `user`.
+    Kick {
+        user: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Admin only: kick several users at once, e.g. during a raid. Answered
+    /// with [`ServerFrame::KickResults`].
+    KickMany {
+        users: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Admin only: ban several users at once, online or not, until lifted.
+    /// Answered with [`ServerFrame::BanResults`].
+    BanMany {
+        users: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Deliver `message` at `deliver_at` (unix seconds), as if sent then.
+    Schedule {
+        message: ChatMessage,
+        deliver_at: u64,
+    },
+    /// The sender's pending scheduled messages.
+    ListScheduled,
+    /// Drop one of the sender's pending scheduled messages.
+    CancelScheduled {
+        id: String,
+    },
+    /// Add `user` to the sender's contacts, or update the entry. With
+    /// `notify_on_online`, the sender gets a `contact_online` notice when
+    /// they come online.
+    AddContact {
+        user: String,
+        #[serde(default)]
+        notify_on_online: bool,
+    },
+    RemoveContact {
+        user: String,
+    },
+    /// Answered with [`ServerFrame::Contacts`].
+    ListContacts,
+    /// Replace the sender's highlight words; an empty list clears them.
+    /// Broadcast and group messages containing one of them as a whole word,
+    /// ignoring case, come with a `keyword_match` notice.
+    SubscribeKeywords {
+        words: Vec<String>,
+    },
+    /// Flag messages delivered between `start` and `end` (`HH:MM`, local
+    /// to `tz`: `UTC` or an offset like `+02:00`) with
+    /// `suppress_notification`. Equal times turn quiet hours off. With
+    /// `mentions`, messages containing `@<your name>` still alert.
+    SetQuietHours {
+        start: String,
+        end: String,
+        tz: String,
+        #[serde(default)]
+        mentions: bool,
+    },
+    /// Encoding for the frames the server sends from now on. Only `json` is
+    /// supported; anything else is answered with `unsupported_encoding` and
+    /// leaves the encoding as it was.
+    SetEncoding {
+        encoding: String,
+    },
+    /// Admin only: reject everything `user` sends for `seconds`.
+    Mute {
+        user: String,
+        seconds: u64,
+    },
+    /// Admin only: lift a mute early.
+    Unmute {
+        user: String,
+    },
+    /// Admin only: silently hide everything `user` sends from everyone but
+    /// admins.
+    ShadowBan {
+        user: String,
+    },
+    /// Admin only: lift a shadow ban.
+    ShadowUnban {
+        user: String,
+    },
+    /// Admin only: silently drop everything `user` sends; they get their
+    /// own messages echoed back as if delivered.
+    ShadowMute {
+        user: String,
+    },
+    /// Admin only: lift a shadow mute.
+    ShadowUnmute {
+        user: String,
+    },
+    /// Admin only: a notice to everyone connected.
+    Announce {
+        text: String,
+    },
+    /// Admin only: receive [`ServerFrame::Metrics`] periodically.
+    SubscribeMetrics,
+    /// Admin only: refuse authentication for `user` or `token`, whichever
+    /// is given, for `seconds` or for good. Disconnects them if online.
+    Ban {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seconds: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Admin only: lift a ban early.
+    Unban {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// Admin only: answered with [`ServerFrame::Bans`].
+    ListBans,
+    /// Admin only: ban `user` for good and drop everything the server keeps
+    /// for them. With `anonymize`, their stored DMs name a tombstone id
+    /// instead. Safe to repeat.
+    Deactivate {
+        user: String,
+        #[serde(default)]
+        anonymize: bool,
+    },
+    /// Answer to [`ServerFrame::PresenceProbe`].
+    ProbeReply,
+    /// Admin only: page through the moderation audit log, oldest first.
+    /// `since`/`until` are unix seconds, inclusive.
+    Audit {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        until: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    /// Answered right away with [`ServerFrame::Time`]; `client_time_ms` is
+    /// echoed back as is.
+    Time {
+        client_time_ms: u64,
+    },
+    /// Admin only: who was online at `timestamp` (unix seconds).
+    PresenceAt {
+        timestamp: u64,
+    },
+    /// Admin only: replace the message of the day; empty text clears it.
+    /// With `broadcast`, everyone online gets the new one right away.
+    SetMotd {
+        text: String,
+        #[serde(default)]
+        broadcast: bool,
+    },
+}
+
+/// Anything a client sends once authenticated; chat messages carry no `type`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ClientEvent {
+    Frame(ClientFrame),
+    Message(ChatMessage),
+}
+
+/// First message on the first-message auth flow.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthMessage {
+    pub token: String,
+    /// Admins only: open a plain-text admin console instead of joining the
+    /// chat.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub console: bool,
+    /// Optional server events this client handles (`motd`,
+    /// `token_refresh`, `server_heartbeat`). Leaving it out means all of them
+    /// except `server_heartbeat`, which has to be asked for. Also accepted as
+    /// `features`.
+    #[serde(default, alias = "features", skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<String>>,
+    /// Preferred language for server notices and error messages, e.g. `fr`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// The client software and its version, e.g. `push` and `0.1.0`, so
+    /// servers can turn away versions that are too old.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+}
+
+/// Server answer to an authentication attempt.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthReply {
+    AuthSuccess {
+        message: String,
+        /// The optional events this connection will get.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        capabilities: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        server_time_ms: Option<u64>,
+    },
+    AuthFailed {
+        message: String,
+    },
+    /// The message wasn't an auth message. The connection stays open for a
+    /// few more tries.
+    AuthRequired {
+        message: String,
+    },
+    ServerFull {
+        message: String,
+        retry_after_ms: u64,
+    },
+    /// The client is older than `min_version`. WebSocket connections are
+    /// then closed with code 4426.
+    UpgradeRequired {
+        message: String,
+        min_version: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        download_url: Option<String>,
+    },
+}
+
+/// Typed server events delivered after authentication.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Error {
+        code: String,
+        message: String,
+    },
+    /// Server notice; clients style it by severity.
+    System {
+        severity: Severity,
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        context: Option<serde_json::Value>,
+    },
+    /// Live server stats for subscribed admins.
+    Metrics {
+        connected: usize,
+        msgs_per_sec: f64,
+        msgs_total: u64,
+        muted: usize,
+        /// Word filter matches per rule.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        filter_hits: HashMap<String, u64>,
+        /// Messages rejected as repeats since startup.
+        #[serde(default)]
+        duplicates_suppressed: u64,
+        /// Audit entries that couldn't be written to the audit file.
+        #[serde(default)]
+        audit_write_failures: u64,
+        /// WebSocket upgrades refused for asking for the wrong path.
+        #[serde(default)]
+        wrong_path_upgrades: u64,
+    },
+    /// Message of the day, sent right after authenticating. `hash` only
+    /// changes with the text, so clients can skip one they've shown.
+    Motd {
+        text: String,
+        hash: String,
+    },
+    /// A single-use token that authenticates a reconnect as this user
+    /// within `expires_in` seconds.
+    TokenRefresh {
+        token: String,
+        expires_in: u64,
+    },
+    /// Answer to [`ClientFrame::Audit`]; pass `next_offset` back to get the
+    /// next page.
+    AuditLog {
+        entries: Vec<AuditEntry>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_offset: Option<usize>,
+    },
+    /// Answer to [`ClientFrame::KickMany`]: `kicked`, `not_found` or
+    /// `forbidden` per user.
+    KickResults {
+        results: HashMap<String, String>,
+    },
+    /// Answer to [`ClientFrame::BanMany`]: `banned`, `not_found` or
+    /// `forbidden` per user.
+    BanResults {
+        results: HashMap<String, String>,
+    },
+    /// Answer to [`ClientFrame::Schedule`].
+    Scheduled {
+        id: String,
+        deliver_at: u64,
+    },
+    /// Answer to a message addressed with [`ChatMessage::members`].
+    Conversation {
+        conversation_id: String,
+        members: Vec<String>,
+    },
+    /// Answer to [`ClientFrame::ListScheduled`], soonest first.
+    ScheduledList {
+        messages: Vec<ScheduledMessage>,
+    },
+    /// The sender's contacts with their current state, sorted by name. Also
+    /// sent right after authenticating to users who have any.
+    Contacts {
+        contacts: Vec<Contact>,
+    },
+    /// Liveness check. Send [`ClientFrame::ProbeReply`], or anything else,
+    /// before the probe timeout or the connection is closed.
+    PresenceProbe,
+    /// Periodic stats, only for clients that asked for the `server_heartbeat`
+    /// capability. `latency_ms` is this connection's last ping round trip,
+    /// `None` until one completes or on transports without pings;
+    /// `queue_depth` is how many frames are waiting to be written to it.
+    ServerHeartbeat {
+        uptime_secs: u64,
+        connected: usize,
+        latency_ms: Option<u64>,
+        queue_depth: usize,
+        /// Average of the last few round trips, `None` like `latency_ms`.
+        #[serde(default)]
+        avg_latency_ms: Option<u64>,
+        /// `avg_latency_ms` rated, for "chat feels laggy" reports.
+        #[serde(default)]
+        quality: Option<ConnectionQuality>,
+    },
+    /// Answer to [`ClientFrame::ListBans`]: active bans, soonest to expire
+    /// first.
+    Bans {
+        bans: Vec<Ban>,
+    },
+    /// Answer to [`ClientFrame::Time`]. Half the round trip after
+    /// `client_time_ms` is roughly when the server read its clock.
+    Time {
+        client_time_ms: u64,
+        server_time_ms: u64,
+    },
+    /// Answer to [`ClientFrame::PresenceAt`], sorted by name.
+    PresenceAt {
+        timestamp: u64,
+        online: Vec<String>,
+    },
+    /// Answer to [`ClientFrame::FetchDmHistory`], oldest message first.
+    DmHistory {
+        with: String,
+        messages: Vec<ServerMessage>,
+    },
+}
+
+/// A message waiting for its delivery time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledMessage {
+    pub id: String,
+    pub from: String,
+    pub to: Option<String>,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub deliver_at: u64,
+}
+
+/// An entry in a user's contact list.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Contact {
+    pub user: String,
+    pub online: bool,
+    pub notify_on_online: bool,
+}
+
+/// A ban on a username or a token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// The admin who issued it.
+    pub by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Unix seconds; `None` for a permanent ban.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+}
+
+/// One privileged action, recorded whether or not it went through.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    /// Unix seconds.
+    pub timestamp: u64,
+    /// `server` for automatic actions.
+    pub actor: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub params: serde_json::Value,
+    /// `ok`, `denied` (not an admin) or `failed`.
+    pub outcome: String,
+    /// Why it was denied or failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Anything the server sends once authenticated.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ServerEvent {
+    Frame(ServerFrame),
+    Message(ServerMessage),
+}
+
+/// How a connection's average ping round trip rates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionQuality {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl ConnectionQuality {
+    /// The name as it appears on the wire.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConnectionQuality::Good => "good",
+            ConnectionQuality::Fair => "fair",
+            ConnectionQuality::Poor => "poor",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
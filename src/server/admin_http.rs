@@ -0,0 +1,125 @@
+//! The HTTP admin endpoint.
+
+use crate::protocol::{ClientFrame, ServerFrame};
+use crate::server::Server;
+use crate::server::auth::{resolve_token, spend_reconnect_token};
+use crate::server::bans::ban_message;
+use crate::server::http::{http_response, read_request_head};
+use crate::server::names::normalize_name;
+use crate::server::routing::run_client_frame;
+use std::sync::Arc;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+// `POST /admin/deactivate/<user>[?anonymize=true]` with an admin's token as
+// `Authorization: Bearer ...`. Runs through the same path as the admin
+// command, so it's audited the same way.
+pub(super) async fn handle_admin_http(stream: TcpStream, server: Arc<Server>) {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    let Some((request_line, headers)) = read_request_head(&mut reader).await else {
+        return;
+    };
+
+    let response = match admin_http_frame(&server, &request_line, &headers) {
+        Ok((admin, frame)) => match run_client_frame(&server, &admin, frame).await {
+            Ok(Some(frame)) => http_response("200 OK", &serde_json::to_string(&frame).unwrap()),
+            Ok(None) => http_response("200 OK", "ok"),
+            Err(frame) => {
+                let status = match &frame {
+                    ServerFrame::Error { code, .. } if code == "forbidden" => "403 Forbidden",
+                    _ => "400 Bad Request",
+                };
+                http_response(status, &serde_json::to_string(&frame).unwrap())
+            }
+        },
+        Err((status, reason)) => http_response(status, reason),
+    };
+
+    let _ = write_half.write_all(response.as_bytes()).await;
+}
+
+pub(super) fn admin_http_frame(
+    server: &Server,
+    request_line: &str,
+    headers: &[(String, String)],
+) -> Result<(String, ClientFrame), (&'static str, &'static str)> {
+    let token = headers
+        .iter()
+        .find(|(name, _)| name == "authorization")
+        .and_then(|(_, value)| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .unwrap_or_default();
+    let user = resolve_token(server, token).ok_or(("401 Unauthorized", "unauthorized"))?;
+    let admin = normalize_name(&server.config, user.username);
+    // The same checks as any other login, so a banned admin is locked out
+    // here too
+    if ban_message(server, token, &admin).is_some() {
+        return Err(("403 Forbidden", "banned"));
+    }
+    if user.reconnect && !spend_reconnect_token(server, token) {
+        return Err(("401 Unauthorized", "unauthorized"));
+    }
+
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or(("400 Bad Request", "bad_request"))?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let user = path
+        .strip_prefix("/admin/deactivate/")
+        .filter(|user| !user.is_empty() && !user.contains('/'))
+        .ok_or(("404 Not Found", "not_found"))?;
+    let anonymize = query.split('&').any(|pair| pair == "anonymize=true");
+
+    Ok((
+        admin,
+        ClientFrame::Deactivate {
+            user: user.to_string(),
+            anonymize,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Ban;
+    use crate::server::testing::*;
+
+    fn deactivate(token: &str, user: &str) -> String {
+        format!(
+            "POST /admin/deactivate/{} HTTP/1.1\r\nHost: localhost\r\n\
+             Authorization: Bearer {}\r\nContent-Length: 0\r\n\r\n",
+            user, token
+        )
+    }
+
+    #[tokio::test]
+    async fn only_unbanned_admins_can_deactivate_over_http() {
+        let test = TestServer::start(config()).await;
+
+        let response = test.http(&deactivate("nope", "charlie")).await;
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+        let response = test.http(&deactivate("token-bob", "charlie")).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+
+        test.server.bans.lock().unwrap().bans.push(Ban {
+            user: Some("alice".into()),
+            token: None,
+            by: "alice".into(),
+            reason: None,
+            until: None,
+        });
+        let response = test.http(&deactivate("token-alice", "charlie")).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(response.ends_with("banned"), "{}", response);
+        assert!(ban_message(&test.server, "token-charlie", "charlie").is_none());
+
+        test.server.bans.lock().unwrap().remove(Some("alice"), None);
+        let response = test.http(&deactivate("token-alice", "charlie")).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(ban_message(&test.server, "token-charlie", "charlie").is_some());
+    }
+}
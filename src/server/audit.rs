@@ -0,0 +1,226 @@
+//! The audit log of privileged commands.
+
+use crate::protocol::{AuditEntry, ClientFrame, ServerFrame};
+use crate::server::console::console_tail;
+use crate::server::{Server, unix_now};
+use std::io::Write;
+use std::sync::atomic::Ordering;
+
+// Kept apart from chat traffic: one entry per privileged command attempt,
+// including denied and failed ones, plus automatic mutes. Nothing removes
+// entries once recorded.
+pub(super) struct PrivilegedCommand {
+    pub(super) action: &'static str,
+    pub(super) target: Option<String>,
+    pub(super) params: serde_json::Value,
+}
+
+pub(super) fn privileged_command(frame: &ClientFrame) -> Option<PrivilegedCommand> {
+    let (action, target, params) = match frame {
+        // Reads don't change anything, and anyone may schedule their own
+        // messages
+        ClientFrame::Schedule { .. }
+        | ClientFrame::ListScheduled
+        | ClientFrame::CancelScheduled { .. }
+        | ClientFrame::SetQuietHours { .. }
+        | ClientFrame::SetEncoding { .. }
+        | ClientFrame::Time { .. }
+        | ClientFrame::AddContact { .. }
+        | ClientFrame::RemoveContact { .. }
+        | ClientFrame::ListContacts
+        | ClientFrame::SubscribeKeywords { .. }
+        | ClientFrame::ListBans
+        | ClientFrame::ProbeReply
+        // Audited by `deactivate` itself, with what it removed
+        | ClientFrame::Deactivate { .. }
+        | ClientFrame::FetchDmHistory { .. }
+        | ClientFrame::Audit { .. }
+        | ClientFrame::PresenceAt { .. } => return None,
+        ClientFrame::Kick { user, reason } => {
+            ("kick", Some(user), serde_json::json!({ "reason": reason }))
+        }
+        ClientFrame::KickMany { users, reason } => (
+            "kick_many",
+            None,
+            serde_json::json!({ "users": users, "reason": reason }),
+        ),
+        ClientFrame::BanMany { users, reason } => (
+            "ban_many",
+            None,
+            serde_json::json!({ "users": users, "reason": reason }),
+        ),
+        ClientFrame::Mute { user, seconds } => (
+            "mute",
+            Some(user),
+            serde_json::json!({ "seconds": seconds }),
+        ),
+        ClientFrame::Unmute { user } => ("unmute", Some(user), serde_json::json!({})),
+        // The token itself stays out of the audit log
+        ClientFrame::Ban {
+            user,
+            token,
+            seconds,
+            reason,
+        } => (
+            "ban",
+            user.as_ref(),
+            serde_json::json!({
+                "by_token": token.is_some(),
+                "seconds": seconds,
+                "reason": reason,
+            }),
+        ),
+        ClientFrame::Unban { user, token } => (
+            "unban",
+            user.as_ref(),
+            serde_json::json!({ "by_token": token.is_some() }),
+        ),
+        ClientFrame::ShadowBan { user } => ("shadow_ban", Some(user), serde_json::json!({})),
+        ClientFrame::ShadowUnban { user } => ("shadow_unban", Some(user), serde_json::json!({})),
+        ClientFrame::ShadowMute { user } => ("shadow_mute", Some(user), serde_json::json!({})),
+        ClientFrame::ShadowUnmute { user } => ("shadow_unmute", Some(user), serde_json::json!({})),
+        ClientFrame::Announce { text } => ("announce", None, serde_json::json!({ "text": text })),
+        ClientFrame::SetMotd { text, broadcast } => (
+            "set_motd",
+            None,
+            serde_json::json!({ "text": text, "broadcast": broadcast }),
+        ),
+        ClientFrame::SubscribeMetrics => ("subscribe_metrics", None, serde_json::json!({})),
+    };
+    Some(PrivilegedCommand {
+        action,
+        target: target.cloned(),
+        params,
+    })
+}
+
+pub(super) fn open_command_audit(path: &str) -> anyhow::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("opening command audit log {}: {}", path, e))
+}
+
+pub(super) fn audit_command(
+    server: &Server,
+    actor: &str,
+    command: PrivilegedCommand,
+    error: Option<&ServerFrame>,
+) {
+    let (outcome, reason) = match error {
+        None => ("ok", None),
+        Some(ServerFrame::Error { code, message }) => {
+            let outcome = if code == "forbidden" {
+                "denied"
+            } else {
+                "failed"
+            };
+            (outcome, Some(message.clone()))
+        }
+        Some(_) => ("failed", None),
+    };
+    record_audit(
+        server,
+        AuditEntry {
+            timestamp: unix_now(),
+            actor: actor.to_string(),
+            action: command.action.to_string(),
+            target: command.target,
+            params: command.params,
+            outcome: outcome.to_string(),
+            reason,
+        },
+    );
+}
+
+// Best effort: a failed file write is logged and counted, the entry is
+// still kept in memory for `audit` queries
+pub(super) fn record_audit(server: &Server, entry: AuditEntry) {
+    if let Some(log) = &server.command_audit {
+        let line = serde_json::to_string(&entry).unwrap();
+        let mut file = log.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            server.audit_write_failures.fetch_add(1, Ordering::Relaxed);
+            eprintln!("AUDIT WRITE FAILED ({}): {}", e, line);
+        }
+    }
+    console_tail(
+        server,
+        &format!(
+            "[audit] {} {} {} {}{}",
+            entry.actor,
+            entry.action,
+            entry.target.as_deref().unwrap_or("-"),
+            entry.outcome,
+            entry
+                .reason
+                .as_ref()
+                .map(|reason| format!(" ({})", reason))
+                .unwrap_or_default()
+        ),
+    );
+    server.audit_log.lock().unwrap().push(entry);
+}
+
+pub(super) const AUDIT_PAGE_DEFAULT: usize = 50;
+pub(super) const AUDIT_PAGE_MAX: usize = 200;
+
+pub(super) fn query_audit(
+    server: &Server,
+    since: Option<u64>,
+    until: Option<u64>,
+    target: Option<&str>,
+    offset: usize,
+    limit: Option<usize>,
+) -> ServerFrame {
+    let limit = limit.unwrap_or(AUDIT_PAGE_DEFAULT).clamp(1, AUDIT_PAGE_MAX);
+    let log = server.audit_log.lock().unwrap();
+    let mut matching = log.iter().filter(|entry| {
+        since.is_none_or(|since| entry.timestamp >= since)
+            && until.is_none_or(|until| entry.timestamp <= until)
+            && target.is_none_or(|target| entry.target.as_deref() == Some(target))
+    });
+
+    let entries: Vec<AuditEntry> = matching
+        .by_ref()
+        .skip(offset)
+        .take(limit)
+        .cloned()
+        .collect();
+    let next_offset = matching.next().map(|_| offset + entries.len());
+    ServerFrame::AuditLog {
+        entries,
+        next_offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn denied_kick_is_written_to_the_command_audit() {
+        let path = temp_file("audit.log");
+        let mut config = config();
+        config.command_audit = Some(path.clone());
+        let test = TestServer::start(config).await;
+        let _alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        bob.send(json!({ "type": "kick", "user": "alice", "reason": "mine" }))
+            .await;
+        bob.expect_error("forbidden").await;
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+        assert_eq!(entry["actor"], "bob");
+        assert_eq!(entry["action"], "kick");
+        assert_eq!(entry["target"], "alice");
+        assert_eq!(entry["params"]["reason"], "mine");
+        assert_eq!(entry["outcome"], "denied");
+        assert!(entry["reason"].as_str().is_some(), "{}", entry);
+        assert!(entry["timestamp"].as_u64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,207 @@
+//! Decoding and checking the auth message.
+
+use crate::protocol::{AuthMessage, AuthReply};
+use crate::server::bans::ban_message;
+use crate::server::capacity::check_capacity;
+use crate::server::names::{name_collides, normalize_name};
+use crate::server::tokens::TokenEntry;
+use crate::server::versions::check_client_version;
+use crate::server::{Server, unix_now_ms};
+use std::collections::HashSet;
+
+pub(super) struct Authenticated {
+    pub(super) username: String,
+    pub(super) tags: Vec<String>,
+    pub(super) signing_key: Option<String>,
+    pub(super) capabilities: HashSet<String>,
+    pub(super) lang: Option<String>,
+    // Set when a reconnect token was used
+    pub(super) resume_after: Option<u64>,
+}
+
+// Checks a first-message auth attempt; the error is the reply to send back.
+// Anything that isn't an auth message at all, like a chat message sent too
+// early, is `auth_required` rather than a failed attempt.
+pub(super) async fn authenticate(server: &Server, text: &str) -> Result<Authenticated, AuthReply> {
+    let Ok(auth) = serde_json::from_str::<AuthMessage>(text) else {
+        return Err(AuthReply::AuthRequired {
+            message: "Authenticate first".into(),
+        });
+    };
+    let Some(user) = resolve_token(server, &auth.token) else {
+        return Err(AuthReply::AuthFailed {
+            message: "Invalid token".into(),
+        });
+    };
+
+    let username = normalize_name(&server.config, user.username);
+    if let Some(message) = ban_message(server, &auth.token, &username) {
+        return Err(AuthReply::AuthFailed { message });
+    }
+    check_client_version(
+        server,
+        auth.client_name.as_deref(),
+        auth.client_version.as_deref(),
+    )?;
+    if name_collides(server, &username).await {
+        return Err(AuthReply::AuthFailed {
+            message: "Name already in use".into(),
+        });
+    }
+
+    if let Some(retry_after_ms) = check_capacity(server).await {
+        return Err(AuthReply::ServerFull {
+            message: "Server is full".into(),
+            retry_after_ms,
+        });
+    }
+    if user.reconnect && !spend_reconnect_token(server, &auth.token) {
+        return Err(AuthReply::AuthFailed {
+            message: "Invalid token".into(),
+        });
+    }
+
+    let (capabilities, lang) = (auth.capabilities, auth.lang);
+    Ok(Authenticated {
+        username,
+        tags: user.tags,
+        signing_key: user.signing_key,
+        capabilities: agree_capabilities(capabilities),
+        lang,
+        resume_after: user.resume_after,
+    })
+}
+
+pub(super) fn auth_success(auth: &Authenticated) -> AuthReply {
+    let mut capabilities: Vec<String> = auth.capabilities.iter().cloned().collect();
+    capabilities.sort();
+    AuthReply::AuthSuccess {
+        message: "Authenticated".into(),
+        capabilities,
+        server_time_ms: Some(unix_now_ms()),
+    }
+}
+
+// Optional server events a client can opt into by listing them when it
+// authenticates; clients that don't send a list get all of them
+pub(super) const CAPABILITIES: [&str; 3] = ["motd", "token_refresh", "server_heartbeat"];
+// Left out unless asked for by name
+pub(super) const OPT_IN_CAPABILITIES: [&str; 1] = ["server_heartbeat"];
+
+pub(super) fn agree_capabilities(requested: Option<Vec<String>>) -> HashSet<String> {
+    let supported = CAPABILITIES.iter().map(|capability| capability.to_string());
+    match requested {
+        None => supported
+            .filter(|capability| !OPT_IN_CAPABILITIES.contains(&capability.as_str()))
+            .collect(),
+        Some(requested) => supported
+            .filter(|capability| requested.contains(capability))
+            .collect(),
+    }
+}
+
+// Who a token belongs to, with the tags policy and diagnostics can key on
+pub(super) struct UserContext {
+    pub(super) username: String,
+    pub(super) tags: Vec<String>,
+    pub(super) signing_key: Option<String>,
+    // Reconnect tokens are only looked at here; spend one with
+    // `spend_reconnect_token` once the rest of auth has passed
+    pub(super) reconnect: bool,
+    // The last message seq a reconnect token's session saw
+    pub(super) resume_after: Option<u64>,
+}
+
+// Static tokens, then reconnect tokens handed out by the server. A
+// reconnect token gets the tags and signing key of its user's static token.
+pub(super) fn resolve_token(server: &Server, token: &str) -> Option<UserContext> {
+    let (entry, resume_after) = {
+        let tokens = server.tokens.read().unwrap();
+        match tokens.get(token) {
+            Some(entry) => (entry.clone(), None),
+            None => {
+                let session = server.sessions.lock().unwrap().peek(token)?;
+                let entry = tokens.for_user(&session.user).cloned();
+                let entry = entry.unwrap_or(TokenEntry {
+                    user: session.user,
+                    tags: Vec::new(),
+                    signing_key: None,
+                });
+                (entry, Some(session.last_seq))
+            }
+        }
+    };
+    let mut tags = entry.tags;
+    if let Some(extra) = server.config.user_tags.get(&entry.user) {
+        tags.extend(extra.iter().cloned());
+    }
+    Some(UserContext {
+        username: entry.user,
+        tags,
+        signing_key: entry.signing_key,
+        reconnect: resume_after.is_some(),
+        resume_after,
+    })
+}
+
+// Single use, but only used up once every other check has passed, so a
+// refused attempt (banned, full, name taken) leaves the token for the next
+// one. Of two attempts racing on one token, the second fails here.
+pub(super) fn spend_reconnect_token(server: &Server, token: &str) -> bool {
+    server.sessions.lock().unwrap().redeem(token).is_some()
+}
+
+pub(super) fn username_for_token(server: &Server, token: &str) -> Option<String> {
+    let tokens = server.tokens.read().unwrap();
+    tokens.get(token).map(|entry| entry.user.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn chat_before_auth_is_refused_then_disconnected() {
+        let mut config = config();
+        config.preauth_attempts = 2;
+        let test = TestServer::start(config).await;
+
+        let mut early = test.connect().await;
+        early.send(json!({ "content": "hi" })).await;
+        early.expect_type("auth_required").await;
+        early.send(json!({ "token": "token-bob" })).await;
+        early.expect_type("auth_success").await;
+
+        let mut pushy = test.connect().await;
+        for _ in 0..2 {
+            pushy.send(json!({ "content": "hi" })).await;
+            pushy.expect_type("auth_required").await;
+        }
+        pushy.expect_close().await;
+    }
+
+    #[tokio::test]
+    async fn frames_follow_the_features_a_client_declares() {
+        let test = TestServer::start(config()).await;
+        *test.server.motd.lock().unwrap() = Some("welcome".to_string());
+
+        let mut minimal = test
+            .login_with(json!({ "token": "token-bob", "features": ["token_refresh"] }))
+            .await;
+        minimal
+            .expect_none(Duration::from_millis(200), |frame| frame["type"] == "motd")
+            .await;
+        minimal.close().await;
+        test.wait_offline("bob").await;
+
+        let mut client = test.connect().await;
+        client
+            .send(json!({ "token": "token-bob", "features": ["motd", "reactions"] }))
+            .await;
+        let reply = client.expect_type("auth_success").await;
+        assert_eq!(reply["capabilities"], json!(["motd"]));
+        assert_eq!(client.expect_type("motd").await["text"], "welcome");
+    }
+}
@@ -0,0 +1,208 @@
+//! Bans by user or token, optionally kept across restarts.
+
+use crate::protocol::{Ban, ServerFrame, Severity};
+use crate::server::auth::username_for_token;
+use crate::server::moderation::{kick_client, require_admin};
+use crate::server::{Server, unix_now, write_atomically};
+
+#[derive(Default)]
+pub(super) struct Bans {
+    pub(super) bans: Vec<Ban>,
+    pub(super) file: Option<String>,
+}
+
+impl Bans {
+    pub(super) fn load(path: &str) -> anyhow::Result<Bans> {
+        let bans = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("parsing bans {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => anyhow::bail!("reading bans {}: {}", path, e),
+        };
+        Ok(Bans {
+            bans,
+            file: Some(path.to_string()),
+        })
+    }
+
+    pub(super) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_atomically(path, &self.bans) {
+            eprintln!("saving bans to {} failed: {}", path, e);
+        }
+    }
+
+    // Expired bans are dropped whenever bans are looked at
+    pub(super) fn prune(&mut self) {
+        let now = unix_now();
+        let before = self.bans.len();
+        self.bans
+            .retain(|ban| ban.until.is_none_or(|until| until > now));
+        if self.bans.len() != before {
+            self.save();
+        }
+    }
+
+    pub(super) fn active(&mut self) -> Vec<Ban> {
+        self.prune();
+        let mut bans = self.bans.clone();
+        bans.sort_by_key(|ban| ban.until.unwrap_or(u64::MAX));
+        bans
+    }
+
+    pub(super) fn find(&mut self, token: &str, username: &str) -> Option<Ban> {
+        self.prune();
+        self.bans
+            .iter()
+            .find(|ban| {
+                ban.token.as_deref() == Some(token) || ban.user.as_deref() == Some(username)
+            })
+            .cloned()
+    }
+
+    pub(super) fn remove(&mut self, user: Option<&str>, token: Option<&str>) -> bool {
+        let before = self.bans.len();
+        self.bans
+            .retain(|ban| ban.user.as_deref() != user || ban.token.as_deref() != token);
+        self.bans.len() != before
+    }
+}
+
+// Users whose messages only admins see
+#[derive(Default)]
+pub(super) struct ShadowBans {
+    pub(super) users: std::collections::BTreeSet<String>,
+    pub(super) file: Option<String>,
+}
+
+impl ShadowBans {
+    pub(super) fn load(path: &str) -> anyhow::Result<ShadowBans> {
+        let users = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("parsing shadow bans {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => anyhow::bail!("reading shadow bans {}: {}", path, e),
+        };
+        Ok(ShadowBans {
+            users,
+            file: Some(path.to_string()),
+        })
+    }
+
+    pub(super) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_atomically(path, &self.users) {
+            eprintln!("saving shadow bans to {} failed: {}", path, e);
+        }
+    }
+}
+
+// The rejection every transport sends a banned user
+pub(super) fn ban_message(server: &Server, token: &str, username: &str) -> Option<String> {
+    let ban = server.bans.lock().unwrap().find(token, username)?;
+    let mut message = match ban.until {
+        Some(until) => format!(
+            "You are banned for another {}s",
+            until.saturating_sub(unix_now()).max(1)
+        ),
+        None => "You are banned".to_string(),
+    };
+    if let Some(reason) = &ban.reason {
+        message.push_str(&format!(": {}", reason));
+    }
+    Some(message)
+}
+
+// Exactly one of `user` and `token`; banning one again replaces the ban
+pub(super) async fn ban(
+    server: &Server,
+    admin: &str,
+    user: Option<String>,
+    token: Option<String>,
+    seconds: Option<u64>,
+    reason: Option<String>,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+    if user.is_some() == token.is_some() || seconds == Some(0) {
+        return Err(ServerFrame::Error {
+            code: "invalid_ban".into(),
+            message: "Ban either a user or a token, for at least a second".into(),
+        });
+    }
+
+    let until = seconds.map(|seconds| unix_now() + seconds);
+    {
+        let mut bans = server.bans.lock().unwrap();
+        bans.remove(user.as_deref(), token.as_deref());
+        bans.bans.push(Ban {
+            user: user.clone(),
+            token: token.clone(),
+            by: admin.to_string(),
+            reason: reason.clone(),
+            until,
+        });
+        bans.save();
+    }
+
+    // Static tokens name their user, so a token ban can disconnect too
+    let target = user.or_else(|| {
+        token
+            .as_deref()
+            .and_then(|token| username_for_token(server, token))
+    });
+    let label = target.clone().unwrap_or_else(|| "The token".to_string());
+    println!("{} banned {}", admin, label);
+    if let Some(target) = &target
+        && server.clients.lock().await.contains_key(target)
+    {
+        let _ = kick_client(
+            server,
+            admin,
+            target,
+            Some(reason.unwrap_or_else(|| "banned".to_string())),
+        )
+        .await;
+    }
+
+    let text = match seconds {
+        Some(seconds) => format!("{} is banned for {}s", label, seconds),
+        None => format!("{} is banned", label),
+    };
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text,
+        context: Some(serde_json::json!({ "event": "ban", "user": target, "until": until })),
+    })
+}
+
+pub(super) fn unban(
+    server: &Server,
+    admin: &str,
+    user: Option<String>,
+    token: Option<String>,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+    let mut bans = server.bans.lock().unwrap();
+    bans.prune();
+    if !bans.remove(user.as_deref(), token.as_deref()) {
+        return Err(ServerFrame::Error {
+            code: "not_banned".into(),
+            message: "No active ban matches".into(),
+        });
+    }
+    bans.save();
+    println!("{} lifted a ban", admin);
+
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: format!(
+            "{} is no longer banned",
+            user.as_deref().unwrap_or("The token")
+        ),
+        context: Some(serde_json::json!({ "event": "unban", "user": user })),
+    })
+}
@@ -0,0 +1,209 @@
+//! System notices and presence announcements.
+
+use crate::protocol::{ServerFrame, Severity};
+use crate::server::console::console_tail;
+use crate::server::contacts::notify_contact_watchers;
+use crate::server::{DisconnectReason, Server};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+pub(super) async fn broadcast_system(
+    server: &Server,
+    severity: Severity,
+    text: &str,
+    context: Option<serde_json::Value>,
+) {
+    let frame = ServerFrame::System {
+        severity,
+        text: text.into(),
+        context,
+    };
+
+    let json = serde_json::to_string(&frame).unwrap();
+    for client in server.clients.lock().await.values() {
+        if client.templates.is_some() {
+            client.send_frame(&frame);
+        } else {
+            let _ = client.tx.send(json.clone());
+        }
+    }
+}
+
+// Users whose presence changed within the current window, with their latest
+// state, and everyone whose last announced state was online
+#[derive(Default)]
+pub(super) struct PresenceCoalescer {
+    pub(super) pending: HashMap<String, (bool, Option<DisconnectReason>)>,
+    pub(super) announced: HashSet<String>,
+}
+
+// With coalescing on, the first change starts a timer and later ones only
+// overwrite the state it reports, so a flapping user produces at most one
+// notice per window, and none if they end up where they started
+pub(super) async fn broadcast_presence(
+    server: &Arc<Server>,
+    username: &str,
+    joined: bool,
+    reason: Option<DisconnectReason>,
+) {
+    if server.config.presence_coalesce.is_zero() {
+        announce_presence(server, username, joined, reason).await;
+        return;
+    }
+
+    let first = server
+        .presence_coalescer
+        .lock()
+        .unwrap()
+        .pending
+        .insert(username.to_string(), (joined, reason))
+        .is_none();
+    if !first {
+        return;
+    }
+
+    let server = server.clone();
+    let username = username.to_string();
+    tokio::spawn(async move {
+        tokio::time::sleep(server.config.presence_coalesce).await;
+        let changed = {
+            let mut coalescer = server.presence_coalescer.lock().unwrap();
+            let (joined, reason) = coalescer
+                .pending
+                .remove(&username)
+                .unwrap_or((joined, reason));
+            let changed = if joined {
+                coalescer.announced.insert(username.clone())
+            } else {
+                coalescer.announced.remove(&username)
+            };
+            changed.then_some((joined, reason))
+        };
+        if let Some((joined, reason)) = changed {
+            announce_presence(&server, &username, joined, reason).await;
+        }
+    });
+}
+
+pub(super) async fn announce_presence(
+    server: &Server,
+    username: &str,
+    joined: bool,
+    reason: Option<DisconnectReason>,
+) {
+    let (event, verb) = if joined {
+        ("join", "joined")
+    } else {
+        ("leave", "left")
+    };
+    let reason = reason.map(DisconnectReason::as_str);
+    console_tail(
+        server,
+        &format!(
+            "[presence] {} {}{}",
+            username,
+            verb,
+            reason
+                .map(|reason| format!(" ({})", reason))
+                .unwrap_or_default()
+        ),
+    );
+    if joined {
+        notify_contact_watchers(server, username).await;
+    }
+
+    let mut context = serde_json::json!({ "event": event, "user": username });
+    if let Some(reason) = reason {
+        context["reason"] = reason.into();
+    }
+    broadcast_system(
+        server,
+        Severity::Info,
+        &format!("{} {} the chat", username, verb),
+        Some(context),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn flapping_within_the_coalesce_window_is_announced_once() {
+        let mut config = config();
+        config.presence_coalesce = Duration::from_millis(400);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        alice
+            .expect(|frame| frame["context"] == json!({ "event": "join", "user": "alice" }))
+            .await;
+        let about_bob = |frame: &serde_json::Value| frame["context"]["user"] == "bob";
+
+        // New: connect, drop and come back, all inside one window
+        let bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+        bob.close().await;
+        test.wait_offline("bob").await;
+        let bob = test.login("token-bob").await;
+        let join = alice.expect(about_bob).await;
+        assert_eq!(join["context"]["event"], "join", "{}", join);
+        alice
+            .expect_none(Duration::from_millis(600), about_bob)
+            .await;
+
+        // Already announced: the same cycle says nothing at all
+        bob.close().await;
+        test.wait_offline("bob").await;
+        let _bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+        alice
+            .expect_none(Duration::from_millis(800), about_bob)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn notices_carry_their_severity() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        let join = alice
+            .expect(|frame| frame["context"] == json!({ "event": "join", "user": "bob" }))
+            .await;
+        assert_eq!(join["severity"], "info", "{}", join);
+
+        alice.send(json!({ "type": "kick", "user": "bob" })).await;
+        let kick = alice.expect_event("kick").await;
+        assert_eq!(kick["severity"], "warning", "{}", kick);
+        bob.expect_close().await;
+    }
+
+    #[tokio::test]
+    async fn leaving_is_info() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        bob.close().await;
+        let leave = alice.expect_event("leave").await;
+        assert_eq!(leave["severity"], "info", "{}", leave);
+    }
+
+    #[tokio::test]
+    async fn automatic_mutes_are_warnings() {
+        let mut config = config();
+        config.rate_limit = 1;
+        config.flood_strikes = 1;
+        let test = TestServer::start(config).await;
+        let mut bob = test.login("token-bob").await;
+
+        bob.send(json!({ "to": null, "content": "one" })).await;
+        bob.send(json!({ "to": null, "content": "two" })).await;
+        let mute = bob.expect_event("mute").await;
+        assert_eq!(mute["severity"], "warning", "{}", mute);
+    }
+}
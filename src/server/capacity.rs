@@ -0,0 +1,120 @@
+//! The connection cap and its retry hints.
+
+use crate::server::Server;
+use std::time::{Duration, Instant};
+
+pub(super) const BASE_RETRY_MS: u64 = 500;
+pub(super) const MAX_RETRY_MS: u64 = 60_000;
+pub(super) const REJECTIONS_PER_DOUBLING: u32 = 4;
+pub(super) const OVERLOAD_QUIET_PERIOD: Duration = Duration::from_secs(30);
+
+// Tracks recent `server_full` rejections so the retry hint grows with
+// reconnect pressure instead of sending every client back at the same time
+pub(super) struct OverloadBackoff {
+    pub(super) rejections: u32,
+    pub(super) last_rejection: Instant,
+}
+
+impl OverloadBackoff {
+    pub(super) fn new() -> Self {
+        OverloadBackoff {
+            rejections: 0,
+            last_rejection: Instant::now(),
+        }
+    }
+
+    pub(super) fn next_retry_after_ms(&mut self) -> u64 {
+        let now = Instant::now();
+        if now.duration_since(self.last_rejection) > OVERLOAD_QUIET_PERIOD {
+            self.rejections = 0;
+        }
+        self.last_rejection = now;
+
+        let doublings = (self.rejections / REJECTIONS_PER_DOUBLING).min(16);
+        self.rejections = self.rejections.saturating_add(1);
+
+        BASE_RETRY_MS
+            .saturating_mul(1 << doublings)
+            .min(MAX_RETRY_MS)
+    }
+}
+
+// Returns the retry hint when the server has no room for another client
+pub(super) async fn check_capacity(server: &Server) -> Option<u64> {
+    if server.clients.lock().await.len() < server.config.max_clients {
+        return None;
+    }
+
+    Some(server.overload.lock().unwrap().next_retry_after_ms())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn retry_hint_doubles_with_reconnect_pressure() {
+        let mut backoff = OverloadBackoff::new();
+        let hints: Vec<u64> = (0..3 * REJECTIONS_PER_DOUBLING)
+            .map(|_| backoff.next_retry_after_ms())
+            .collect();
+        assert!(hints.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(hints[0], BASE_RETRY_MS);
+        assert_eq!(hints[hints.len() - 1], BASE_RETRY_MS * 4);
+    }
+
+    #[test]
+    fn retry_hint_is_capped() {
+        let mut backoff = OverloadBackoff::new();
+        for _ in 0..1000 {
+            assert!(backoff.next_retry_after_ms() <= MAX_RETRY_MS);
+        }
+    }
+
+    #[tokio::test]
+    async fn full_server_rejects_with_a_growing_retry_hint() {
+        let mut config = config();
+        config.max_clients = 1;
+        let test = TestServer::start(config).await;
+        let _alice = test.login("token-alice").await;
+        test.wait_online("alice").await;
+
+        let mut hints = Vec::new();
+        for _ in 0..2 * REJECTIONS_PER_DOUBLING {
+            let mut bob = test.connect().await;
+            bob.send(json!({ "token": "token-bob" })).await;
+            let reply = bob.recv().await.unwrap();
+            assert_eq!(reply["type"], "server_full", "{}", reply);
+            hints.push(reply["retry_after_ms"].as_u64().unwrap());
+        }
+        assert!(
+            hints.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{:?}",
+            hints
+        );
+        assert!(hints[hints.len() - 1] > hints[0], "{:?}", hints);
+    }
+
+    #[tokio::test]
+    async fn only_joins_near_the_cap_are_warned() {
+        let mut config = config();
+        config.max_clients = 4;
+        config.near_capacity_slots = 1;
+        let test = TestServer::start(config).await;
+        let near_capacity = |frame: &Value| frame["context"]["event"] == "near_capacity";
+
+        let mut alice = test.login("token-alice").await;
+        alice
+            .expect_none(Duration::from_millis(200), near_capacity)
+            .await;
+        let _bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        let mut charlie = test.login("token-charlie").await;
+        let warning = charlie.expect_event("near_capacity").await;
+        assert_eq!(warning["severity"], "warning");
+        assert_eq!(warning["context"]["slots_left"], 1);
+    }
+}
@@ -0,0 +1,273 @@
+//! Configuration, read from `PUSH_*` environment variables.
+
+use crate::protocol::DEFAULT_CONTENT_TYPE;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+pub(super) struct Config {
+    // WebSocket and plain HTTP listener
+    pub(super) addr: String,
+    pub(super) max_clients: usize,
+    // Messages a user may send per `rate_window`; 0 disables the limit
+    pub(super) rate_limit: usize,
+    pub(super) rate_window: Duration,
+    // Hitting the limit this many times within `flood_window` mutes the user
+    pub(super) flood_strikes: usize,
+    pub(super) flood_window: Duration,
+    // Also close the connection of a user muted for flooding
+    pub(super) flood_disconnect: bool,
+    // Per-connection ping timer, which also paces `server_heartbeat` events;
+    // 0 disables both
+    pub(super) heartbeat_interval: Duration,
+    // Messages per second across the server that trip the circuit breaker;
+    // 0 disables it
+    pub(super) breaker_rate: u64,
+    // How often the aggregate rate is measured
+    pub(super) breaker_window: Duration,
+    // How long the rate must stay below the threshold before relaxing
+    pub(super) breaker_cooldown: Duration,
+    // Per-user limit per `rate_window` while the breaker is tripped
+    pub(super) breaker_rate_limit: usize,
+    // Copies of the same content allowed per `duplicate_window`; 0 disables it
+    pub(super) duplicate_limit: usize,
+    pub(super) duplicate_window: Duration,
+    // Shorter messages ("ok", "+1") are never treated as duplicates
+    pub(super) duplicate_min_len: usize,
+    // Recent `client_id`s remembered per user to drop retried sends; 0
+    // disables it
+    pub(super) client_id_window: usize,
+    pub(super) client_id_ttl: Duration,
+    // Layout limits on message content; 0 disables each
+    pub(super) max_lines: usize,
+    pub(super) max_line_len: usize,
+    // Warn newcomers when at most this many slots are left; 0 disables it
+    pub(super) near_capacity_slots: usize,
+    pub(super) max_dm_partners: usize,
+    // Keep-alive comment interval on SSE streams, at least a second
+    pub(super) sse_heartbeat: Duration,
+    // WebSocket upgrades are only accepted on this path; any path when unset
+    pub(super) ws_path: Option<String>,
+    // How often a batch of connections is asked to prove they're alive; 0
+    // disables probing
+    pub(super) probe_interval: Duration,
+    pub(super) probe_batch: usize,
+    // Probed connections silent for this long are closed
+    pub(super) probe_timeout: Duration,
+    // Connections must last this long before they join the roster, so quick
+    // connect/disconnect cycles make no presence noise
+    pub(super) warm_up: Duration,
+    // Non-auth messages a connection may send before it's closed for not
+    // authenticating
+    pub(super) preauth_attempts: usize,
+    // Presence changes within this window are folded into the final state;
+    // 0 sends each one right away
+    pub(super) presence_coalesce: Duration,
+    pub(super) metrics_interval: Duration,
+    // How often connected clients get a fresh reconnect token; 0 disables it
+    pub(super) token_refresh: Duration,
+    pub(super) reconnect_token_ttl: Duration,
+    // Unexpired reconnect tokens are kept here so they survive a restart
+    pub(super) sessions_file: Option<String>,
+    // Recent messages kept for resumed sessions to catch up on; 0 keeps none
+    pub(super) history_size: usize,
+    // Where those messages are appended so they survive a restart
+    pub(super) history_file: Option<String>,
+    // Messages kept per DM thread; 0 means DMs are never stored
+    pub(super) dm_history: usize,
+    // Trim and collapse whitespace in names coming from the auth layer
+    pub(super) normalize_names: bool,
+    // Treat names differing only in case as the same user for uniqueness
+    pub(super) case_fold_names: bool,
+    // Accepted `content_type` values
+    pub(super) content_types: HashSet<String>,
+    // Content types whose HTML is stripped before relaying; empty disables it
+    pub(super) sanitize_types: HashSet<String>,
+    // Content types whose whitespace is tidied before relaying; empty
+    // disables it
+    pub(super) normalize_types: HashSet<String>,
+    // Users allowed to kick others
+    pub(super) admins: HashSet<String>,
+    // User -> metadata tags, e.g. `alice=tier:pro region:eu`
+    pub(super) user_tags: HashMap<String, Vec<String>>,
+    // Token store file; without it only the demo tokens work
+    pub(super) tokens_file: Option<String>,
+    // Whether the kick notice everyone sees includes the reason
+    pub(super) kick_reason_public: bool,
+    // Close connections that send text that isn't UTF-8 instead of only
+    // answering with an error
+    pub(super) strict_utf8: bool,
+    // How long an announcement is also shown to clients that connect later
+    pub(super) announcement_sticky: Duration,
+    // Append-only JSON lines log of privileged commands, off unless set
+    pub(super) command_audit: Option<String>,
+    // JSON lines file of joins and leaves, replayed on startup; off unless set
+    pub(super) presence_log: Option<String>,
+    // Initial message of the day, unless `motd_file` has one
+    pub(super) motd: Option<String>,
+    // Where runtime MOTD changes are saved and read back on startup
+    pub(super) motd_file: Option<String>,
+    // How far ahead messages can be scheduled
+    pub(super) schedule_horizon: Duration,
+    // Pending scheduled messages are kept here across restarts
+    pub(super) schedule_file: Option<String>,
+    // Contact lists are kept here across restarts
+    pub(super) contacts_file: Option<String>,
+    // Highlight words are kept here across restarts
+    pub(super) keywords_file: Option<String>,
+    // Bans are kept here across restarts
+    pub(super) bans_file: Option<String>,
+    // Mutes are kept here across restarts
+    pub(super) mutes_file: Option<String>,
+    // Shadow bans are kept here across restarts
+    pub(super) shadow_bans_file: Option<String>,
+    // Directory of `<lang>.txt` translation files
+    pub(super) locales_dir: Option<String>,
+    // Used when a client asks for a language there are no translations for
+    pub(super) default_lang: String,
+    // Program that translates chat messages: target language as its
+    // argument, text on stdin, translation on stdout
+    pub(super) translate_cmd: Option<String>,
+    pub(super) translate_timeout: Duration,
+    // Word filter rules file, reloaded on SIGHUP
+    pub(super) word_filter: Option<String>,
+    // Minimum version per client name, reloaded on SIGHUP
+    pub(super) client_versions: Option<String>,
+    // Where clients told to upgrade can get a new version
+    pub(super) upgrade_url: Option<String>,
+    // Turn away clients whose name isn't in the versions file
+    pub(super) reject_unknown_clients: bool,
+    // Newline-delimited JSON listener, off unless set
+    pub(super) tcp_addr: Option<String>,
+    #[cfg(feature = "irc")]
+    pub(super) irc_addr: String,
+    // Incoming webhook secret -> bot identity the messages are sent as
+    pub(super) webhooks: HashMap<String, String>,
+}
+
+impl Config {
+    pub(super) fn from_env() -> Self {
+        Config {
+            addr: env_or("PUSH_ADDR", "127.0.0.1:8080".to_string()),
+            max_clients: env_or("PUSH_MAX_CLIENTS", 10_000),
+            near_capacity_slots: env_or("PUSH_NEAR_CAPACITY_SLOTS", 0),
+            rate_limit: env_or("PUSH_RATE_LIMIT", 0),
+            rate_window: Duration::from_secs(env_or("PUSH_RATE_WINDOW_SECS", 10)),
+            flood_strikes: env_or("PUSH_FLOOD_STRIKES", 3),
+            flood_window: Duration::from_secs(env_or("PUSH_FLOOD_WINDOW_SECS", 60)),
+            flood_disconnect: env_or("PUSH_FLOOD_DISCONNECT", false),
+            heartbeat_interval: Duration::from_secs(env_or("PUSH_HEARTBEAT_SECS", 0)),
+            breaker_rate: env_or("PUSH_BREAKER_RATE", 0),
+            breaker_window: Duration::from_secs(env_or("PUSH_BREAKER_WINDOW_SECS", 10).max(1)),
+            breaker_cooldown: Duration::from_secs(env_or("PUSH_BREAKER_COOLDOWN_SECS", 60)),
+            breaker_rate_limit: env_or("PUSH_BREAKER_RATE_LIMIT", 2).max(1),
+            duplicate_limit: env_or("PUSH_DUPLICATE_LIMIT", 0),
+            duplicate_window: Duration::from_secs(env_or("PUSH_DUPLICATE_WINDOW_SECS", 30)),
+            duplicate_min_len: env_or("PUSH_DUPLICATE_MIN_LEN", 8),
+            client_id_window: env_or("PUSH_CLIENT_ID_WINDOW", 100),
+            client_id_ttl: Duration::from_secs(env_or("PUSH_CLIENT_ID_TTL_SECS", 300)),
+            max_lines: env_or("PUSH_MAX_LINES", 0),
+            max_line_len: env_or("PUSH_MAX_LINE_LEN", 0),
+            max_dm_partners: env_or("PUSH_MAX_DM_PARTNERS", 50),
+            sse_heartbeat: Duration::from_secs(env_or("PUSH_SSE_HEARTBEAT_SECS", 15).max(1)),
+            ws_path: std::env::var("PUSH_WS_PATH").ok(),
+            probe_interval: Duration::from_secs(env_or("PUSH_PROBE_INTERVAL_SECS", 0)),
+            probe_batch: env_or("PUSH_PROBE_BATCH", 10),
+            probe_timeout: Duration::from_secs(env_or("PUSH_PROBE_TIMEOUT_SECS", 10)),
+            warm_up: Duration::from_millis(env_or("PUSH_WARM_UP_MS", 0)),
+            preauth_attempts: env_or("PUSH_PREAUTH_ATTEMPTS", 3).max(1),
+            presence_coalesce: Duration::from_millis(env_or("PUSH_PRESENCE_COALESCE_MS", 0)),
+            token_refresh: Duration::from_secs(env_or("PUSH_TOKEN_REFRESH_SECS", 0)),
+            reconnect_token_ttl: Duration::from_secs(env_or("PUSH_RECONNECT_TOKEN_TTL_SECS", 120)),
+            sessions_file: std::env::var("PUSH_SESSIONS_FILE").ok(),
+            history_size: env_or("PUSH_HISTORY_SIZE", 0),
+            history_file: std::env::var("PUSH_HISTORY_FILE").ok(),
+            metrics_interval: Duration::from_secs(env_or("PUSH_METRICS_INTERVAL_SECS", 5).max(1)),
+            dm_history: env_or("PUSH_DM_HISTORY", 0),
+            normalize_names: env_or("PUSH_NORMALIZE_NAMES", false),
+            case_fold_names: env_or("PUSH_CASE_FOLD_NAMES", false),
+            content_types: content_types_from_env(),
+            sanitize_types: env_list("PUSH_SANITIZE_CONTENT_TYPES"),
+            normalize_types: env_list("PUSH_NORMALIZE_CONTENT_TYPES"),
+            admins: env_list("PUSH_ADMINS"),
+            user_tags: env_pairs("PUSH_USER_TAGS")
+                .into_iter()
+                .map(|(user, tags)| {
+                    let mut tags: Vec<String> =
+                        tags.split_whitespace().map(str::to_string).collect();
+                    tags.sort();
+                    (user, tags)
+                })
+                .collect(),
+            tokens_file: std::env::var("PUSH_TOKENS_FILE").ok(),
+            kick_reason_public: env_or("PUSH_KICK_REASON_PUBLIC", false),
+            strict_utf8: env_or("PUSH_STRICT_UTF8", false),
+            announcement_sticky: Duration::from_secs(env_or("PUSH_ANNOUNCEMENT_STICKY_SECS", 0)),
+            command_audit: std::env::var("PUSH_COMMAND_AUDIT").ok(),
+            presence_log: std::env::var("PUSH_PRESENCE_LOG").ok(),
+            motd: std::env::var("PUSH_MOTD")
+                .ok()
+                .filter(|text| !text.trim().is_empty()),
+            motd_file: std::env::var("PUSH_MOTD_FILE").ok(),
+            schedule_horizon: Duration::from_secs(env_or(
+                "PUSH_SCHEDULE_MAX_HORIZON_SECS",
+                7 * 24 * 60 * 60,
+            )),
+            schedule_file: std::env::var("PUSH_SCHEDULE_FILE").ok(),
+            contacts_file: std::env::var("PUSH_CONTACTS_FILE").ok(),
+            keywords_file: std::env::var("PUSH_KEYWORDS_FILE").ok(),
+            bans_file: std::env::var("PUSH_BANS_FILE").ok(),
+            mutes_file: std::env::var("PUSH_MUTES_FILE").ok(),
+            shadow_bans_file: std::env::var("PUSH_SHADOW_BANS_FILE").ok(),
+            locales_dir: std::env::var("PUSH_LOCALES_DIR").ok(),
+            default_lang: env_or("PUSH_DEFAULT_LANG", "en".to_string()),
+            translate_cmd: std::env::var("PUSH_TRANSLATE_CMD").ok(),
+            translate_timeout: Duration::from_millis(env_or("PUSH_TRANSLATE_TIMEOUT_MS", 2000)),
+            word_filter: std::env::var("PUSH_WORD_FILTER").ok(),
+            client_versions: std::env::var("PUSH_CLIENT_VERSIONS").ok(),
+            upgrade_url: std::env::var("PUSH_UPGRADE_URL").ok(),
+            reject_unknown_clients: env_or("PUSH_REJECT_UNKNOWN_CLIENTS", false),
+            tcp_addr: std::env::var("PUSH_TCP_ADDR").ok(),
+            #[cfg(feature = "irc")]
+            irc_addr: env_or("PUSH_IRC_ADDR", "127.0.0.1:6667".to_string()),
+            webhooks: env_pairs("PUSH_WEBHOOKS"),
+        }
+    }
+}
+
+pub(super) fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Parses `a,b,c` lists
+pub(super) fn env_list(key: &str) -> HashSet<String> {
+    std::env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// The default type is always accepted
+pub(super) fn content_types_from_env() -> HashSet<String> {
+    let mut types = env_list("PUSH_CONTENT_TYPES");
+    if types.is_empty() {
+        types.insert("text/markdown".to_string());
+    }
+    types.insert(DEFAULT_CONTENT_TYPE.to_string());
+    types
+}
+
+// Parses `key=value,key=value` lists
+pub(super) fn env_pairs(key: &str) -> HashMap<String, String> {
+    std::env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
@@ -0,0 +1,476 @@
+//! The line-based admin console.
+
+use crate::protocol::{AuthMessage, AuthReply, ClientFrame, ServerFrame};
+use crate::server::auth::{resolve_token, spend_reconnect_token};
+use crate::server::bans::ban_message;
+use crate::server::names::normalize_name;
+use crate::server::routing::run_client_frame;
+use crate::server::{Client, Server, unix_now_ms};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+// A WebSocket session in plain text for admins: one command per line, with
+// moderation and presence events tailed live. Consoles never join the roster.
+pub(super) const CONSOLE_COMMANDS: [(&str, &str); 19] = [
+    ("help", "show this list"),
+    ("who", "list connected users and their tags"),
+    ("clients", "show how each user is connected"),
+    ("kick <user> [reason]", "disconnect a user"),
+    (
+        "kickmany <user,user,...> [reason]",
+        "disconnect several users",
+    ),
+    (
+        "banmany <user,user,...> [reason]",
+        "ban several users until lifted",
+    ),
+    (
+        "mute <user> <seconds>",
+        "reject everything a user sends for a while",
+    ),
+    ("unmute <user>", "lift a mute early"),
+    (
+        "ban <user> [seconds] [reason]",
+        "refuse a user's logins, for good without seconds",
+    ),
+    ("unban <user>", "lift a ban early"),
+    ("bans", "list active bans"),
+    (
+        "deactivate <user> [anonymize]",
+        "ban a user for good and clear their data",
+    ),
+    (
+        "shadowban <user>",
+        "hide a user's messages from everyone but admins",
+    ),
+    ("unshadowban <user>", "lift a shadow ban"),
+    ("shadowmute <user>", "silently drop a user's messages"),
+    ("unshadowmute <user>", "lift a shadow mute"),
+    ("announce <text>", "send a notice to everyone"),
+    ("audit [user]", "show recent audit entries"),
+    ("quit", "close the console"),
+];
+
+pub(super) fn wants_console(text: &str) -> bool {
+    serde_json::from_str::<AuthMessage>(text).is_ok_and(|auth| auth.console)
+}
+
+pub(super) async fn run_console<W, R>(server: &Server, text: &str, mut write: W, mut read: R)
+where
+    W: SinkExt<Message> + Unpin,
+    R: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    let admin = match console_admin(server, text) {
+        Ok(admin) => admin,
+        Err(message) => {
+            let reply = AuthReply::AuthFailed { message };
+            let _ = write
+                .send(Message::Text(serde_json::to_string(&reply).unwrap().into()))
+                .await;
+            return;
+        }
+    };
+
+    let reply = AuthReply::AuthSuccess {
+        message: "Console ready".into(),
+        capabilities: Vec::new(),
+        server_time_ms: Some(unix_now_ms()),
+    };
+    let _ = write
+        .send(Message::Text(serde_json::to_string(&reply).unwrap().into()))
+        .await;
+    println!("{} opened the admin console", admin);
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let _ = tx.send(console_help());
+    server.consoles.lock().unwrap().push(tx.clone());
+
+    loop {
+        tokio::select! {
+            line = rx.recv() => {
+                let Some(line) = line else { break };
+                if write.send(Message::Text(line.into())).await.is_err() {
+                    break;
+                }
+            }
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(line))) => {
+                    let line = line.trim();
+                    if line == "quit" {
+                        let _ = write.send(Message::Close(None)).await;
+                        break;
+                    }
+                    if !line.is_empty() {
+                        let _ = tx.send(run_console_line(server, &admin, line).await);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    server
+        .consoles
+        .lock()
+        .unwrap()
+        .retain(|console| !console.same_channel(&tx));
+    println!("{} closed the admin console", admin);
+}
+
+// The same token and ban checks as a normal login, then the admin check
+pub(super) fn console_admin(server: &Server, text: &str) -> Result<String, String> {
+    let auth: AuthMessage =
+        serde_json::from_str(text).map_err(|_| "Authenticate first".to_string())?;
+    let user = resolve_token(server, &auth.token).ok_or_else(|| "Invalid token".to_string())?;
+    let username = normalize_name(&server.config, user.username);
+    if let Some(message) = ban_message(server, &auth.token, &username) {
+        return Err(message);
+    }
+    if !server.config.admins.contains(&username) {
+        return Err("The console is for admins only".into());
+    }
+    if user.reconnect && !spend_reconnect_token(server, &auth.token) {
+        return Err("Invalid token".into());
+    }
+    Ok(username)
+}
+
+// One line per connection; the console is the only place this is shown
+pub(super) fn list_connections(clients: &HashMap<String, Client>) -> String {
+    let mut lines: Vec<String> = clients
+        .iter()
+        .map(|(user, client)| {
+            let info = &client.connection;
+            let mut capabilities: Vec<&str> =
+                client.capabilities.iter().map(String::as_str).collect();
+            capabilities.sort();
+            let mut line = format!(
+                "{} {} {} up {}s caps [{}]",
+                user,
+                info.transport,
+                info.peer
+                    .map_or_else(|| "-".to_string(), |peer| peer.to_string()),
+                client.connected_at.elapsed().as_secs(),
+                capabilities.join(","),
+            );
+            if let Some(rtt) = &client.rtt {
+                let rtt = rtt.lock().unwrap();
+                if let (Some(average), Some(quality)) = (rtt.average(), rtt.quality()) {
+                    line.push_str(&format!(" rtt {}ms {}", average, quality.as_str()));
+                }
+            }
+            if let Some(user_agent) = &info.user_agent {
+                line.push_str(&format!(" ua {:?}", user_agent));
+            }
+            if let Some(origin) = &info.origin {
+                line.push_str(&format!(" origin {:?}", origin));
+            }
+            line
+        })
+        .collect();
+    lines.sort();
+    lines.insert(0, format!("{} connected", lines.len()));
+    lines.join("\n")
+}
+
+pub(super) fn console_help() -> String {
+    let mut help = String::from("Admin console. Commands:");
+    for (usage, description) in CONSOLE_COMMANDS {
+        help.push_str(&format!("\n  {:<34} {}", usage, description));
+    }
+    help
+}
+
+pub(super) async fn run_console_line(server: &Server, admin: &str, line: &str) -> String {
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+    let user = || args.split_whitespace().next().map(str::to_string);
+
+    let frame = match name {
+        "help" => return console_help(),
+        "who" => {
+            let mut users: Vec<String> = server
+                .clients
+                .lock()
+                .await
+                .iter()
+                .map(|(user, client)| {
+                    if client.tags.is_empty() {
+                        user.clone()
+                    } else {
+                        format!("{} [{}]", user, client.tags.join(" "))
+                    }
+                })
+                .collect();
+            users.sort();
+            return format!("{} online: {}", users.len(), users.join(", "));
+        }
+        "clients" => return list_connections(&*server.clients.lock().await),
+        "kick" => user().map(|user| {
+            let reason = args[user.len()..].trim();
+            ClientFrame::Kick {
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+                user,
+            }
+        }),
+        "kickmany" => user().map(|users| {
+            let reason = args[users.len()..].trim();
+            ClientFrame::KickMany {
+                users: split_users(&users),
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+            }
+        }),
+        "banmany" => user().map(|users| {
+            let reason = args[users.len()..].trim();
+            ClientFrame::BanMany {
+                users: split_users(&users),
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+            }
+        }),
+        "mute" => {
+            let mut parts = args.split_whitespace();
+            match (parts.next(), parts.next().and_then(|n| n.parse().ok())) {
+                (Some(user), Some(seconds)) => Some(ClientFrame::Mute {
+                    user: user.to_string(),
+                    seconds,
+                }),
+                _ => None,
+            }
+        }
+        "unmute" => user().map(|user| ClientFrame::Unmute { user }),
+        "ban" => user().map(|user| {
+            let rest = args[user.len()..].trim();
+            let (seconds, reason) = match rest.split_once(' ').unwrap_or((rest, "")) {
+                (seconds, reason) if seconds.parse::<u64>().is_ok() => {
+                    (seconds.parse().ok(), reason.trim())
+                }
+                _ => (None, rest),
+            };
+            ClientFrame::Ban {
+                user: Some(user),
+                token: None,
+                seconds,
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+            }
+        }),
+        "unban" => user().map(|user| ClientFrame::Unban {
+            user: Some(user),
+            token: None,
+        }),
+        "bans" => Some(ClientFrame::ListBans),
+        "deactivate" => user().map(|user| ClientFrame::Deactivate {
+            anonymize: args[user.len()..].trim() == "anonymize",
+            user,
+        }),
+        "shadowban" => user().map(|user| ClientFrame::ShadowBan { user }),
+        "unshadowban" => user().map(|user| ClientFrame::ShadowUnban { user }),
+        "shadowmute" => user().map(|user| ClientFrame::ShadowMute { user }),
+        "unshadowmute" => user().map(|user| ClientFrame::ShadowUnmute { user }),
+        "announce" if !args.is_empty() => Some(ClientFrame::Announce {
+            text: args.to_string(),
+        }),
+        "audit" => Some(ClientFrame::Audit {
+            since: None,
+            until: None,
+            target: user(),
+            offset: 0,
+            limit: None,
+        }),
+        _ => return format!("Unknown command {}; try help", name),
+    };
+    let Some(frame) = frame else {
+        let usage = CONSOLE_COMMANDS
+            .iter()
+            .find(|(usage, _)| usage.split(' ').next() == Some(name))
+            .map_or(name, |(usage, _)| usage);
+        return format!("Usage: {}", usage);
+    };
+
+    match run_client_frame(server, admin, frame).await {
+        Ok(None) => "done".into(),
+        Ok(Some(frame)) | Err(frame) => console_format(&frame),
+    }
+}
+
+pub(super) fn split_users(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub(super) fn console_format(frame: &ServerFrame) -> String {
+    match frame {
+        ServerFrame::Error { code, message } => format!("error: {} ({})", message, code),
+        ServerFrame::System { text, .. } => text.clone(),
+        ServerFrame::KickResults { results } | ServerFrame::BanResults { results } => {
+            let mut results: Vec<_> = results.iter().collect();
+            results.sort();
+            results
+                .iter()
+                .map(|(user, result)| format!("{}: {}", user, result))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        ServerFrame::AuditLog { entries, .. } => {
+            let mut out = format!("{} audit entries", entries.len());
+            for entry in entries {
+                out.push_str(&format!(
+                    "\n  {} {} {} {} {}",
+                    entry.timestamp,
+                    entry.actor,
+                    entry.action,
+                    entry.target.as_deref().unwrap_or("-"),
+                    entry.outcome
+                ));
+            }
+            out
+        }
+        ServerFrame::Bans { bans } => {
+            let mut out = format!("{} active bans", bans.len());
+            for ban in bans {
+                out.push_str(&format!(
+                    "\n  {} by {} until {} {}",
+                    ban.user.as_deref().unwrap_or("(token)"),
+                    ban.by,
+                    ban.until
+                        .map_or("forever".to_string(), |until| until.to_string()),
+                    ban.reason.as_deref().unwrap_or("")
+                ));
+            }
+            out
+        }
+        other => serde_json::to_string(other).unwrap(),
+    }
+}
+
+// Live event lines for every open console
+pub(super) fn console_tail(server: &Server, line: &str) {
+    server
+        .consoles
+        .lock()
+        .unwrap()
+        .retain(|console| console.send(line.to_string()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Ban;
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn open_console(test: &TestServer, token: &str) -> (TestClient, serde_json::Value) {
+        let mut console = test.connect().await;
+        console
+            .send(json!({ "token": token, "console": true }))
+            .await;
+        let reply = console.recv().await.unwrap();
+        (console, reply)
+    }
+
+    #[tokio::test]
+    async fn console_sessions_stay_out_of_who_and_presence() {
+        let test = TestServer::start(config()).await;
+        let mut bob = test.login("token-bob").await;
+        let (mut console, reply) = open_console(&test, "token-alice").await;
+        assert_eq!(reply["type"], "auth_success", "{}", reply);
+        let help = console.recv_text().await.unwrap();
+        assert!(help.starts_with("Admin console"), "{}", help);
+
+        console.ws.send(Message::Text("who".into())).await.unwrap();
+        assert_eq!(console.recv_text().await.unwrap(), "1 online: bob");
+        bob.expect_none(Duration::from_millis(200), |frame| {
+            frame["context"]["user"] == "alice"
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn console_refuses_banned_admins() {
+        let test = TestServer::start(config()).await;
+        test.server.bans.lock().unwrap().bans.push(Ban {
+            user: Some("alice".into()),
+            token: None,
+            by: "alice".into(),
+            reason: Some("testing".into()),
+            until: None,
+        });
+
+        let (_, reply) = open_console(&test, "token-alice").await;
+        assert_eq!(reply["type"], "auth_failed", "{}", reply);
+        assert!(
+            reply["message"].as_str().unwrap().contains("banned"),
+            "{}",
+            reply
+        );
+
+        let (_, reply) = open_console(&test, "token-bob").await;
+        assert_eq!(reply["type"], "auth_failed", "{}", reply);
+    }
+
+    #[tokio::test]
+    async fn tags_from_the_token_store_show_in_the_roster() {
+        let mut config = config();
+        config.user_tags = HashMap::from([(
+            "bob".to_string(),
+            vec!["tier:pro".to_string(), "region:eu".to_string()],
+        )]);
+        let test = TestServer::start(config).await;
+        let _bob = test.login("token-bob").await;
+        let _charlie = test.login("token-charlie").await;
+        test.wait_online("charlie").await;
+
+        let (mut console, _) = open_console(&test, "token-alice").await;
+        console.recv_text().await.unwrap();
+        console.ws.send(Message::Text("who".into())).await.unwrap();
+        assert_eq!(
+            console.recv_text().await.unwrap(),
+            "2 online: bob [tier:pro region:eu], charlie"
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_metadata_is_only_shown_to_admins() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let test = TestServer::start(config()).await;
+        let mut request = format!("ws://{}", test.addr).into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("user-agent", "PushTest/1.0".parse().unwrap());
+        let (ws, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let mut bob = TestClient { ws };
+        bob.send(json!({ "token": "token-bob" })).await;
+        bob.expect_type("auth_success").await;
+        test.wait_online("bob").await;
+
+        let (mut console, _) = open_console(&test, "token-alice").await;
+        console.recv_text().await.unwrap();
+        console
+            .ws
+            .send(Message::Text("clients".into()))
+            .await
+            .unwrap();
+        let listing = console.recv_text().await.unwrap();
+        assert!(listing.contains(r#"ua "PushTest/1.0""#), "{}", listing);
+
+        let mut charlie = test.login("token-charlie").await;
+        charlie
+            .send(json!({ "type": "add_contact", "user": "bob" }))
+            .await;
+        charlie.send(json!({ "type": "list_contacts" })).await;
+        let contacts = charlie.expect_type("contacts").await;
+        assert!(!contacts.to_string().contains("PushTest"), "{}", contacts);
+        charlie
+            .expect_none(Duration::from_millis(200), |frame| {
+                frame.to_string().contains("PushTest")
+            })
+            .await;
+    }
+}
@@ -0,0 +1,175 @@
+//! Per-user contact lists and online notifications.
+
+use crate::protocol::{Contact, ServerFrame, Severity};
+use crate::server::{Client, Server, write_atomically};
+use std::collections::{HashMap, HashSet};
+
+pub(super) const MAX_CONTACTS: usize = 200;
+
+#[derive(Default)]
+pub(super) struct Contacts {
+    // Owner -> contact -> notify when they come online
+    pub(super) lists: HashMap<String, HashMap<String, bool>>,
+    // Contact -> owners asking to be notified, so a join only looks at
+    // those who care
+    pub(super) watchers: HashMap<String, HashSet<String>>,
+    pub(super) file: Option<String>,
+}
+
+impl Contacts {
+    pub(super) fn load(path: &str) -> anyhow::Result<Contacts> {
+        let lists: HashMap<String, HashMap<String, bool>> = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("parsing contacts {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => anyhow::bail!("reading contacts {}: {}", path, e),
+        };
+        let mut contacts = Contacts {
+            lists: HashMap::new(),
+            watchers: HashMap::new(),
+            file: Some(path.to_string()),
+        };
+        for (owner, list) in lists {
+            for (user, notify) in list {
+                contacts.set(&owner, user, notify);
+            }
+        }
+        Ok(contacts)
+    }
+
+    pub(super) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_atomically(path, &self.lists) {
+            eprintln!("saving contacts to {} failed: {}", path, e);
+        }
+    }
+
+    pub(super) fn set(&mut self, owner: &str, user: String, notify: bool) {
+        if notify {
+            self.watchers
+                .entry(user.clone())
+                .or_default()
+                .insert(owner.to_string());
+        } else {
+            self.unwatch(owner, &user);
+        }
+        self.lists
+            .entry(owner.to_string())
+            .or_default()
+            .insert(user, notify);
+    }
+
+    pub(super) fn remove(&mut self, owner: &str, user: &str) -> bool {
+        let Some(list) = self.lists.get_mut(owner) else {
+            return false;
+        };
+        let removed = list.remove(user).is_some();
+        if list.is_empty() {
+            self.lists.remove(owner);
+        }
+        self.unwatch(owner, user);
+        removed
+    }
+
+    pub(super) fn unwatch(&mut self, owner: &str, user: &str) {
+        if let Some(watchers) = self.watchers.get_mut(user) {
+            watchers.remove(owner);
+            if watchers.is_empty() {
+                self.watchers.remove(user);
+            }
+        }
+    }
+
+    pub(super) fn list(&self, owner: &str, clients: &HashMap<String, Client>) -> Vec<Contact> {
+        let Some(list) = self.lists.get(owner) else {
+            return Vec::new();
+        };
+        let mut contacts: Vec<Contact> = list
+            .iter()
+            .map(|(user, &notify_on_online)| Contact {
+                user: user.clone(),
+                online: clients.contains_key(user),
+                notify_on_online,
+            })
+            .collect();
+        contacts.sort_by(|a, b| a.user.cmp(&b.user));
+        contacts
+    }
+}
+
+pub(super) async fn add_contact(
+    server: &Server,
+    username: &str,
+    user: String,
+    notify_on_online: bool,
+) -> Result<ServerFrame, ServerFrame> {
+    if user == username {
+        return Err(ServerFrame::Error {
+            code: "invalid_contact".into(),
+            message: "You can't add yourself as a contact".into(),
+        });
+    }
+
+    let clients_guard = server.clients.lock().await;
+    let mut contacts = server.contacts.lock().unwrap();
+    let list = contacts.lists.get(username);
+    let known = list.is_some_and(|list| list.contains_key(&user));
+    if !known && list.is_some_and(|list| list.len() >= MAX_CONTACTS) {
+        return Err(ServerFrame::Error {
+            code: "too_many_contacts".into(),
+            message: format!("You can have at most {} contacts", MAX_CONTACTS),
+        });
+    }
+    contacts.set(username, user, notify_on_online);
+    contacts.save();
+
+    Ok(ServerFrame::Contacts {
+        contacts: contacts.list(username, &clients_guard),
+    })
+}
+
+pub(super) async fn remove_contact(
+    server: &Server,
+    username: &str,
+    user: &str,
+) -> Result<ServerFrame, ServerFrame> {
+    let clients_guard = server.clients.lock().await;
+    let mut contacts = server.contacts.lock().unwrap();
+    if !contacts.remove(username, user) {
+        return Err(ServerFrame::Error {
+            code: "not_a_contact".into(),
+            message: format!("{} is not in your contacts", user),
+        });
+    }
+    contacts.save();
+
+    Ok(ServerFrame::Contacts {
+        contacts: contacts.list(username, &clients_guard),
+    })
+}
+
+pub(super) async fn notify_contact_watchers(server: &Server, username: &str) {
+    let Some(watchers) = server
+        .contacts
+        .lock()
+        .unwrap()
+        .watchers
+        .get(username)
+        .cloned()
+    else {
+        return;
+    };
+    let frame = ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("{} is online", username),
+        context: Some(serde_json::json!({ "event": "contact_online", "user": username })),
+    };
+    let clients_guard = server.clients.lock().await;
+    for watcher in &watchers {
+        if let Some(client) = clients_guard.get(watcher) {
+            client.send_frame(&frame);
+        }
+    }
+}
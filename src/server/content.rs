@@ -0,0 +1,165 @@
+//! Content normalizing: whitespace and HTML.
+
+// Trailing spaces go from every line, runs of blank lines shrink to one and
+// the message is trimmed at both ends. Indentation inside is kept.
+pub(super) fn normalize_whitespace(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut blank_run = 0;
+    for line in content.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank_run += 1;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank_run > 0 { "\n\n" } else { "\n" });
+        }
+        blank_run = 0;
+        out.push_str(line);
+    }
+    out.trim_start().to_string()
+}
+
+// Dropped along with everything up to their closing tag
+pub(super) const RAW_TEXT_TAGS: [&str; 2] = ["script", "style"];
+
+// Removes tags and comments, and whatever script and style elements hold.
+// A `<` that can't start a tag (`a < b`) is kept; an unterminated tag runs
+// to the end and is dropped, as a browser would.
+pub(super) fn strip_html(content: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find('<') {
+        let after = &rest[start + 1..];
+        if !after.starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?')) {
+            stripped.push_str(&rest[..start + 1]);
+            rest = after;
+            continue;
+        }
+        stripped.push_str(&rest[..start]);
+
+        let (tag_end, close) = if after.starts_with("!--") {
+            (after.find("-->"), 3)
+        } else {
+            (find_tag_end(after), 1)
+        };
+        let Some(tag_end) = tag_end else {
+            return stripped;
+        };
+        let name = after[..tag_end]
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        rest = &after[tag_end + close..];
+
+        if RAW_TEXT_TAGS.contains(&name.as_str()) {
+            // ASCII lowercasing keeps byte offsets, so they index `rest`
+            let closing = format!("</{}", name);
+            let Some(body_end) = rest.to_ascii_lowercase().find(&closing) else {
+                return stripped;
+            };
+            let Some(tag_end) = rest[body_end..].find('>') else {
+                return stripped;
+            };
+            rest = &rest[body_end + tag_end + 1..];
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+// The `>` closing a tag, skipping any inside quoted attribute values
+pub(super) fn find_tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    #[test]
+    fn strip_html_drops_tags_scripts_and_comments() {
+        assert_eq!(
+            strip_html("hi <script>alert('x')</script><b>there</b>"),
+            "hi there"
+        );
+        assert_eq!(strip_html("a <!-- note --> b"), "a  b");
+        assert_eq!(strip_html("1 < 2 and 3 > 2"), "1 < 2 and 3 > 2");
+        assert_eq!(strip_html("cut <img src=x"), "cut ");
+    }
+
+    #[tokio::test]
+    async fn script_is_stripped_only_for_sanitized_types() {
+        let mut config = config();
+        config.content_types.insert("text/html".to_string());
+        config.sanitize_types = std::collections::HashSet::from(["text/html".to_string()]);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let content = "hi<script>steal()</script>";
+
+        alice
+            .send(json!({ "to": "bob", "content": content, "content_type": "text/html" }))
+            .await;
+        assert_eq!(bob.expect_message().await["content"], "hi");
+
+        alice
+            .send(json!({ "to": "bob", "content": content, "content_type": "text/plain" }))
+            .await;
+        assert_eq!(bob.expect_message().await["content"], content);
+    }
+
+    #[test]
+    fn normalize_whitespace_trims_and_collapses_blank_lines() {
+        assert_eq!(
+            normalize_whitespace("\n  hi  \n\n\n\n    indented \nthere\n\n"),
+            "hi\n\n    indented\nthere"
+        );
+        assert_eq!(normalize_whitespace(" \n \n"), "");
+    }
+
+    #[tokio::test]
+    async fn whitespace_is_normalized_only_when_enabled_for_the_type() {
+        let content = "  hi there  \n\n\n\nbye\n";
+        let start = |normalize: bool| {
+            let mut config = config();
+            config.content_types.insert("text/x-code".to_string());
+            if normalize {
+                config.normalize_types =
+                    std::collections::HashSet::from(["text/plain".to_string()]);
+            }
+            TestServer::start(config)
+        };
+
+        let test = start(true).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        alice
+            .send(json!({ "to": "bob", "content": content, "content_type": "text/plain" }))
+            .await;
+        assert_eq!(bob.expect_message().await["content"], "hi there\n\nbye");
+        alice
+            .send(json!({ "to": "bob", "content": content, "content_type": "text/x-code" }))
+            .await;
+        assert_eq!(bob.expect_message().await["content"], content);
+
+        let test = start(false).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        alice
+            .send(json!({ "to": "bob", "content": content, "content_type": "text/plain" }))
+            .await;
+        assert_eq!(bob.expect_message().await["content"], content);
+    }
+}
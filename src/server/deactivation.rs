@@ -0,0 +1,265 @@
+//! Deactivating an account and clearing what the server keeps for it.
+
+use crate::protocol::{Ban, ServerFrame, Severity};
+use crate::server::Server;
+use crate::server::audit::{PrivilegedCommand, audit_command};
+use crate::server::moderation::{kick_client, require_admin};
+use sha1::{Digest, Sha1};
+
+// Bans the user for good, then clears what the server keeps for them. The
+// ban comes first so they can't reconnect halfway through, and every step
+// after it only removes what's still there, so running it again after a
+// crash or a failed save finishes the job. Recorded as one audit entry
+// listing what was removed.
+pub(super) async fn deactivate(
+    server: &Server,
+    admin: &str,
+    user: &str,
+    anonymize: bool,
+) -> Result<ServerFrame, ServerFrame> {
+    let outcome = match require_admin(server, admin) {
+        Ok(()) => Ok(clear_account(server, admin, user, anonymize).await),
+        Err(frame) => Err(frame),
+    };
+    audit_command(
+        server,
+        admin,
+        PrivilegedCommand {
+            action: "deactivate",
+            target: Some(user.to_string()),
+            params: serde_json::json!({
+                "anonymize": anonymize,
+                "removed": outcome.as_ref().ok(),
+            }),
+        },
+        outcome.as_ref().err(),
+    );
+    let removed = outcome?;
+
+    println!("{} deactivated {}", admin, user);
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("{} is deactivated", user),
+        context: Some(
+            serde_json::json!({ "event": "deactivate", "user": user, "removed": removed }),
+        ),
+    })
+}
+
+pub(super) async fn clear_account(
+    server: &Server,
+    admin: &str,
+    user: &str,
+    anonymize: bool,
+) -> serde_json::Value {
+    let banned = {
+        let mut bans = server.bans.lock().unwrap();
+        bans.prune();
+        let banned = bans
+            .bans
+            .iter()
+            .any(|ban| ban.user.as_deref() == Some(user) && ban.until.is_none());
+        if !banned {
+            bans.remove(Some(user), None);
+            bans.bans.push(Ban {
+                user: Some(user.to_string()),
+                token: None,
+                by: admin.to_string(),
+                reason: Some("account deactivated".into()),
+                until: None,
+            });
+        }
+        bans.save();
+        !banned
+    };
+
+    let disconnected = server.clients.lock().await.contains_key(user)
+        && kick_client(server, admin, user, Some("account deactivated".into()))
+            .await
+            .is_ok();
+
+    let reconnect_tokens = {
+        let mut sessions = server.sessions.lock().unwrap();
+        let before = sessions.tokens.len();
+        sessions.tokens.retain(|_, session| session.user != user);
+        sessions.save();
+        before - sessions.tokens.len()
+    };
+
+    let scheduled = {
+        let mut schedule = server.schedule.lock().unwrap();
+        let before = schedule.messages.len();
+        schedule.messages.retain(|scheduled| scheduled.from != user);
+        schedule.save();
+        before - schedule.messages.len()
+    };
+
+    // Their own list, and their entry in everyone else's
+    let contacts = {
+        let mut contacts = server.contacts.lock().unwrap();
+        let own: Vec<String> = contacts
+            .lists
+            .get(user)
+            .map(|list| list.keys().cloned().collect())
+            .unwrap_or_default();
+        let owners: Vec<String> = contacts
+            .lists
+            .iter()
+            .filter(|(_, list)| list.contains_key(user))
+            .map(|(owner, _)| owner.clone())
+            .collect();
+        let mut removed = 0;
+        for contact in &own {
+            removed += contacts.remove(user, contact) as usize;
+        }
+        for owner in &owners {
+            removed += contacts.remove(owner, user) as usize;
+        }
+        contacts.save();
+        removed
+    };
+
+    let keywords = {
+        let mut keywords = server.keywords.lock().unwrap();
+        let removed = keywords
+            .subscriptions
+            .remove(user)
+            .map_or(0, |words| words.len());
+        keywords.matcher = None;
+        keywords.save();
+        removed
+    };
+
+    let quiet_hours = server.quiet_hours.lock().unwrap().remove(user).is_some();
+    server.mutes.lock().unwrap().remove(user);
+    server.rate.lock().unwrap().remove(user);
+
+    let anonymized = if anonymize {
+        anonymize_dm_history(server, user)
+    } else {
+        0
+    };
+
+    // DMs the log holds for them to catch up on after a reconnect
+    let tombstone = anonymize.then(|| tombstone_id(user));
+    let queued = server
+        .history
+        .lock()
+        .unwrap()
+        .forget(user, tombstone.as_deref());
+
+    serde_json::json!({
+        "ban": banned,
+        "connection": disconnected,
+        "reconnect_tokens": reconnect_tokens,
+        "scheduled": scheduled,
+        "contacts": contacts,
+        "keywords": keywords,
+        "quiet_hours": quiet_hours,
+        "anonymized_messages": anonymized,
+        "queued_messages": queued,
+    })
+}
+
+// The same user always gets the same tombstone, so a second run leaves
+// threads where the first put them
+pub(super) fn tombstone_id(user: &str) -> String {
+    let digest = Sha1::digest(user.as_bytes());
+    let hex: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("deleted-{}", hex)
+}
+
+// Rewrites both ends of each stored DM, and rekeys the thread so the other
+// participant finds it under the tombstone id
+pub(super) fn anonymize_dm_history(server: &Server, user: &str) -> usize {
+    let tombstone = tombstone_id(user);
+    let mut history = server.dm_history.lock().unwrap();
+    let keys: Vec<(String, String)> = history
+        .keys()
+        .filter(|(a, b)| a == user || b == user)
+        .cloned()
+        .collect();
+
+    let mut anonymized = 0;
+    for key in keys {
+        let mut thread = history.remove(&key).unwrap();
+        for message in &mut thread {
+            if message.from == user {
+                message.from = tombstone.clone();
+                anonymized += 1;
+            }
+            if message.to.as_deref() == Some(user) {
+                message.to = Some(tombstone.clone());
+            }
+        }
+        let other = if key.0 == user { key.1 } else { key.0 };
+        let key = if other < tombstone {
+            (other, tombstone.clone())
+        } else {
+            (tombstone.clone(), other)
+        };
+        history.entry(key).or_default().extend(thread);
+    }
+    anonymized
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn a_deactivated_user_cannot_come_back_and_their_queue_is_purged() {
+        let mut config = config();
+        config.token_refresh = Duration::from_secs(1);
+        config.history_size = 100;
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let refresh = bob.expect_type("token_refresh").await;
+        bob.close().await;
+        test.wait_offline("bob").await;
+
+        alice
+            .send(json!({ "to": "bob", "content": "queued for bob" }))
+            .await;
+        alice.send(json!({ "content": "for everyone" })).await;
+        let deactivate = json!({ "type": "deactivate", "user": "bob" });
+        alice.send(deactivate.clone()).await;
+        let done = alice.expect_event("deactivate").await;
+        let removed = &done["context"]["removed"];
+        assert_eq!(removed["ban"], true, "{}", done);
+        assert_eq!(removed["queued_messages"], 1, "{}", done);
+        assert!(
+            removed["reconnect_tokens"].as_u64().unwrap() >= 1,
+            "{}",
+            done
+        );
+
+        let missed: Vec<String> = test
+            .server
+            .history
+            .lock()
+            .unwrap()
+            .missed("bob", 0)
+            .iter()
+            .map(|message| message.content.clone())
+            .collect();
+        assert_eq!(missed, ["for everyone"]);
+
+        for token in ["token-bob", refresh["token"].as_str().unwrap()] {
+            let mut again = test.connect().await;
+            again.send(json!({ "token": token })).await;
+            assert_eq!(again.recv().await.unwrap()["type"], "auth_failed");
+        }
+
+        // A second run finds nothing left to do
+        alice.send(deactivate).await;
+        let again = alice.expect_event("deactivate").await;
+        let removed = &again["context"]["removed"];
+        assert_eq!(removed["ban"], false, "{}", again);
+        assert_eq!(removed["queued_messages"], 0, "{}", again);
+        assert_eq!(removed["reconnect_tokens"], 0, "{}", again);
+    }
+}
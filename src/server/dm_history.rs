@@ -0,0 +1,103 @@
+//! Recent DM history.
+
+use crate::protocol::{ServerFrame, ServerMessage};
+use crate::server::Server;
+
+pub(super) fn dm_thread_key(a: &str, b: &str) -> (String, String) {
+    if a <= b {
+        (a.to_string(), b.to_string())
+    } else {
+        (b.to_string(), a.to_string())
+    }
+}
+
+pub(super) fn record_dm(server: &Server, msg: &ServerMessage) {
+    let (Some(to), true) = (&msg.to, server.config.dm_history > 0) else {
+        return;
+    };
+
+    let mut history = server.dm_history.lock().unwrap();
+    let thread = history.entry(dm_thread_key(&msg.from, to)).or_default();
+    if thread.len() == server.config.dm_history {
+        thread.pop_front();
+    }
+    thread.push_back(msg.clone());
+}
+
+// The requester is always one side of the thread key, so nobody can read a
+// thread they aren't part of
+pub(super) fn fetch_dm_history(
+    server: &Server,
+    username: &str,
+    with: String,
+    limit: Option<usize>,
+) -> ServerFrame {
+    if server.config.dm_history == 0 {
+        return ServerFrame::Error {
+            code: "dm_history_disabled".into(),
+            message: "DM history is not stored on this server".into(),
+        };
+    }
+
+    let history = server.dm_history.lock().unwrap();
+    let messages = match history.get(&dm_thread_key(username, &with)) {
+        Some(thread) => {
+            let limit = limit.unwrap_or(thread.len()).min(thread.len());
+            thread.iter().skip(thread.len() - limit).cloned().collect()
+        }
+        None => Vec::new(),
+    };
+
+    ServerFrame::DmHistory { with, messages }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    fn fetch(with: &str) -> serde_json::Value {
+        json!({ "type": "fetch_dm_history", "with": with })
+    }
+
+    #[tokio::test]
+    async fn only_participants_see_a_thread() {
+        let mut config = config();
+        config.dm_history = 10;
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let mut charlie = test.login("token-charlie").await;
+        test.wait_online("bob").await;
+
+        alice
+            .send(json!({ "to": "bob", "content": "secret" }))
+            .await;
+        bob.expect_message().await;
+
+        bob.send(fetch("alice")).await;
+        let history = bob.expect_type("dm_history").await;
+        assert_eq!(history["messages"][0]["content"], "secret");
+        alice.send(fetch("bob")).await;
+        let history = alice.expect_type("dm_history").await;
+        assert_eq!(history["messages"].as_array().unwrap().len(), 1);
+
+        // Naming either participant only finds charlie's own thread with them
+        for with in ["alice", "bob"] {
+            charlie.send(fetch(with)).await;
+            let history = charlie.expect_type("dm_history").await;
+            assert_eq!(history["with"], with);
+            assert_eq!(history["messages"], json!([]), "{}", history);
+        }
+    }
+
+    #[tokio::test]
+    async fn history_is_off_unless_configured() {
+        let mut config = config();
+        config.dm_history = 0;
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        alice.send(fetch("bob")).await;
+        alice.expect_error("dm_history_disabled").await;
+    }
+}
@@ -0,0 +1,111 @@
+//! Group conversations.
+
+use crate::protocol::ServerFrame;
+use crate::server::Server;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+pub(super) const MAX_GROUP_MEMBERS: usize = 20;
+
+// The id is a hash of the sorted members, so the same people always land
+// in the same group and membership can't change. `Ok(None)` is a plain DM
+// or broadcast.
+pub(super) fn resolve_conversation(
+    server: &Server,
+    username: &str,
+    members: Option<Vec<String>>,
+    conversation_id: Option<String>,
+) -> Result<Option<(String, Vec<String>)>, ServerFrame> {
+    if let Some(mut members) = members {
+        members.push(username.to_string());
+        members.sort();
+        members.dedup();
+        if members.len() < 3 || members.len() > MAX_GROUP_MEMBERS {
+            return Err(ServerFrame::Error {
+                code: "invalid_group".into(),
+                message: format!(
+                    "Groups need 3 to {} members, yourself included",
+                    MAX_GROUP_MEMBERS
+                ),
+            });
+        }
+        let mut hasher = DefaultHasher::new();
+        members.hash(&mut hasher);
+        let id = format!("{:016x}", hasher.finish());
+        server
+            .conversations
+            .lock()
+            .unwrap()
+            .entry(id.clone())
+            .or_insert_with(|| members.clone());
+        return Ok(Some((id, members)));
+    }
+
+    let Some(id) = conversation_id else {
+        return Ok(None);
+    };
+    match server.conversations.lock().unwrap().get(&id) {
+        Some(members) if members.iter().any(|member| member == username) => {
+            Ok(Some((id, members.clone())))
+        }
+        // Not being a member looks the same as a wrong id
+        _ => Err(ServerFrame::Error {
+            code: "no_such_conversation".into(),
+            message: "No group conversation with that id".into(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn group_messages_reach_every_member_and_replies_use_the_id() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let mut charlie = test.login("token-charlie").await;
+
+        alice
+            .send(json!({ "to": null, "content": "lunch?", "members": ["bob", "charlie"] }))
+            .await;
+        let created = alice.expect_type("conversation").await;
+        let id = created["conversation_id"].as_str().unwrap().to_string();
+        for member in [&mut bob, &mut charlie] {
+            let message = member.expect_message().await;
+            assert_eq!(message["conversation_id"], id.as_str());
+            assert_eq!(message["members"], json!(["alice", "bob", "charlie"]));
+        }
+
+        bob.send(json!({ "to": null, "content": "sure", "conversation_id": id }))
+            .await;
+        for member in [&mut alice, &mut charlie] {
+            let reply = member.expect_message().await;
+            assert_eq!(reply["from"], "bob");
+            assert_eq!(reply["content"], "sure");
+        }
+
+        // The same people always get the same group
+        charlie
+            .send(json!({ "to": null, "content": "again", "members": ["alice", "bob"] }))
+            .await;
+        let again = charlie.expect_type("conversation").await;
+        assert_eq!(again["conversation_id"], id.as_str());
+    }
+
+    #[tokio::test]
+    async fn groups_need_three_members_and_a_known_id() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+
+        alice
+            .send(json!({ "to": null, "content": "hi", "members": ["bob"] }))
+            .await;
+        alice.expect_error("invalid_group").await;
+        alice
+            .send(json!({ "to": null, "content": "hi", "conversation_id": "nope" }))
+            .await;
+        alice.expect_error("no_such_conversation").await;
+    }
+}
@@ -0,0 +1,161 @@
+//! The per-connection ping timer, round trip stats and opt-in
+//! `server_heartbeat` events.
+
+use crate::protocol::{ConnectionQuality, ServerFrame};
+use crate::server::Server;
+use crate::server::config::Config;
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+// Round trips averaged into a connection's quality
+pub(super) const RTT_SAMPLES: usize = 8;
+// Average round trips up to these are `good` and `fair`; above is `poor`
+pub(super) const GOOD_RTT_MS: u64 = 150;
+pub(super) const FAIR_RTT_MS: u64 = 400;
+
+// Round trips of the last few answered pings, newest last. Shared between a
+// connection's reader, which records pongs, and whoever reports on it.
+#[derive(Default)]
+pub(super) struct RttStats {
+    pub(super) samples: VecDeque<u64>,
+}
+
+impl RttStats {
+    pub(super) fn record(&mut self, rtt_ms: u64) {
+        if self.samples.len() == RTT_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt_ms);
+    }
+
+    pub(super) fn last(&self) -> Option<u64> {
+        self.samples.back().copied()
+    }
+
+    pub(super) fn average(&self) -> Option<u64> {
+        let total: u64 = self.samples.iter().sum();
+        (!self.samples.is_empty()).then(|| total / self.samples.len() as u64)
+    }
+
+    pub(super) fn quality(&self) -> Option<ConnectionQuality> {
+        self.average().map(|average| {
+            if average <= GOOD_RTT_MS {
+                ConnectionQuality::Good
+            } else if average <= FAIR_RTT_MS {
+                ConnectionQuality::Fair
+            } else {
+                ConnectionQuality::Poor
+            }
+        })
+    }
+}
+
+// First tick one interval in, not on connect. Never ticks when the interval
+// is 0; callers guard on that too.
+pub(super) fn heartbeat_timer(config: &Config) -> tokio::time::Interval {
+    let period = if config.heartbeat_interval.is_zero() {
+        Duration::from_secs(60 * 60)
+    } else {
+        config.heartbeat_interval
+    };
+    tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+}
+
+// Only atomics and the connection's own state, so no connection's heartbeat
+// ever waits on the clients map. `rtt` is `None` on transports without
+// pings.
+pub(super) fn heartbeat_frame(
+    server: &Server,
+    rtt: Option<&std::sync::Mutex<RttStats>>,
+    queue_depth: usize,
+) -> String {
+    let (latency_ms, avg_latency_ms, quality) = match rtt {
+        Some(rtt) => {
+            let rtt = rtt.lock().unwrap();
+            (rtt.last(), rtt.average(), rtt.quality())
+        }
+        None => (None, None, None),
+    };
+    serde_json::to_string(&ServerFrame::ServerHeartbeat {
+        uptime_secs: server.started_at.elapsed().as_secs(),
+        connected: server.connected.load(Ordering::Relaxed),
+        latency_ms,
+        queue_depth,
+        avg_latency_ms,
+        quality,
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::console::list_connections;
+    use crate::server::testing::*;
+    use crate::server::{Client, unix_now_ms};
+    use futures_util::SinkExt;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[test]
+    fn quality_follows_the_rolling_average() {
+        let mut rtt = RttStats::default();
+        assert_eq!(rtt.quality(), None);
+        rtt.record(20);
+        assert_eq!(rtt.quality(), Some(ConnectionQuality::Good));
+        for _ in 0..3 {
+            rtt.record(900);
+        }
+        assert_eq!(rtt.average(), Some(680));
+        assert_eq!(rtt.quality(), Some(ConnectionQuality::Poor));
+        // Old samples age out of the window
+        for _ in 0..RTT_SAMPLES {
+            rtt.record(300);
+        }
+        assert_eq!(rtt.last(), Some(300));
+        assert_eq!(rtt.quality(), Some(ConnectionQuality::Fair));
+    }
+
+    #[test]
+    fn console_lists_the_rating() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (mut client, _kicked) = Client::new(tx);
+        let rtt = Arc::new(std::sync::Mutex::new(RttStats::default()));
+        rtt.lock().unwrap().record(500);
+        client.rtt = Some(rtt);
+        let listing = list_connections(&HashMap::from([("alice".to_string(), client)]));
+        assert!(listing.contains("rtt 500ms poor"), "{}", listing);
+    }
+
+    // Pongs carry the ping's send time, so an old timestamp reads as a
+    // slow round trip
+    async fn pong_sent_ago(client: &mut TestClient, ago_ms: u64) {
+        let sent = (unix_now_ms() - ago_ms).to_be_bytes().to_vec();
+        client.ws.send(Message::Pong(sent.into())).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn high_round_trips_degrade_the_reported_quality() {
+        let mut config = config();
+        config.heartbeat_interval = Duration::from_secs(1);
+        let test = TestServer::start(config).await;
+        let mut alice = test
+            .login_with(json!({ "token": "token-alice", "capabilities": ["server_heartbeat"] }))
+            .await;
+
+        pong_sent_ago(&mut alice, 10).await;
+        let heartbeat = alice.expect_type("server_heartbeat").await;
+        assert_eq!(heartbeat["quality"], "good", "{}", heartbeat);
+
+        for _ in 0..RTT_SAMPLES {
+            pong_sent_ago(&mut alice, 2000).await;
+        }
+        let heartbeat = alice.expect_type("server_heartbeat").await;
+        assert_eq!(heartbeat["quality"], "poor", "{}", heartbeat);
+        assert!(heartbeat["avg_latency_ms"].as_u64().unwrap() > FAIR_RTT_MS);
+    }
+}
@@ -0,0 +1,254 @@
+//! Recent messages, kept so a resumed session can catch up.
+
+use crate::protocol::{ServerFrame, ServerMessage, Severity};
+use crate::server::{Client, Server};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::Write;
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct LoggedMessage {
+    pub(super) seq: u64,
+    pub(super) message: ServerMessage,
+}
+
+// The last `capacity` messages routed, numbered so a session can say how far
+// it got. Appended to `file` as one JSON line each.
+#[derive(Default)]
+pub(super) struct MessageLog {
+    pub(super) messages: VecDeque<LoggedMessage>,
+    pub(super) last_seq: u64,
+    pub(super) capacity: usize,
+    pub(super) path: Option<String>,
+    pub(super) file: Option<std::fs::File>,
+}
+
+impl MessageLog {
+    pub(super) fn new(capacity: usize) -> MessageLog {
+        MessageLog {
+            capacity,
+            ..MessageLog::default()
+        }
+    }
+
+    // Keeps the newest `capacity` lines and rewrites the file to just those,
+    // so it doesn't grow across restarts
+    pub(super) fn open(path: &str, capacity: usize) -> anyhow::Result<MessageLog> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => anyhow::bail!("reading message history {}: {}", path, e),
+        };
+        let mut messages: VecDeque<LoggedMessage> = text
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let last_seq = messages.back().map_or(0, |logged| logged.seq);
+        while messages.len() > capacity {
+            messages.pop_front();
+        }
+
+        let mut log = MessageLog {
+            messages,
+            last_seq,
+            capacity,
+            path: Some(path.to_string()),
+            file: None,
+        };
+        log.rewrite()
+            .map_err(|e| anyhow::anyhow!("writing message history {}: {}", path, e))?;
+        Ok(log)
+    }
+
+    // Replaces the file with what's in memory and reopens it for appending
+    pub(super) fn rewrite(&mut self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = format!("{}.tmp", path);
+        let lines: String = self
+            .messages
+            .iter()
+            .map(|logged| serde_json::to_string(logged).unwrap() + "\n")
+            .collect();
+        std::fs::write(&tmp, lines)?;
+        std::fs::rename(&tmp, path)?;
+        self.file = Some(std::fs::OpenOptions::new().append(true).open(path)?);
+        Ok(())
+    }
+
+    // Drops the DMs waiting for `user` and, given a tombstone, renames them
+    // in everything else. Returns how many DMs were dropped.
+    pub(super) fn forget(&mut self, user: &str, tombstone: Option<&str>) -> usize {
+        let before = self.messages.len();
+        self.messages.retain(|logged| {
+            logged.message.to.as_deref() != Some(user) || logged.message.members.is_some()
+        });
+        let dropped = before - self.messages.len();
+        if let Some(tombstone) = tombstone {
+            for logged in &mut self.messages {
+                if logged.message.from == user {
+                    logged.message.from = tombstone.to_string();
+                }
+            }
+        }
+        if let Err(e) = self.rewrite() {
+            eprintln!("message history rewrite failed: {}", e);
+        }
+        dropped
+    }
+
+    pub(super) fn record(&mut self, message: &ServerMessage) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.last_seq += 1;
+        let logged = LoggedMessage {
+            seq: self.last_seq,
+            message: message.clone(),
+        };
+        if let Some(file) = &mut self.file
+            && let Err(e) = writeln!(file, "{}", serde_json::to_string(&logged).unwrap())
+        {
+            eprintln!("message history write failed: {}", e);
+        }
+        if self.messages.len() == self.capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(logged);
+    }
+
+    // What `username` would have been sent after `seq`: broadcasts, their
+    // DMs and their groups' messages, but not their own
+    pub(super) fn missed(&self, username: &str, seq: u64) -> Vec<&ServerMessage> {
+        self.messages
+            .iter()
+            .filter(|logged| logged.seq > seq)
+            .map(|logged| &logged.message)
+            .filter(|message| message.from != username)
+            .filter(|message| match (&message.to, &message.members) {
+                (_, Some(members)) => members.iter().any(|member| member == username),
+                (Some(to), None) => to == username,
+                (None, None) => true,
+            })
+            .collect()
+    }
+}
+
+// Sends what a resumed session missed, ahead of anything live; the caller
+// holds the clients lock, which routing also holds while recording
+pub(super) fn replay_missed(server: &Server, username: &str, client: &Client, seq: u64) {
+    let log = server.history.lock().unwrap();
+    let missed = log.missed(username, seq);
+    if missed.is_empty() {
+        return;
+    }
+    client.send_frame(&ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("{} messages while you were away", missed.len()),
+        context: Some(serde_json::json!({ "event": "replay", "count": missed.len() })),
+    });
+    for message in missed {
+        let _ = client.tx.send(serde_json::to_string(message).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn message(from: &str, to: Option<&str>, content: &str) -> ServerMessage {
+        serde_json::from_value(json!({ "from": from, "to": to, "content": content })).unwrap()
+    }
+
+    #[test]
+    fn reopening_keeps_the_newest_messages_and_their_numbers() {
+        let path = temp_file("history.log");
+        let mut log = MessageLog::open(&path, 2).unwrap();
+        log.record(&message("alice", None, "one"));
+        log.record(&message("alice", Some("bob"), "two"));
+        log.record(&message("bob", None, "three"));
+        drop(log);
+
+        let mut log = MessageLog::open(&path, 2).unwrap();
+        assert_eq!(log.last_seq, 3);
+        let contents = |log: &MessageLog, user: &str, seq: u64| -> Vec<String> {
+            log.missed(user, seq)
+                .iter()
+                .map(|message| message.content.clone())
+                .collect()
+        };
+        assert_eq!(contents(&log, "bob", 0), ["two"]);
+        assert_eq!(contents(&log, "charlie", 0), ["three"]);
+        assert!(contents(&log, "charlie", 3).is_empty());
+
+        log.record(&message("alice", None, "four"));
+        assert_eq!(log.last_seq, 4);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_resumed_session_gets_what_it_missed_across_a_restart() {
+        let sessions = temp_file("sessions.json");
+        let history = temp_file("history.log");
+        let config = || {
+            let mut config = config();
+            config.token_refresh = Duration::from_secs(1);
+            config.sessions_file = Some(sessions.clone());
+            config.history_file = Some(history.clone());
+            config.history_size = 100;
+            config
+        };
+
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+        alice.send(json!({ "content": "before" })).await;
+        bob.expect_message().await;
+        let refresh = bob.expect_type("token_refresh").await;
+        let token = refresh["token"].as_str().unwrap().to_string();
+        bob.close().await;
+        test.wait_offline("bob").await;
+
+        alice.send(json!({ "content": "while away" })).await;
+        alice
+            .send(json!({ "to": "bob", "content": "dm while away" }))
+            .await;
+        alice
+            .send(json!({ "to": "charlie", "content": "not for bob" }))
+            .await;
+        // Routing records under the clients lock, so once this round trip
+        // is back the messages are on disk
+        alice
+            .send(json!({ "type": "time", "client_time_ms": 1 }))
+            .await;
+        alice.expect_type("time").await;
+        alice.close().await;
+        test.wait_offline("alice").await;
+
+        // A second server on the same files stands in for the restart
+        let test = TestServer::start(config()).await;
+        let mut bob = test.login(&token).await;
+        let replay = bob.expect_event("replay").await;
+        assert_eq!(replay["context"]["count"], 2);
+        assert_eq!(bob.expect_message().await["content"], "while away");
+        let dm = bob.expect_message().await;
+        assert_eq!(
+            (&dm["content"], &dm["to"]),
+            (&json!("dm while away"), &json!("bob"))
+        );
+
+        // Logging in with the original token starts fresh
+        bob.close().await;
+        test.wait_offline("bob").await;
+        let mut bob = test.login("token-bob").await;
+        bob.expect_none(Duration::from_millis(200), |frame| {
+            frame["context"]["event"] == "replay" || frame.get("from").is_some()
+        })
+        .await;
+    }
+}
@@ -0,0 +1,127 @@
+//! Plain HTTP routes sharing the WebSocket port.
+
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::net::TcpStream;
+
+#[derive(Clone, Copy)]
+pub(super) enum HttpRoute {
+    Events,
+    Hook,
+    Health,
+    Admin,
+}
+
+// Request line prefixes, each no longer than the peek buffer
+pub(super) const HTTP_ROUTES: [(&[u8], HttpRoute); 4] = [
+    (b"GET /events", HttpRoute::Events),
+    (b"POST /hooks/", HttpRoute::Hook),
+    (b"GET /healthz", HttpRoute::Health),
+    (b"POST /admin/", HttpRoute::Admin),
+];
+
+// How long a client gets to send enough of its request line to be routed
+pub(super) const PEEK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Peeks until the request line matches a route, rules them all out or ends;
+// a request line split across packets is waited for rather than taken for a
+// WebSocket upgrade
+pub(super) async fn peek_http_route(stream: &TcpStream) -> Option<HttpRoute> {
+    let mut buf = [0u8; 12];
+    let deadline = tokio::time::Instant::now() + PEEK_TIMEOUT;
+    loop {
+        let n = tokio::time::timeout_at(deadline, stream.peek(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        let head = &buf[..n];
+
+        let undecided = HTTP_ROUTES
+            .iter()
+            .any(|(prefix, _)| prefix.len() > n && prefix.starts_with(head));
+        if n == 0 || !undecided || head.contains(&b'\n') {
+            return HTTP_ROUTES
+                .iter()
+                .find(|(prefix, _)| head.starts_with(prefix))
+                .map(|&(_, route)| route);
+        }
+        // Peeking again returns right away with the same bytes, so give the
+        // rest of the line a moment to arrive
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// Reads the request line and headers (names lowercased)
+pub(super) async fn read_request_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Option<(String, Vec<(String, String)>)> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.ok()?;
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => return None,
+            Ok(_) if line.trim().is_empty() => break,
+            Ok(_) => {
+                if let Some((name, value)) = line.split_once(':') {
+                    headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+                }
+            }
+        }
+    }
+
+    Some((request_line, headers))
+}
+
+pub(super) fn http_response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    async fn route_of(chunks: &[&'static [u8]]) -> Option<&'static str> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let chunks = chunks.to_vec();
+        tokio::spawn(async move {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            for chunk in chunks {
+                client.write_all(chunk).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        peek_http_route(&stream).await.map(|route| match route {
+            HttpRoute::Events => "events",
+            HttpRoute::Hook => "hook",
+            HttpRoute::Health => "health",
+            HttpRoute::Admin => "admin",
+        })
+    }
+
+    #[tokio::test]
+    async fn routes_a_request_line_split_across_packets() {
+        let route = route_of(&[b"GE", b"T /ev", b"ents?token=x HTTP/1.1\r\n\r\n"]).await;
+        assert_eq!(route, Some("events"));
+        let route = route_of(&[b"POST /ho", b"oks/secret HTTP/1.1\r\n"]).await;
+        assert_eq!(route, Some("hook"));
+    }
+
+    #[tokio::test]
+    async fn websocket_upgrades_are_not_routed() {
+        assert_eq!(route_of(&[b"GET /", b" HTTP/1.1\r\n"]).await, None);
+        assert_eq!(route_of(&[b"GET /chat HTTP/1.1\r\n"]).await, None);
+    }
+}
@@ -0,0 +1,419 @@
+//! The IRC gateway.
+
+use crate::protocol::ServerMessage;
+use crate::server::auth::{resolve_token, spend_reconnect_token};
+use crate::server::bans::ban_message;
+use crate::server::broadcast::broadcast_presence;
+use crate::server::capacity::check_capacity;
+use crate::server::names::{name_collides, normalize_name};
+use crate::server::routing::{add_client, remove_client, route_message};
+use crate::server::{Client, ConnectionInfo, DisconnectReason, Server};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+// A minimal IRC front end: PASS carries the token, the single `#general`
+// channel is the broadcast stream and PRIVMSG to a nick is a DM.
+pub(super) const IRC_SERVER: &str = "push";
+pub(super) const IRC_CHANNEL: &str = "#general";
+
+pub(super) struct IrcLine {
+    pub(super) command: String,
+    pub(super) params: Vec<String>,
+}
+
+pub(super) fn parse_irc_line(line: &str) -> Option<IrcLine> {
+    let mut rest = line.trim_end_matches(['\r', '\n']);
+    if let Some(prefixed) = rest.strip_prefix(':') {
+        rest = prefixed.split_once(' ')?.1;
+    }
+
+    let (head, trailing) = match rest.split_once(" :") {
+        Some((head, trailing)) => (head, Some(trailing)),
+        None => (rest, None),
+    };
+
+    let mut words = head.split_whitespace();
+    let command = words.next()?.to_ascii_uppercase();
+    let mut params: Vec<String> = words.map(str::to_string).collect();
+    if let Some(trailing) = trailing {
+        params.push(trailing.to_string());
+    }
+
+    Some(IrcLine { command, params })
+}
+
+pub(super) async fn irc_send(write: &mut tokio::net::tcp::OwnedWriteHalf, line: &str) -> bool {
+    write
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await
+        .is_ok()
+}
+
+pub(super) async fn run_irc_gateway(listener: TcpListener, server: Arc<Server>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(handle_irc(stream, server.clone()));
+    }
+}
+
+pub(super) async fn handle_irc(stream: TcpStream, server: Arc<Server>) {
+    let peer = stream.peer_addr().ok();
+    let (read_half, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+
+    // --- REGISTRATION ---
+    let mut pass = None;
+    let mut nick = None;
+    let mut user_seen = false;
+    while nick.is_none() || !user_seen {
+        let Ok(Some(line)) = lines.next_line().await else {
+            return;
+        };
+        let Some(msg) = parse_irc_line(&line) else {
+            continue;
+        };
+        match msg.command.as_str() {
+            "PASS" => pass = msg.params.first().cloned(),
+            "NICK" => nick = msg.params.first().cloned(),
+            "USER" => user_seen = true,
+            // No capability negotiation; clients fall back to plain IRC
+            "CAP" => {}
+            "QUIT" => return,
+            other => {
+                let reply = format!(":{} 451 * {} :You have not registered", IRC_SERVER, other);
+                irc_send(&mut write, &reply).await;
+            }
+        }
+    }
+
+    let nick = nick.unwrap_or_default();
+    let Some(user) = pass
+        .as_deref()
+        .and_then(|token| resolve_token(&server, token))
+    else {
+        irc_send(
+            &mut write,
+            &format!(":{} 464 {} :Password incorrect", IRC_SERVER, nick),
+        )
+        .await;
+        irc_send(&mut write, "ERROR :Closing link (invalid token)").await;
+        return;
+    };
+    let username = normalize_name(&server.config, user.username);
+
+    if let Some(message) = ban_message(&server, pass.as_deref().unwrap_or_default(), &username) {
+        irc_send(
+            &mut write,
+            &format!(":{} 465 {} :{}", IRC_SERVER, username, message),
+        )
+        .await;
+        irc_send(&mut write, "ERROR :Closing link (banned)").await;
+        return;
+    }
+
+    if name_collides(&server, &username).await {
+        irc_send(
+            &mut write,
+            &format!(
+                ":{} 433 * {} :Nickname is already in use",
+                IRC_SERVER, username
+            ),
+        )
+        .await;
+        return;
+    }
+
+    if let Some(retry_after_ms) = check_capacity(&server).await {
+        irc_send(
+            &mut write,
+            &format!("ERROR :Server is full, retry in {}ms", retry_after_ms),
+        )
+        .await;
+        return;
+    }
+
+    if user.reconnect && !spend_reconnect_token(&server, pass.as_deref().unwrap_or_default()) {
+        irc_send(
+            &mut write,
+            &format!(":{} 464 {} :Password incorrect", IRC_SERVER, username),
+        )
+        .await;
+        irc_send(&mut write, "ERROR :Closing link (invalid token)").await;
+        return;
+    }
+
+    // The token decides the identity, whatever nick was asked for
+    let welcome = [
+        format!(
+            ":{} 001 {} :Welcome to push, {}",
+            IRC_SERVER, username, username
+        ),
+        format!(
+            ":{} 002 {} :Your host is {}",
+            IRC_SERVER, username, IRC_SERVER
+        ),
+        format!(":{} 004 {} {} 0.1.0 i n", IRC_SERVER, username, IRC_SERVER),
+        format!(":{} 422 {} :MOTD File is missing", IRC_SERVER, username),
+    ];
+    for line in &welcome {
+        if !irc_send(&mut write, line).await {
+            return;
+        }
+    }
+    if nick != username {
+        irc_send(
+            &mut write,
+            &format!(":{}!{}@{} NICK :{}", nick, nick, IRC_SERVER, username),
+        )
+        .await;
+    }
+
+    println!("{} connected (irc)", username);
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (mut client, mut kicked) = Client::new(tx.clone());
+    client.connection = ConnectionInfo {
+        transport: "irc",
+        peer,
+        ..ConnectionInfo::default()
+    };
+    client.tags = user.tags;
+    client.signing_key = user.signing_key;
+    client.resume_after = user.resume_after;
+    client.templates = server.catalog.resolve(None, &server.config.default_lang);
+    add_client(&server, &username, client).await;
+
+    broadcast_presence(&server, &username, true, None).await;
+
+    // --- SESSION ---
+    let mut joined = false;
+    let reason = loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let line = match line {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break DisconnectReason::ClientClosed,
+                    Err(_) => break DisconnectReason::ConnectionLost,
+                };
+                let Some(msg) = parse_irc_line(&line) else { continue };
+
+                let replies = match msg.command.as_str() {
+                    "QUIT" => break DisconnectReason::ClientClosed,
+                    "CAP" => Vec::new(),
+                    "PING" => {
+                        let token = msg.params.first().map(String::as_str).unwrap_or(IRC_SERVER);
+                        vec![format!(":{} PONG {} :{}", IRC_SERVER, IRC_SERVER, token)]
+                    }
+                    "JOIN" | "PART" | "NAMES" => {
+                        irc_channel_command(&server, &username, &msg, &mut joined).await
+                    }
+                    "PRIVMSG" => irc_privmsg(&server, &username, &msg, joined).await,
+                    "PASS" | "USER" => vec![format!(
+                        ":{} 462 {} :You may not reregister",
+                        IRC_SERVER, username
+                    )],
+                    other => vec![format!(
+                        ":{} 421 {} {} :Unknown command",
+                        IRC_SERVER, username, other
+                    )],
+                };
+
+                let mut ok = true;
+                for reply in &replies {
+                    ok &= irc_send(&mut write, reply).await;
+                }
+                if !ok {
+                    break DisconnectReason::WriteFailed;
+                }
+            }
+            kick = &mut kicked => {
+                let (why, reason) = kick.unwrap_or((DisconnectReason::Kicked, String::new()));
+                let reason = match why {
+                    DisconnectReason::Kicked => format!("Kicked: {}", reason),
+                    _ => reason,
+                };
+                irc_send(&mut write, &format!("ERROR :Closing link ({})", reason)).await;
+                break why;
+            }
+            msg = rx.recv() => {
+                let Some(msg) = msg else { break DisconnectReason::ConnectionLost };
+                let mut ok = true;
+                for line in irc_lines_for(&msg, &username, joined) {
+                    ok &= irc_send(&mut write, &line).await;
+                }
+                if !ok {
+                    break DisconnectReason::WriteFailed;
+                }
+            }
+        }
+    };
+
+    println!("{} disconnected ({})", username, reason.as_str());
+    if remove_client(&server, &username, &tx).await {
+        broadcast_presence(&server, &username, false, Some(reason)).await;
+    }
+}
+
+pub(super) async fn irc_channel_command(
+    server: &Server,
+    username: &str,
+    msg: &IrcLine,
+    joined: &mut bool,
+) -> Vec<String> {
+    let channels = msg
+        .params
+        .first()
+        .map(String::as_str)
+        .unwrap_or(IRC_CHANNEL);
+
+    let mut replies = Vec::new();
+    for channel in channels.split(',') {
+        if !channel.eq_ignore_ascii_case(IRC_CHANNEL) {
+            replies.push(format!(
+                ":{} 403 {} {} :No such channel",
+                IRC_SERVER, username, channel
+            ));
+            continue;
+        }
+
+        match msg.command.as_str() {
+            "JOIN" => {
+                *joined = true;
+                replies.push(format!(
+                    ":{}!{}@{} JOIN {}",
+                    username, username, IRC_SERVER, IRC_CHANNEL
+                ));
+                replies.extend(irc_names(server, username).await);
+            }
+            "PART" if !*joined => replies.push(format!(
+                ":{} 442 {} {} :You're not on that channel",
+                IRC_SERVER, username, IRC_CHANNEL
+            )),
+            "PART" => {
+                *joined = false;
+                replies.push(format!(
+                    ":{}!{}@{} PART {}",
+                    username, username, IRC_SERVER, IRC_CHANNEL
+                ));
+            }
+            _ => replies.extend(irc_names(server, username).await),
+        }
+    }
+
+    replies
+}
+
+pub(super) async fn irc_names(server: &Server, username: &str) -> Vec<String> {
+    let mut names: Vec<String> = server.clients.lock().await.keys().cloned().collect();
+    names.sort();
+
+    vec![
+        format!(
+            ":{} 353 {} = {} :{}",
+            IRC_SERVER,
+            username,
+            IRC_CHANNEL,
+            names.join(" ")
+        ),
+        format!(
+            ":{} 366 {} {} :End of /NAMES list",
+            IRC_SERVER, username, IRC_CHANNEL
+        ),
+    ]
+}
+
+pub(super) async fn irc_privmsg(
+    server: &Server,
+    username: &str,
+    msg: &IrcLine,
+    joined: bool,
+) -> Vec<String> {
+    let (Some(target), Some(text)) = (msg.params.first(), msg.params.get(1)) else {
+        return vec![format!(":{} 412 {} :No text to send", IRC_SERVER, username)];
+    };
+
+    let to = if target.starts_with('#') {
+        if !target.eq_ignore_ascii_case(IRC_CHANNEL) {
+            return vec![format!(
+                ":{} 403 {} {} :No such channel",
+                IRC_SERVER, username, target
+            )];
+        }
+        if !joined {
+            return vec![format!(
+                ":{} 404 {} {} :Cannot send to channel",
+                IRC_SERVER, username, target
+            )];
+        }
+        None
+    } else {
+        if !server.clients.lock().await.contains_key(target) {
+            return vec![format!(
+                ":{} 401 {} {} :No such nick/channel",
+                IRC_SERVER, username, target
+            )];
+        }
+        Some(target.clone())
+    };
+
+    // A rejection has already been sent to the sender
+    let _ = route_message(
+        server,
+        ServerMessage {
+            from: username.to_string(),
+            to,
+            content: text.clone(),
+            content_type: None,
+            scheduled: false,
+            suppress_notification: false,
+            conversation_id: None,
+            members: None,
+            translated: None,
+            verified: false,
+            client_id: None,
+        },
+    )
+    .await;
+
+    Vec::new()
+}
+
+// Translates a routed JSON message into IRC lines for this session
+pub(super) fn irc_lines_for(json: &str, username: &str, joined: bool) -> Vec<String> {
+    let Ok(msg) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+    if msg["type"] == "motd" {
+        let text = msg["text"].as_str().unwrap_or_default();
+        return text
+            .lines()
+            .map(|line| format!(":{} NOTICE {} :{}", IRC_SERVER, username, line))
+            .collect();
+    }
+    let notice = match msg["type"].as_str() {
+        Some("error") => msg["message"].as_str(),
+        Some("system") => msg["text"].as_str(),
+        _ => None,
+    };
+    if let Some(text) = notice {
+        return vec![format!(":{} NOTICE {} :{}", IRC_SERVER, username, text)];
+    }
+
+    let from = msg["from"].as_str().unwrap_or_default();
+    let content = msg["content"].as_str().unwrap_or_default();
+
+    let prefix = if msg["to"].is_string() {
+        format!(":{}!{}@{} PRIVMSG {}", from, from, IRC_SERVER, username)
+    } else if joined {
+        format!(":{}!{}@{} PRIVMSG {}", from, from, IRC_SERVER, IRC_CHANNEL)
+    } else {
+        return Vec::new();
+    };
+
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("{} :{}", prefix, line))
+        .collect()
+}
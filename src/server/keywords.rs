@@ -0,0 +1,247 @@
+//! Keyword highlights.
+
+use crate::protocol::{ServerFrame, ServerMessage, Severity};
+use crate::server::{Client, Server, write_atomically};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub(super) const MAX_KEYWORDS: usize = 20;
+pub(super) const MAX_KEYWORD_LEN: usize = 50;
+
+#[derive(Default)]
+pub(super) struct Keywords {
+    // User -> lowercased words, sorted
+    pub(super) subscriptions: HashMap<String, Vec<String>>,
+    // Over the words of connected subscribers only; dropped when a
+    // subscription changes or a subscriber comes or goes, and rebuilt by
+    // the next message that needs it
+    pub(super) matcher: Option<KeywordMatcher>,
+    pub(super) file: Option<String>,
+}
+
+impl Keywords {
+    pub(super) fn load(path: &str) -> anyhow::Result<Keywords> {
+        let subscriptions = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("parsing keywords {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => anyhow::bail!("reading keywords {}: {}", path, e),
+        };
+        Ok(Keywords {
+            subscriptions,
+            matcher: None,
+            file: Some(path.to_string()),
+        })
+    }
+
+    pub(super) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_atomically(path, &self.subscriptions) {
+            eprintln!("saving keywords to {} failed: {}", path, e);
+        }
+    }
+
+    pub(super) fn presence_changed(&mut self, username: &str) {
+        if self.subscriptions.contains_key(username) {
+            self.matcher = None;
+        }
+    }
+
+    // Recipient -> the words of theirs the content contains
+    pub(super) fn matches(
+        &mut self,
+        clients: &HashMap<String, Client>,
+        from: &str,
+        content: &str,
+    ) -> HashMap<String, Vec<String>> {
+        if self.subscriptions.is_empty() {
+            return HashMap::new();
+        }
+        let subscriptions = &self.subscriptions;
+        let matcher = self.matcher.get_or_insert_with(|| {
+            KeywordMatcher::new(
+                subscriptions
+                    .iter()
+                    .filter(|(user, _)| clients.contains_key(*user)),
+            )
+        });
+
+        let mut matches: HashMap<String, Vec<String>> = HashMap::new();
+        for index in matcher.find(&content.to_lowercase()) {
+            // A subscriber who left since the build isn't in `clients`
+            for owner in &matcher.owners[index] {
+                if owner != from && clients.contains_key(owner) {
+                    matches
+                        .entry(owner.clone())
+                        .or_default()
+                        .push(matcher.words[index].clone());
+                }
+            }
+        }
+        for words in matches.values_mut() {
+            words.sort();
+        }
+        matches
+    }
+}
+
+// Aho-Corasick over the UTF-8 bytes of the words, so a message is scanned
+// once however many subscribers there are
+pub(super) struct KeywordMatcher {
+    // Trie edges; node 0 is the root
+    pub(super) next: Vec<HashMap<u8, usize>>,
+    pub(super) fail: Vec<usize>,
+    // Indexes of the words ending at each node, including through its
+    // failure links
+    pub(super) output: Vec<Vec<usize>>,
+    pub(super) words: Vec<String>,
+    // Word index -> users subscribed to it
+    pub(super) owners: Vec<Vec<String>>,
+}
+
+impl KeywordMatcher {
+    pub(super) fn new<'a>(
+        subscriptions: impl Iterator<Item = (&'a String, &'a Vec<String>)>,
+    ) -> Self {
+        let mut by_word: HashMap<&str, Vec<String>> = HashMap::new();
+        for (user, words) in subscriptions {
+            for word in words {
+                by_word.entry(word).or_default().push(user.clone());
+            }
+        }
+
+        let mut matcher = KeywordMatcher {
+            next: vec![HashMap::new()],
+            fail: Vec::new(),
+            output: vec![Vec::new()],
+            words: Vec::new(),
+            owners: Vec::new(),
+        };
+        for (word, owners) in by_word {
+            let mut node = 0;
+            for &byte in word.as_bytes() {
+                node = match matcher.next[node].get(&byte) {
+                    Some(&child) => child,
+                    None => {
+                        matcher.next.push(HashMap::new());
+                        matcher.output.push(Vec::new());
+                        let child = matcher.next.len() - 1;
+                        matcher.next[node].insert(byte, child);
+                        child
+                    }
+                };
+            }
+            matcher.output[node].push(matcher.words.len());
+            matcher.words.push(word.to_string());
+            matcher.owners.push(owners);
+        }
+
+        // Breadth first, so a node's failure target is finished before it
+        matcher.fail = vec![0; matcher.next.len()];
+        let mut queue: VecDeque<usize> = matcher.next[0].values().copied().collect();
+        while let Some(node) = queue.pop_front() {
+            let edges: Vec<(u8, usize)> = matcher.next[node]
+                .iter()
+                .map(|(&byte, &child)| (byte, child))
+                .collect();
+            for (byte, child) in edges {
+                let mut fallback = matcher.fail[node];
+                while fallback != 0 && !matcher.next[fallback].contains_key(&byte) {
+                    fallback = matcher.fail[fallback];
+                }
+                let target = matcher.next[fallback].get(&byte).copied().unwrap_or(0);
+                matcher.fail[child] = target;
+                let inherited = matcher.output[target].clone();
+                matcher.output[child].extend(inherited);
+                queue.push_back(child);
+            }
+        }
+        matcher
+    }
+
+    // Indexes of the words found as whole words in `text`
+    pub(super) fn find(&self, text: &str) -> HashSet<usize> {
+        let mut found = HashSet::new();
+        let mut node = 0;
+        for (i, &byte) in text.as_bytes().iter().enumerate() {
+            while node != 0 && !self.next[node].contains_key(&byte) {
+                node = self.fail[node];
+            }
+            node = self.next[node].get(&byte).copied().unwrap_or(0);
+            for &index in &self.output[node] {
+                let end = i + 1;
+                let start = end - self.words[index].len();
+                if is_word_boundary(text, start, end) {
+                    found.insert(index);
+                }
+            }
+        }
+        found
+    }
+}
+
+// Words are whole UTF-8 sequences, so `start` and `end` are char boundaries
+pub(super) fn is_word_boundary(text: &str, start: usize, end: usize) -> bool {
+    let word_char = |c: char| c.is_alphanumeric() || c == '_';
+    !text[..start].chars().next_back().is_some_and(word_char)
+        && !text[end..].chars().next().is_some_and(word_char)
+}
+
+pub(super) fn subscribe_keywords(
+    server: &Server,
+    username: &str,
+    words: Vec<String>,
+) -> Result<ServerFrame, ServerFrame> {
+    let mut words: Vec<String> = words
+        .iter()
+        .map(|word| word.trim().to_lowercase())
+        .collect();
+    if words
+        .iter()
+        .any(|word| word.is_empty() || word.chars().count() > MAX_KEYWORD_LEN)
+    {
+        return Err(ServerFrame::Error {
+            code: "invalid_keyword".into(),
+            message: format!("Keywords must be 1 to {} characters", MAX_KEYWORD_LEN),
+        });
+    }
+    words.sort();
+    words.dedup();
+    if words.len() > MAX_KEYWORDS {
+        return Err(ServerFrame::Error {
+            code: "too_many_keywords".into(),
+            message: format!("You can have at most {} keywords", MAX_KEYWORDS),
+        });
+    }
+
+    let mut keywords = server.keywords.lock().unwrap();
+    if words.is_empty() {
+        keywords.subscriptions.remove(username);
+    } else {
+        keywords
+            .subscriptions
+            .insert(username.to_string(), words.clone());
+    }
+    keywords.matcher = None;
+    keywords.save();
+
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("Watching {} keywords", words.len()),
+        context: Some(serde_json::json!({ "event": "keywords", "words": words })),
+    })
+}
+
+pub(super) fn keyword_match_frame(msg: &ServerMessage, words: &[String]) -> ServerFrame {
+    ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("{} mentioned {}", msg.from, words.join(", ")),
+        context: Some(serde_json::json!({
+            "event": "keyword_match",
+            "from": msg.from,
+            "conversation_id": msg.conversation_id,
+            "words": words,
+        })),
+    }
+}
@@ -0,0 +1,177 @@
+//! Translated server notices.
+
+use crate::protocol::ServerFrame;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Translations of server notices and error messages, one `<lang>.txt` per
+// language with `key = template` lines. Notices are keyed `system.<event>`
+// and fill `{placeholders}` from their context; errors are keyed
+// `error.<code>`. The English text built into the server is the fallback,
+// and codes never change.
+pub(super) type Templates = HashMap<String, String>;
+
+#[derive(Default)]
+pub(super) struct Catalog {
+    pub(super) languages: HashMap<String, Arc<Templates>>,
+}
+
+impl Catalog {
+    pub(super) fn load(dir: &str) -> anyhow::Result<Catalog> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| anyhow::anyhow!("reading locales dir {}: {}", dir, e))?;
+        let mut languages = HashMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "txt") {
+                continue;
+            }
+            let Some(lang) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("reading {}: {}", path.display(), e))?;
+            languages.insert(lang.to_lowercase(), Arc::new(Catalog::parse(&text)));
+        }
+        println!("Loaded translations for {} languages", languages.len());
+        Ok(Catalog { languages })
+    }
+
+    pub(super) fn parse(text: &str) -> Templates {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, template)| (key.trim().to_string(), template.trim().to_string()))
+            .collect()
+    }
+
+    // Requested language, then the server default, then English
+    pub(super) fn resolve(&self, requested: Option<&str>, default: &str) -> Option<Arc<Templates>> {
+        requested
+            .and_then(|lang| self.languages.get(&lang.to_lowercase()))
+            .or_else(|| self.languages.get(&default.to_lowercase()))
+            .cloned()
+    }
+}
+
+// `None` keeps the original frame
+pub(super) fn localize(templates: &Templates, frame: &ServerFrame) -> Option<ServerFrame> {
+    match frame {
+        ServerFrame::System {
+            severity,
+            context: Some(context),
+            ..
+        } => {
+            let template = templates.get(&format!("system.{}", context["event"].as_str()?))?;
+            Some(ServerFrame::System {
+                severity: *severity,
+                text: render_template(template, context)?,
+                context: Some(context.clone()),
+            })
+        }
+        ServerFrame::Error { code, .. } => Some(ServerFrame::Error {
+            code: code.clone(),
+            message: templates.get(&format!("error.{}", code))?.clone(),
+        }),
+        _ => None,
+    }
+}
+
+// Fills `{name}` from the context's string and number fields. A placeholder
+// with nothing to fill it, or a stray brace, fails the whole template so the
+// English text is used instead of a half-rendered one.
+pub(super) fn render_template(template: &str, params: &serde_json::Value) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}')? + start;
+        let value = match &params[&rest[start + 1..end]] {
+            serde_json::Value::String(text) => text.clone(),
+            serde_json::Value::Number(number) => number.to_string(),
+            _ => return None,
+        };
+        out.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    if rest.contains('}') {
+        return None;
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    #[test]
+    fn templates_fill_placeholders_or_fail_whole() {
+        let params = json!({ "user": "bob", "seconds": 30, "extra": null });
+        assert_eq!(
+            render_template("{user} est muet pour {seconds}s", &params).as_deref(),
+            Some("bob est muet pour 30s")
+        );
+        assert_eq!(render_template("rien", &params).as_deref(), Some("rien"));
+        assert_eq!(render_template("{missing} est parti", &params), None);
+        assert_eq!(render_template("{extra}", &params), None);
+        assert_eq!(render_template("{user", &params), None);
+        assert_eq!(render_template("user}", &params), None);
+    }
+
+    #[test]
+    fn languages_fall_back_to_the_default_then_english() {
+        let catalog = Catalog {
+            languages: HashMap::from([
+                ("fr".to_string(), Arc::new(Catalog::parse("a = fr"))),
+                ("de".to_string(), Arc::new(Catalog::parse("# c\na = de\n"))),
+            ]),
+        };
+        let pick = |requested, default| {
+            catalog
+                .resolve(requested, default)
+                .map(|templates| templates["a"].clone())
+        };
+        assert_eq!(pick(Some("FR"), "de").as_deref(), Some("fr"));
+        assert_eq!(pick(Some("es"), "de").as_deref(), Some("de"));
+        assert_eq!(pick(None, "de").as_deref(), Some("de"));
+        assert_eq!(pick(Some("es"), "en"), None);
+    }
+
+    #[tokio::test]
+    async fn clients_get_notices_and_errors_in_their_language() {
+        let path = temp_file("fr.txt");
+        std::fs::write(
+            &path,
+            "system.join = {user} a rejoint le chat\nerror.forbidden = Réservé aux admins\n",
+        )
+        .unwrap();
+        let mut config = config();
+        config.locales_dir = Some(
+            std::path::Path::new(&path)
+                .parent()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned(),
+        );
+        let test = TestServer::start(config).await;
+        let mut bob = test
+            .login_with(json!({ "token": "token-bob", "lang": "fr" }))
+            .await;
+
+        let _charlie = test.login("token-charlie").await;
+        let join = bob
+            .expect(|frame| {
+                frame["context"]["event"] == "join" && frame["context"]["user"] == "charlie"
+            })
+            .await;
+        assert_eq!(join["text"], "charlie a rejoint le chat");
+
+        bob.send(json!({ "type": "kick", "user": "charlie" })).await;
+        let error = bob.expect_error("forbidden").await;
+        assert_eq!(error["message"], "Réservé aux admins");
+    }
+}
@@ -0,0 +1,71 @@
+//! The metrics stream for admins.
+
+use crate::protocol::ServerFrame;
+use crate::server::Server;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+// Pushes a snapshot to subscribed admins every `metrics_interval`
+pub(super) async fn run_metrics(server: Arc<Server>) {
+    let mut ticker = tokio::time::interval(server.config.metrics_interval);
+    let mut last_total = 0;
+    let mut last_tick = Instant::now();
+
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+        let total = server.msgs_total.load(Ordering::Relaxed);
+        let elapsed = now
+            .duration_since(last_tick)
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let msgs_per_sec = (total - last_total) as f64 / elapsed;
+        last_total = total;
+        last_tick = now;
+
+        let muted = server.mutes.lock().unwrap().active();
+        let clients_guard = server.clients.lock().await;
+        let frame = ServerFrame::Metrics {
+            connected: clients_guard.len(),
+            msgs_per_sec,
+            msgs_total: total,
+            muted,
+            filter_hits: server.filter_hits.lock().unwrap().clone(),
+            duplicates_suppressed: server.duplicates_suppressed.load(Ordering::Relaxed),
+            audit_write_failures: server.audit_write_failures.load(Ordering::Relaxed),
+            wrong_path_upgrades: server.wrong_path_upgrades.load(Ordering::Relaxed),
+        };
+        for client in clients_guard.values().filter(|c| c.metrics_subscribed) {
+            client.send_frame(&frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn admins_get_periodic_metrics_and_others_are_denied() {
+        let mut config = config();
+        config.metrics_interval = Duration::from_secs(1);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        bob.send(json!({ "type": "subscribe_metrics" })).await;
+        bob.expect_error("forbidden").await;
+
+        alice.send(json!({ "type": "subscribe_metrics" })).await;
+        let first = alice.expect_type("metrics").await;
+        assert_eq!(first["connected"], 2);
+        bob.send(json!({ "to": "alice", "content": "hi" })).await;
+        let second = alice
+            .expect(|frame| frame["type"] == "metrics" && frame["msgs_total"] == 1)
+            .await;
+        assert!(second["msgs_per_sec"].as_f64().unwrap() > 0.0);
+    }
+}
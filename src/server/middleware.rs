@@ -0,0 +1,19 @@
+//! Hooks every chat message passes through before it's fanned out.
+
+use crate::protocol::{ServerFrame, ServerMessage};
+use crate::server::Server;
+
+pub(super) enum Verdict {
+    // Deliver, possibly rewritten
+    Pass,
+    // Deliver, and send this notice to the online admins
+    Flag(ServerFrame),
+    // Deliver nothing; the sender gets this error code and message
+    Reject(&'static str, String),
+}
+
+// Runs in order, after the mute, rate and duplicate checks; the first
+// rejection stops the chain
+pub(super) trait Middleware: Send + Sync {
+    fn on_message(&self, server: &Server, msg: &mut ServerMessage) -> Verdict;
+}
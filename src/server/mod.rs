@@ -0,0 +1,502 @@
+//! The chat server: shared state, startup and the accept loop. Each
+//! feature lives in its own submodule.
+
+mod admin_http;
+mod audit;
+mod auth;
+mod bans;
+mod broadcast;
+mod capacity;
+mod config;
+mod console;
+mod contacts;
+mod content;
+mod deactivation;
+mod dm_history;
+mod groups;
+mod heartbeat;
+mod history;
+mod http;
+#[cfg(feature = "irc")]
+mod irc;
+mod keywords;
+mod locale;
+mod metrics;
+mod middleware;
+mod moderation;
+mod motd;
+mod mutes;
+mod names;
+mod pattern;
+mod presence_log;
+mod probes;
+mod quiet_hours;
+mod rate_limit;
+mod routing;
+mod schedule;
+mod sessions;
+mod shutdown;
+mod signing;
+mod sse;
+mod tcp;
+#[cfg(test)]
+mod testing;
+mod tokens;
+mod translation;
+mod versions;
+mod webhooks;
+mod word_filter;
+mod ws;
+
+use audit::open_command_audit;
+use auth::agree_capabilities;
+use bans::{Bans, ShadowBans};
+use broadcast::PresenceCoalescer;
+use capacity::OverloadBackoff;
+use config::Config;
+use contacts::Contacts;
+use heartbeat::RttStats;
+use history::MessageLog;
+#[cfg(feature = "irc")]
+use irc::run_irc_gateway;
+use keywords::Keywords;
+use locale::{Catalog, Templates, localize};
+use metrics::run_metrics;
+use middleware::Middleware;
+use moderation::StickyAnnouncement;
+use motd::load_motd;
+use mutes::Mutes;
+use presence_log::PresenceLog;
+use probes::run_prober;
+use quiet_hours::QuietHours;
+use rate_limit::{RateState, run_circuit_breaker};
+use schedule::{Schedule, run_scheduler};
+use sessions::{SessionRegistry, run_token_refresh};
+use shutdown::{shut_down, shutdown_signal};
+use tcp::run_tcp_listener;
+use tokens::TokenStore;
+use translation::{CommandTranslator, Translator};
+use versions::ClientVersions;
+use word_filter::{WordFilter, WordFilterMiddleware};
+use ws::handle_connection;
+
+use crate::protocol::{AuditEntry, ServerFrame, ServerMessage};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::{Mutex, mpsc, oneshot};
+
+struct Client {
+    tx: mpsc::UnboundedSender<String>,
+    // Users with an open DM thread with this client, either direction
+    dm_partners: HashSet<String>,
+    // Fires with the reason when the server closes this connection
+    kick: Option<oneshot::Sender<(DisconnectReason, String)>>,
+    metrics_subscribed: bool,
+    // Optional events agreed on at auth
+    capabilities: HashSet<String>,
+    // Translations for this client's language; `None` means English
+    templates: Option<Arc<Templates>>,
+    // Metadata from the token store, e.g. `tier:pro`
+    tags: Vec<String>,
+    // Language asked for at auth, lowercased
+    lang: Option<String>,
+    // Key this user signs messages with, if they opted in
+    signing_key: Option<String>,
+    // When an unanswered presence probe went out
+    probed_at: Option<Instant>,
+    // Only ever shown to admins
+    connection: ConnectionInfo,
+    // Last message seq a resumed session saw; what came after is replayed
+    resume_after: Option<u64>,
+    // Ping round trips; `None` on transports without pings
+    rtt: Option<Arc<std::sync::Mutex<RttStats>>>,
+    connected_at: Instant,
+}
+
+// Where and how a client connected
+#[derive(Default)]
+struct ConnectionInfo {
+    // `ws`, `tcp`, `sse` or `irc`
+    transport: &'static str,
+    peer: Option<std::net::SocketAddr>,
+    // From the WebSocket or SSE request
+    user_agent: Option<String>,
+    origin: Option<String>,
+}
+
+// Why a connection ended; logged and put in the `leave` event
+#[derive(Clone, Copy, Debug)]
+enum DisconnectReason {
+    // Close frame, EOF or IRC `QUIT`
+    ClientClosed,
+    // The read side failed
+    ConnectionLost,
+    // Invalid UTF-8 with PUSH_STRICT_UTF8 on
+    ProtocolError,
+    // By an admin, a ban or a deactivation
+    Kicked,
+    // No answer to a presence probe
+    IdleTimeout,
+    // Flooding with PUSH_FLOOD_DISCONNECT on
+    RateLimited,
+    // Ctrl-C or SIGTERM
+    ServerShutdown,
+    WriteFailed,
+}
+
+impl DisconnectReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            DisconnectReason::ClientClosed => "client_closed",
+            DisconnectReason::ConnectionLost => "connection_lost",
+            DisconnectReason::ProtocolError => "protocol_error",
+            DisconnectReason::Kicked => "kicked",
+            DisconnectReason::IdleTimeout => "idle_timeout",
+            DisconnectReason::RateLimited => "rate_limited",
+            DisconnectReason::ServerShutdown => "server_shutdown",
+            DisconnectReason::WriteFailed => "write_failed",
+        }
+    }
+}
+
+impl Client {
+    // The receiver also resolves (with an error) once the entry is dropped
+    fn new(
+        tx: mpsc::UnboundedSender<String>,
+    ) -> (Self, oneshot::Receiver<(DisconnectReason, String)>) {
+        let (kick, kicked) = oneshot::channel();
+        let client = Client {
+            tx,
+            dm_partners: HashSet::new(),
+            kick: Some(kick),
+            metrics_subscribed: false,
+            capabilities: agree_capabilities(None),
+            templates: None,
+            tags: Vec::new(),
+            lang: None,
+            signing_key: None,
+            probed_at: None,
+            connection: ConnectionInfo::default(),
+            resume_after: None,
+            rtt: None,
+            connected_at: Instant::now(),
+        };
+        (client, kicked)
+    }
+
+    fn send_frame(&self, frame: &ServerFrame) {
+        let localized = self
+            .templates
+            .as_ref()
+            .and_then(|templates| localize(templates, frame));
+        let frame = localized.as_ref().unwrap_or(frame);
+        let _ = self.tx.send(serde_json::to_string(frame).unwrap());
+    }
+}
+
+struct Server {
+    config: Config,
+    clients: Mutex<HashMap<String, Client>>,
+    overload: std::sync::Mutex<OverloadBackoff>,
+    // Keyed by the two participants in sorted order
+    dm_history: std::sync::Mutex<HashMap<(String, String), VecDeque<ServerMessage>>>,
+    // Username -> when the mute expires; kept across reconnects
+    mutes: std::sync::Mutex<Mutes>,
+    shadow_bans: std::sync::Mutex<ShadowBans>,
+    shadow_mutes: std::sync::Mutex<HashSet<String>>,
+    announcement: std::sync::Mutex<Option<StickyAnnouncement>>,
+    // Per-username send history for rate limiting and flood escalation
+    rate: std::sync::Mutex<HashMap<String, RateState>>,
+    sessions: std::sync::Mutex<SessionRegistry>,
+    history: std::sync::Mutex<MessageLog>,
+    // Chat messages accepted for routing since startup
+    msgs_total: AtomicU64,
+    duplicates_suppressed: AtomicU64,
+    wrong_path_upgrades: AtomicU64,
+    // Messages seen by the rate limiter since the breaker last looked
+    breaker_count: AtomicU64,
+    breaker_tripped: AtomicBool,
+    // Size of `clients`, readable without its lock
+    connected: AtomicUsize,
+    started_at: Instant,
+    tokens: std::sync::RwLock<TokenStore>,
+    word_filter: std::sync::RwLock<WordFilter>,
+    middleware: Vec<Box<dyn Middleware>>,
+    client_versions: std::sync::RwLock<ClientVersions>,
+    catalog: Catalog,
+    translator: Option<Box<dyn Translator>>,
+    command_audit: Option<std::sync::Mutex<std::fs::File>>,
+    // Every audit entry since startup, append-only
+    audit_log: std::sync::Mutex<Vec<AuditEntry>>,
+    audit_write_failures: AtomicU64,
+    presence_log: std::sync::Mutex<PresenceLog>,
+    presence_coalescer: std::sync::Mutex<PresenceCoalescer>,
+    motd: std::sync::Mutex<Option<String>>,
+    schedule: std::sync::Mutex<Schedule>,
+    // Kept per user, not per connection, so reconnecting doesn't reset them
+    quiet_hours: std::sync::Mutex<HashMap<String, QuietHours>>,
+    // Group conversation id -> its fixed, sorted members
+    conversations: std::sync::Mutex<HashMap<String, Vec<String>>>,
+    contacts: std::sync::Mutex<Contacts>,
+    keywords: std::sync::Mutex<Keywords>,
+    bans: std::sync::Mutex<Bans>,
+    // Admin console sessions; not part of the roster
+    consoles: std::sync::Mutex<Vec<mpsc::UnboundedSender<String>>>,
+    // Rule word -> matches since startup; survives filter reloads
+    filter_hits: std::sync::Mutex<HashMap<String, u64>>,
+}
+
+impl Server {
+    // Loads every file the config points at
+    fn new(config: Config) -> anyhow::Result<Server> {
+        let tokens = match &config.tokens_file {
+            Some(path) => TokenStore::load(path)?,
+            None => TokenStore::default(),
+        };
+        let word_filter = match &config.word_filter {
+            Some(path) => WordFilter::load(path)?,
+            None => WordFilter::default(),
+        };
+        let client_versions = match &config.client_versions {
+            Some(path) => ClientVersions::load(path)?,
+            None => ClientVersions::default(),
+        };
+        let presence_log = match &config.presence_log {
+            Some(path) => PresenceLog::open(path)?,
+            None => PresenceLog::default(),
+        };
+        let motd = load_motd(&config)?;
+        let schedule = match &config.schedule_file {
+            Some(path) => Schedule::load(path)?,
+            None => Schedule::default(),
+        };
+        let contacts = match &config.contacts_file {
+            Some(path) => Contacts::load(path)?,
+            None => Contacts::default(),
+        };
+        let sessions = match &config.sessions_file {
+            Some(path) => SessionRegistry::load(path)?,
+            None => SessionRegistry::default(),
+        };
+        let history = match &config.history_file {
+            Some(path) => MessageLog::open(path, config.history_size)?,
+            None => MessageLog::new(config.history_size),
+        };
+        let bans = match &config.bans_file {
+            Some(path) => Bans::load(path)?,
+            None => Bans::default(),
+        };
+        let mutes = match &config.mutes_file {
+            Some(path) => Mutes::load(path)?,
+            None => Mutes::default(),
+        };
+        let shadow_bans = match &config.shadow_bans_file {
+            Some(path) => ShadowBans::load(path)?,
+            None => ShadowBans::default(),
+        };
+        let keywords = match &config.keywords_file {
+            Some(path) => Keywords::load(path)?,
+            None => Keywords::default(),
+        };
+        let translator = config.translate_cmd.clone().map(|program| {
+            Box::new(CommandTranslator {
+                program,
+                timeout: config.translate_timeout,
+            }) as Box<dyn Translator>
+        });
+        let catalog = match &config.locales_dir {
+            Some(dir) => Catalog::load(dir)?,
+            None => Catalog::default(),
+        };
+        let command_audit = match &config.command_audit {
+            Some(path) => Some(std::sync::Mutex::new(open_command_audit(path)?)),
+            None => None,
+        };
+        Ok(Server {
+            config,
+            clients: Mutex::new(HashMap::new()),
+            overload: std::sync::Mutex::new(OverloadBackoff::new()),
+            dm_history: std::sync::Mutex::new(HashMap::new()),
+            mutes: std::sync::Mutex::new(mutes),
+            shadow_bans: std::sync::Mutex::new(shadow_bans),
+            shadow_mutes: std::sync::Mutex::new(HashSet::new()),
+            announcement: std::sync::Mutex::new(None),
+            rate: std::sync::Mutex::new(HashMap::new()),
+            sessions: std::sync::Mutex::new(sessions),
+            history: std::sync::Mutex::new(history),
+            msgs_total: AtomicU64::new(0),
+            duplicates_suppressed: AtomicU64::new(0),
+            wrong_path_upgrades: AtomicU64::new(0),
+            breaker_count: AtomicU64::new(0),
+            breaker_tripped: AtomicBool::new(false),
+            connected: AtomicUsize::new(0),
+            started_at: Instant::now(),
+            tokens: std::sync::RwLock::new(tokens),
+            word_filter: std::sync::RwLock::new(word_filter),
+            middleware: vec![Box::new(WordFilterMiddleware)],
+            client_versions: std::sync::RwLock::new(client_versions),
+            catalog,
+            translator,
+            command_audit,
+            audit_log: std::sync::Mutex::new(Vec::new()),
+            audit_write_failures: AtomicU64::new(0),
+            presence_log: std::sync::Mutex::new(presence_log),
+            presence_coalescer: std::sync::Mutex::new(PresenceCoalescer::default()),
+            motd: std::sync::Mutex::new(motd),
+            schedule: std::sync::Mutex::new(schedule),
+            quiet_hours: std::sync::Mutex::new(HashMap::new()),
+            conversations: std::sync::Mutex::new(HashMap::new()),
+            contacts: std::sync::Mutex::new(contacts),
+            keywords: std::sync::Mutex::new(keywords),
+            bans: std::sync::Mutex::new(bans),
+            consoles: std::sync::Mutex::new(Vec::new()),
+            filter_hits: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+/// Runs the server with the configuration from the environment.
+pub async fn run() -> anyhow::Result<()> {
+    let server = Arc::new(Server::new(Config::from_env())?);
+    let listener = TcpListener::bind(&server.config.addr).await?;
+
+    println!("Chat server running on ws://{}", server.config.addr);
+    println!(
+        "SSE fallback on http://{}/events?token=...",
+        server.config.addr
+    );
+
+    tokio::select! {
+        result = serve(listener, server.clone()) => result,
+        _ = shutdown_signal() => {
+            shut_down(&server).await;
+            Ok(())
+        }
+    }
+}
+
+// Background tasks, the optional listeners and the accept loop for
+// WebSocket and plain HTTP connections on `listener`
+async fn serve(listener: TcpListener, server: Arc<Server>) -> anyhow::Result<()> {
+    tokio::spawn(run_metrics(server.clone()));
+    tokio::spawn(run_scheduler(server.clone()));
+    if !server.config.probe_interval.is_zero() {
+        tokio::spawn(run_prober(server.clone()));
+    }
+    if server.config.breaker_rate > 0 {
+        tokio::spawn(run_circuit_breaker(server.clone()));
+    }
+    if !server.config.token_refresh.is_zero() {
+        tokio::spawn(run_token_refresh(server.clone()));
+    }
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(server.clone()));
+
+    if let Some(addr) = &server.config.tcp_addr {
+        let tcp_listener = TcpListener::bind(addr).await?;
+        println!("Raw TCP line protocol on {}", addr);
+        tokio::spawn(run_tcp_listener(tcp_listener, server.clone()));
+    }
+
+    #[cfg(feature = "irc")]
+    {
+        let irc_listener = TcpListener::bind(&server.config.irc_addr).await?;
+        println!("IRC gateway on {}", server.config.irc_addr);
+        tokio::spawn(run_irc_gateway(irc_listener, server.clone()));
+    }
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(handle_connection(stream, server.clone()));
+    }
+
+    Ok(())
+}
+
+// Written to a temp file and renamed, so a crash mid-save leaves either the
+// old contents or the new ones
+fn write_atomically(path: &str, value: &impl Serialize) -> std::io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, serde_json::to_string(value)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(server: Arc<Server>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        return;
+    };
+    while hangups.recv().await.is_some() {
+        if let Some(path) = &server.config.tokens_file {
+            match TokenStore::load(path) {
+                Ok(tokens) => {
+                    println!("Reloaded tokens ({} tokens)", tokens.tokens.len());
+                    *server.tokens.write().unwrap() = tokens;
+                }
+                Err(e) => eprintln!("{}; keeping the old tokens", e),
+            }
+        }
+        if let Some(path) = &server.config.word_filter {
+            match WordFilter::load(path) {
+                Ok(filter) => {
+                    println!("Reloaded word filter ({} rules)", filter.rules.len());
+                    *server.word_filter.write().unwrap() = filter;
+                }
+                Err(e) => eprintln!("{}; keeping the old rules", e),
+            }
+        }
+        if let Some(path) = &server.config.client_versions {
+            match ClientVersions::load(path) {
+                Ok(versions) => {
+                    println!(
+                        "Reloaded client versions ({} clients)",
+                        versions.minimums.len()
+                    );
+                    *server.client_versions.write().unwrap() = versions;
+                }
+                Err(e) => eprintln!("{}; keeping the old versions", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomically_replaces_the_file_and_leaves_no_temp() {
+        let dir = std::env::temp_dir().join(format!("push-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("list.json").to_string_lossy().into_owned();
+        std::fs::write(&path, "old").unwrap();
+
+        write_atomically(&path, &vec!["alice", "bob"]).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            r#"["alice","bob"]"#
+        );
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,685 @@
+//! Admin moderation: kicks, mutes, shadow bans and announcements.
+
+use crate::protocol::{ServerFrame, Severity};
+use crate::server::bans::ban;
+use crate::server::broadcast::broadcast_system;
+use crate::server::routing::check_line_limits;
+use crate::server::{Client, DisconnectReason, Server, unix_now};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+pub(super) fn require_admin(server: &Server, username: &str) -> Result<(), ServerFrame> {
+    if server.config.admins.contains(username) {
+        Ok(())
+    } else {
+        Err(ServerFrame::Error {
+            code: "forbidden".into(),
+            message: "Only admins can do that".into(),
+        })
+    }
+}
+
+// Closes the target's connection (close code 1008 on WebSockets). The
+// connection then leaves like any other, with `kicked` as the reason. They
+// may reconnect unless they're also banned.
+pub(super) async fn kick_client(
+    server: &Server,
+    admin: &str,
+    user: &str,
+    reason: Option<String>,
+) -> Result<(), ServerFrame> {
+    require_admin(server, admin)?;
+
+    // Already being kicked counts as offline
+    let kick = server
+        .clients
+        .lock()
+        .await
+        .get_mut(user)
+        .and_then(|client| client.kick.take());
+    let Some(kick) = kick else {
+        return Err(ServerFrame::Error {
+            code: "no_such_user".into(),
+            message: format!("{} is not online", user),
+        });
+    };
+
+    let reason = reason.unwrap_or_default();
+    let _ = kick.send((DisconnectReason::Kicked, reason.clone()));
+    println!("{} kicked {} ({})", admin, user, reason);
+
+    let text = if server.config.kick_reason_public && !reason.is_empty() {
+        format!("{} was kicked by {}: {}", user, admin, reason)
+    } else {
+        format!("{} was kicked by {}", user, admin)
+    };
+    broadcast_system(
+        server,
+        Severity::Warning,
+        &text,
+        Some(serde_json::json!({ "event": "kick", "user": user, "by": admin })),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub(super) const MAX_BULK_KICK: usize = 50;
+pub(super) const MAX_ANNOUNCEMENT_CHARS: usize = 2000;
+
+// The last announcement, while clients connecting later still get it
+pub(super) struct StickyAnnouncement {
+    pub(super) frame: String,
+    pub(super) until: Instant,
+    // Users who already got it, so reconnecting doesn't repeat it
+    pub(super) seen: HashSet<String>,
+}
+
+// Other admins are never kicked in bulk, so a slip during a raid can't
+// take out the moderators
+pub(super) async fn kick_many(
+    server: &Server,
+    admin: &str,
+    users: Vec<String>,
+    reason: Option<String>,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+    if users.len() > MAX_BULK_KICK {
+        return Err(ServerFrame::Error {
+            code: "too_many_users".into(),
+            message: format!("At most {} users per bulk kick", MAX_BULK_KICK),
+        });
+    }
+
+    let mut results = HashMap::new();
+    for user in users {
+        let result = if server.config.admins.contains(&user) {
+            "forbidden"
+        } else {
+            match kick_client(server, admin, &user, reason.clone()).await {
+                Ok(()) => "kicked",
+                Err(_) => "not_found",
+            }
+        };
+        results.insert(user, result.to_string());
+    }
+    Ok(ServerFrame::KickResults { results })
+}
+
+// Like `kick_many`, but bans, so it reaches users who are offline too. A
+// user is only found if they're online or a token names them.
+pub(super) async fn ban_many(
+    server: &Server,
+    admin: &str,
+    users: Vec<String>,
+    reason: Option<String>,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+    if users.len() > MAX_BULK_KICK {
+        return Err(ServerFrame::Error {
+            code: "too_many_users".into(),
+            message: format!("At most {} users per bulk ban", MAX_BULK_KICK),
+        });
+    }
+
+    let mut results = HashMap::new();
+    for user in users {
+        let known = server.tokens.read().unwrap().for_user(&user).is_some()
+            || server.clients.lock().await.contains_key(&user);
+        let result = if server.config.admins.contains(&user) {
+            "forbidden"
+        } else if !known {
+            "not_found"
+        } else {
+            match ban(
+                server,
+                admin,
+                Some(user.clone()),
+                None,
+                None,
+                reason.clone(),
+            )
+            .await
+            {
+                Ok(_) => "banned",
+                Err(_) => "not_found",
+            }
+        };
+        results.insert(user, result.to_string());
+    }
+    Ok(ServerFrame::BanResults { results })
+}
+
+// Mutes apply by username, online or not. Both sides get a notice; the
+// admin's is the returned frame.
+pub(super) async fn mute_user(
+    server: &Server,
+    admin: &str,
+    user: &str,
+    seconds: u64,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+    if seconds == 0 {
+        return Err(ServerFrame::Error {
+            code: "invalid_duration".into(),
+            message: "Mute duration must be at least one second".into(),
+        });
+    }
+
+    let until_secs = unix_now() + seconds;
+    server.mutes.lock().unwrap().insert(user, until_secs);
+    println!("{} muted {} for {}s", admin, user, seconds);
+
+    notify_user(
+        server,
+        user,
+        &format!("You have been muted by {} for {}s", admin, seconds),
+        serde_json::json!({ "event": "mute", "by": admin, "until": until_secs }),
+    )
+    .await;
+
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("{} is muted for {}s", user, seconds),
+        context: Some(serde_json::json!({ "event": "mute", "user": user, "until": until_secs })),
+    })
+}
+
+pub(super) async fn unmute_user(
+    server: &Server,
+    admin: &str,
+    user: &str,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+    if !server.mutes.lock().unwrap().remove(user) {
+        return Err(ServerFrame::Error {
+            code: "not_muted".into(),
+            message: format!("{} is not muted", user),
+        });
+    }
+    println!("{} unmuted {}", admin, user);
+
+    notify_user(
+        server,
+        user,
+        &format!("You have been unmuted by {}", admin),
+        serde_json::json!({ "event": "unmute", "by": admin }),
+    )
+    .await;
+
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("{} is no longer muted", user),
+        context: Some(serde_json::json!({ "event": "unmute", "user": user })),
+    })
+}
+
+// When the user's mute ends, as unix seconds; expired mutes are dropped here
+pub(super) fn mute_until(server: &Server, username: &str) -> Option<u64> {
+    server.mutes.lock().unwrap().until(username)
+}
+
+// Reaches everyone online, and with PUSH_ANNOUNCEMENT_STICKY_SECS set,
+// anyone connecting in that window too
+pub(super) async fn announce(server: &Server, admin: &str, text: &str) -> Result<(), ServerFrame> {
+    require_admin(server, admin)?;
+    if text.trim().is_empty() {
+        return Err(ServerFrame::Error {
+            code: "empty_announcement".into(),
+            message: "Announcements need some text".into(),
+        });
+    }
+    if text.chars().count() > MAX_ANNOUNCEMENT_CHARS {
+        return Err(ServerFrame::Error {
+            code: "announcement_too_long".into(),
+            message: format!(
+                "Announcements can be at most {} characters",
+                MAX_ANNOUNCEMENT_CHARS
+            ),
+        });
+    }
+    check_line_limits(&server.config, text)?;
+
+    let frame = ServerFrame::System {
+        severity: Severity::Info,
+        text: text.into(),
+        context: Some(
+            serde_json::json!({ "event": "announcement", "announcement": true, "by": admin }),
+        ),
+    };
+    let json = serde_json::to_string(&frame).unwrap();
+
+    let clients_guard = server.clients.lock().await;
+    for client in clients_guard.values() {
+        let _ = client.tx.send(json.clone());
+    }
+
+    let sticky = server.config.announcement_sticky;
+    *server.announcement.lock().unwrap() = if sticky.is_zero() {
+        None
+    } else {
+        Some(StickyAnnouncement {
+            frame: json,
+            until: Instant::now() + sticky,
+            seen: clients_guard.keys().cloned().collect(),
+        })
+    };
+    Ok(())
+}
+
+// The target is never told
+pub(super) fn set_shadow_ban(
+    server: &Server,
+    admin: &str,
+    user: &str,
+    banned: bool,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+
+    let mut shadow_bans = server.shadow_bans.lock().unwrap();
+    let changed = if banned {
+        shadow_bans.users.insert(user.to_string())
+    } else {
+        shadow_bans.users.remove(user)
+    };
+    if changed {
+        shadow_bans.save();
+    } else {
+        return Err(ServerFrame::Error {
+            code: if banned {
+                "already_shadow_banned"
+            } else {
+                "not_shadow_banned"
+            }
+            .into(),
+            message: format!("Nothing to change for {}", user),
+        });
+    }
+    println!(
+        "{} {} {}",
+        admin,
+        if banned {
+            "shadow-banned"
+        } else {
+            "lifted the shadow ban on"
+        },
+        user
+    );
+
+    let (event, text) = if banned {
+        ("shadow_ban", format!("{} is shadow-banned", user))
+    } else {
+        (
+            "shadow_unban",
+            format!("{} is no longer shadow-banned", user),
+        )
+    };
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text,
+        context: Some(serde_json::json!({ "event": event, "user": user })),
+    })
+}
+
+// Like a shadow ban, but admins don't see the messages either
+pub(super) fn set_shadow_mute(
+    server: &Server,
+    admin: &str,
+    user: &str,
+    muted: bool,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+
+    let mut shadow_mutes = server.shadow_mutes.lock().unwrap();
+    let changed = if muted {
+        shadow_mutes.insert(user.to_string())
+    } else {
+        shadow_mutes.remove(user)
+    };
+    if !changed {
+        return Err(ServerFrame::Error {
+            code: if muted {
+                "already_shadow_muted"
+            } else {
+                "not_shadow_muted"
+            }
+            .into(),
+            message: format!("Nothing to change for {}", user),
+        });
+    }
+    println!(
+        "{} {} {}",
+        admin,
+        if muted {
+            "shadow-muted"
+        } else {
+            "lifted the shadow mute on"
+        },
+        user
+    );
+
+    let (event, text) = if muted {
+        ("shadow_mute", format!("{} is shadow-muted", user))
+    } else {
+        (
+            "shadow_unmute",
+            format!("{} is no longer shadow-muted", user),
+        )
+    };
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text,
+        context: Some(serde_json::json!({ "event": event, "user": user })),
+    })
+}
+
+pub(super) fn notify_admins(
+    server: &Server,
+    clients: &HashMap<String, Client>,
+    frame: &ServerFrame,
+) {
+    for (username, client) in clients {
+        if server.config.admins.contains(username) {
+            client.send_frame(frame);
+        }
+    }
+}
+
+pub(super) async fn notify_user(
+    server: &Server,
+    username: &str,
+    text: &str,
+    context: serde_json::Value,
+) {
+    if let Some(client) = server.clients.lock().await.get(username) {
+        client.send_frame(&ServerFrame::System {
+            severity: Severity::Warning,
+            text: text.into(),
+            context: Some(context),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    #[tokio::test]
+    async fn kick_closes_with_policy_and_leaves_like_a_disconnect() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        alice
+            .send(json!({ "type": "kick", "user": "bob", "reason": "spam" }))
+            .await;
+        let close = bob.expect_close().await.unwrap();
+        assert_eq!(close.code, CloseCode::Policy);
+        assert_eq!(close.reason, "spam");
+
+        alice.expect_event("kick").await;
+        let leave = alice.expect_event("leave").await;
+        assert_eq!(leave["context"]["user"], "bob");
+        assert_eq!(leave["context"]["reason"], "kicked");
+        test.wait_offline("bob").await;
+
+        alice
+            .send(json!({ "type": "audit", "target": "bob" }))
+            .await;
+        let audit = alice.expect_type("audit_log").await;
+        assert_eq!(audit["entries"][0]["action"], "kick");
+        assert_eq!(audit["entries"][0]["outcome"], "ok");
+    }
+
+    #[tokio::test]
+    async fn kicked_users_can_reconnect_but_banned_users_cannot() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        alice.send(json!({ "type": "kick", "user": "bob" })).await;
+        bob.expect_close().await;
+        test.wait_offline("bob").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        alice.send(json!({ "type": "ban", "user": "bob" })).await;
+        bob.expect_close().await;
+        test.wait_offline("bob").await;
+        let mut again = test.connect().await;
+        again.send(json!({ "token": "token-bob" })).await;
+        assert_eq!(again.recv().await.unwrap()["type"], "auth_failed");
+    }
+
+    #[tokio::test]
+    async fn kicking_needs_an_online_target_and_an_admin() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        alice
+            .send(json!({ "type": "kick", "user": "charlie" }))
+            .await;
+        alice.expect_error("no_such_user").await;
+        bob.send(json!({ "type": "kick", "user": "alice" })).await;
+        bob.expect_error("forbidden").await;
+    }
+
+    #[tokio::test]
+    async fn sticky_announcement_reaches_each_late_joiner_once() {
+        let mut config = config();
+        config.announcement_sticky = Duration::from_secs(60);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        alice
+            .send(json!({ "type": "announce", "text": "maintenance at 6" }))
+            .await;
+        let notice = bob.expect_event("announcement").await;
+        assert_eq!(notice["text"], "maintenance at 6");
+        assert_eq!(notice["context"]["announcement"], true);
+
+        // Online when it went out, so a reconnect doesn't repeat it
+        bob.close().await;
+        test.wait_offline("bob").await;
+        let mut bob = test.login("token-bob").await;
+        bob.expect_none(Duration::from_millis(200), |frame| {
+            frame["context"]["event"] == "announcement"
+        })
+        .await;
+
+        let mut charlie = test.login("token-charlie").await;
+        charlie.expect_event("announcement").await;
+        charlie.close().await;
+        test.wait_offline("charlie").await;
+        let mut charlie = test.login("token-charlie").await;
+        charlie
+            .expect_none(Duration::from_millis(200), |frame| {
+                frame["context"]["event"] == "announcement"
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn announcements_are_admin_only_and_size_limited() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        bob.send(json!({ "type": "announce", "text": "hi" })).await;
+        bob.expect_error("forbidden").await;
+
+        let text = "x".repeat(MAX_ANNOUNCEMENT_CHARS + 1);
+        alice
+            .send(json!({ "type": "announce", "text": text }))
+            .await;
+        alice.expect_error("announcement_too_long").await;
+    }
+
+    #[tokio::test]
+    async fn shadow_muted_broadcasts_only_echo_back() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let mut charlie = test.login("token-charlie").await;
+        test.wait_online("charlie").await;
+
+        alice
+            .send(json!({ "type": "shadow_mute", "user": "bob" }))
+            .await;
+        alice.expect_event("shadow_mute").await;
+
+        bob.send(json!({ "to": null, "content": "can anyone hear me" }))
+            .await;
+        let echo = bob.expect_message().await;
+        assert_eq!(echo["from"], "bob");
+        assert_eq!(echo["content"], "can anyone hear me");
+        bob.expect_none(Duration::from_millis(200), |frame| frame["type"] == "error")
+            .await;
+        for other in [&mut alice, &mut charlie] {
+            other
+                .expect_none(Duration::from_millis(200), |frame| frame["from"] == "bob")
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn shadow_bans_survive_a_restart() {
+        let path = temp_file("shadow_bans.json");
+        let config = || {
+            let mut config = config();
+            config.shadow_bans_file = Some(path.clone());
+            config
+        };
+
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        alice
+            .send(json!({ "type": "shadow_ban", "user": "bob" }))
+            .await;
+        alice.expect_event("shadow_ban").await;
+        alice.close().await;
+        test.wait_offline("alice").await;
+
+        // A second server on the same file stands in for the restart
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let mut charlie = test.login("token-charlie").await;
+        test.wait_online("charlie").await;
+
+        bob.send(json!({ "content": "still here" })).await;
+        let notice = alice.expect_event("shadow").await;
+        assert_eq!(notice["context"]["user"], "bob");
+        charlie
+            .expect_none(Duration::from_millis(200), |frame| frame["from"] == "bob")
+            .await;
+
+        alice
+            .send(json!({ "type": "shadow_unban", "user": "bob" }))
+            .await;
+        alice.expect_event("shadow_unban").await;
+        let test = TestServer::start(config()).await;
+        assert!(test.server.shadow_bans.lock().unwrap().users.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bulk_kick_reports_each_user() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let mut charlie = test.login("token-charlie").await;
+        test.wait_online("charlie").await;
+
+        alice
+            .send(json!({
+                "type": "kick_many",
+                "users": ["bob", "charlie", "dave", "alice"],
+                "reason": "raid",
+            }))
+            .await;
+        let reply = alice.expect_type("kick_results").await;
+        assert_eq!(
+            reply["results"],
+            json!({
+                "bob": "kicked",
+                "charlie": "kicked",
+                "dave": "not_found",
+                "alice": "forbidden",
+            })
+        );
+        for kicked in [&mut bob, &mut charlie] {
+            assert_eq!(kicked.expect_close().await.unwrap().code, CloseCode::Policy);
+        }
+        test.wait_offline("bob").await;
+        test.wait_offline("charlie").await;
+
+        let users: Vec<String> = (0..=MAX_BULK_KICK).map(|i| format!("user{}", i)).collect();
+        alice
+            .send(json!({ "type": "kick_many", "users": users }))
+            .await;
+        alice.expect_error("too_many_users").await;
+    }
+
+    #[tokio::test]
+    async fn bulk_ban_reaches_online_and_offline_users() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        // charlie has a token but isn't connected
+        alice
+            .send(json!({
+                "type": "ban_many",
+                "users": ["bob", "charlie", "dave", "alice"],
+                "reason": "raid",
+            }))
+            .await;
+        let reply = alice.expect_type("ban_results").await;
+        assert_eq!(
+            reply["results"],
+            json!({
+                "bob": "banned",
+                "charlie": "banned",
+                "dave": "not_found",
+                "alice": "forbidden",
+            })
+        );
+        let close = bob.expect_close().await.unwrap();
+        assert_eq!(close.code, CloseCode::Policy);
+        assert_eq!(close.reason, "raid");
+        test.wait_offline("bob").await;
+
+        for token in ["token-bob", "token-charlie"] {
+            let mut again = test.connect().await;
+            again.send(json!({ "token": token })).await;
+            let reply = again.recv().await.unwrap();
+            assert_eq!(reply["type"], "auth_failed");
+        }
+        let banned: Vec<_> = test
+            .server
+            .bans
+            .lock()
+            .unwrap()
+            .active()
+            .into_iter()
+            .filter_map(|ban| ban.user)
+            .collect();
+        assert_eq!(banned.len(), 2);
+
+        let users: Vec<String> = (0..=MAX_BULK_KICK).map(|i| format!("user{}", i)).collect();
+        alice
+            .send(json!({ "type": "ban_many", "users": users }))
+            .await;
+        alice.expect_error("too_many_users").await;
+    }
+}
@@ -0,0 +1,74 @@
+//! The message of the day.
+
+use crate::protocol::{ServerFrame, Severity};
+use crate::server::Server;
+use crate::server::config::Config;
+use crate::server::moderation::require_admin;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+pub(super) const MAX_MOTD_CHARS: usize = 2000;
+
+pub(super) fn load_motd(config: &Config) -> anyhow::Result<Option<String>> {
+    let Some(path) = &config.motd_file else {
+        return Ok(config.motd.clone());
+    };
+    match std::fs::read_to_string(path) {
+        Ok(text) if text.trim().is_empty() => Ok(None),
+        Ok(text) => Ok(Some(text.trim_end().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(config.motd.clone()),
+        Err(e) => anyhow::bail!("reading MOTD {}: {}", path, e),
+    }
+}
+
+pub(super) fn motd_frame(server: &Server) -> Option<ServerFrame> {
+    let text = server.motd.lock().unwrap().clone()?;
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    Some(ServerFrame::Motd {
+        text,
+        hash: format!("{:016x}", hasher.finish()),
+    })
+}
+
+pub(super) async fn set_motd(
+    server: &Server,
+    admin: &str,
+    text: &str,
+    broadcast: bool,
+) -> Result<ServerFrame, ServerFrame> {
+    require_admin(server, admin)?;
+    if text.chars().count() > MAX_MOTD_CHARS {
+        return Err(ServerFrame::Error {
+            code: "motd_too_long".into(),
+            message: format!("The MOTD can be at most {} characters", MAX_MOTD_CHARS),
+        });
+    }
+
+    let text = text.trim_end();
+    if let Some(path) = &server.config.motd_file
+        && let Err(e) = std::fs::write(path, text)
+    {
+        // Still applied, just not kept across restarts
+        eprintln!("saving MOTD to {} failed: {}", path, e);
+    }
+    *server.motd.lock().unwrap() = (!text.trim().is_empty()).then(|| text.to_string());
+    println!("{} changed the MOTD", admin);
+
+    if broadcast && let Some(frame) = motd_frame(server) {
+        for client in server.clients.lock().await.values() {
+            if client.capabilities.contains("motd") {
+                client.send_frame(&frame);
+            }
+        }
+    }
+
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: if text.trim().is_empty() {
+            "MOTD cleared".into()
+        } else {
+            "MOTD updated".into()
+        },
+        context: Some(serde_json::json!({ "event": "motd" })),
+    })
+}
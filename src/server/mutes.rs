@@ -0,0 +1,121 @@
+//! Mutes by user, optionally kept across restarts.
+
+use crate::server::{unix_now, write_atomically};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub(super) struct Mutes {
+    // Username to the unix time the mute ends
+    pub(super) mutes: HashMap<String, u64>,
+    pub(super) file: Option<String>,
+}
+
+impl Mutes {
+    pub(super) fn load(path: &str) -> anyhow::Result<Mutes> {
+        let mutes = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("parsing mutes {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => anyhow::bail!("reading mutes {}: {}", path, e),
+        };
+        let mut mutes = Mutes {
+            mutes,
+            file: Some(path.to_string()),
+        };
+        mutes.prune();
+        Ok(mutes)
+    }
+
+    pub(super) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_atomically(path, &self.mutes) {
+            eprintln!("saving mutes to {} failed: {}", path, e);
+        }
+    }
+
+    // Expired mutes are dropped whenever mutes are looked at
+    pub(super) fn prune(&mut self) {
+        let now = unix_now();
+        let before = self.mutes.len();
+        self.mutes.retain(|_, until| *until > now);
+        if self.mutes.len() != before {
+            self.save();
+        }
+    }
+
+    pub(super) fn insert(&mut self, user: &str, until: u64) {
+        self.mutes.insert(user.to_string(), until);
+        self.save();
+    }
+
+    pub(super) fn remove(&mut self, user: &str) -> bool {
+        let removed = self.mutes.remove(user).is_some();
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    // When the user's mute ends, if they're muted
+    pub(super) fn until(&mut self, user: &str) -> Option<u64> {
+        self.prune();
+        self.mutes.get(user).copied()
+    }
+
+    pub(super) fn active(&mut self) -> usize {
+        self.prune();
+        self.mutes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    #[test]
+    fn load_keeps_running_mutes_and_drops_expired_ones() {
+        let path = temp_file("mutes.json");
+        let mut mutes = Mutes::load(&path).unwrap();
+        mutes.insert("carol", unix_now() + 600);
+        mutes.insert("dave", unix_now() + 600);
+        mutes.mutes.insert("erin".to_string(), unix_now() - 1);
+        mutes.save();
+
+        let mut reloaded = Mutes::load(&path).unwrap();
+        assert!(reloaded.until("carol").is_some());
+        assert!(reloaded.until("erin").is_none());
+
+        assert!(reloaded.remove("dave"));
+        assert!(!reloaded.remove("dave"));
+        assert!(Mutes::load(&path).unwrap().until("dave").is_none());
+    }
+
+    #[tokio::test]
+    async fn muted_error_names_the_expiry_and_survives_a_restart() {
+        let path = temp_file("mutes.json");
+        let config = || {
+            let mut config = config();
+            config.mutes_file = Some(path.clone());
+            config
+        };
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        alice
+            .send(json!({ "type": "mute", "user": "bob", "seconds": 600 }))
+            .await;
+        let notice = alice.expect_event("mute").await;
+        let until = notice["context"]["until"].as_u64().unwrap();
+
+        // A second server on the same file stands in for a restart
+        let restarted = TestServer::start(config()).await;
+        let mut bob = restarted.login("token-bob").await;
+        bob.send(json!({ "to": "alice", "content": "hi" })).await;
+        let error = bob.expect_error("muted").await;
+        let message = error["message"].as_str().unwrap();
+        assert!(message.contains(&until.to_string()), "{}", message);
+    }
+}
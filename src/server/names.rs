@@ -0,0 +1,80 @@
+//! Display name normalizing and collisions.
+
+use crate::server::Server;
+use crate::server::config::Config;
+
+pub(super) fn normalize_name(config: &Config, name: String) -> String {
+    if !config.normalize_names {
+        return name;
+    }
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// The form names are compared in; the display name keeps its original case
+pub(super) fn name_key(config: &Config, name: &str) -> String {
+    if config.case_fold_names {
+        name.to_lowercase()
+    } else {
+        name.to_string()
+    }
+}
+
+// True when someone else is online under a name that only differs in case.
+// The exact same name is a reconnect and replaces the old entry as before.
+pub(super) async fn name_collides(server: &Server, name: &str) -> bool {
+    let key = name_key(&server.config, name);
+    server
+        .clients
+        .lock()
+        .await
+        .keys()
+        .any(|other| other != name && name_key(&server.config, other) == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Client;
+    use crate::server::testing::*;
+    use tokio::sync::mpsc;
+
+    async fn server_with_online(config: Config, name: &str) -> Server {
+        let server = Server::new(config).unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (client, _kicked) = Client::new(tx);
+        server.clients.lock().await.insert(name.to_string(), client);
+        server
+    }
+
+    #[test]
+    fn normalizing_trims_and_collapses_whitespace() {
+        let mut config = config();
+        config.normalize_names = true;
+        assert_eq!(
+            normalize_name(&config, "  Alice \n Smith ".into()),
+            "Alice Smith"
+        );
+        config.normalize_names = false;
+        assert_eq!(normalize_name(&config, " Alice ".into()), " Alice ");
+    }
+
+    #[tokio::test]
+    async fn case_folded_names_collide() {
+        let mut config = config();
+        config.case_fold_names = true;
+        let server = server_with_online(config, "Alice").await;
+        assert!(name_collides(&server, "alice").await);
+        assert!(name_collides(&server, "ALICE").await);
+        // The same name again is a reconnect, not a collision
+        assert!(!name_collides(&server, "Alice").await);
+        assert!(!name_collides(&server, "bob").await);
+    }
+
+    #[tokio::test]
+    async fn names_are_case_sensitive_by_default() {
+        let mut config = config();
+        config.case_fold_names = false;
+        let server = server_with_online(config, "Alice").await;
+        assert!(!name_collides(&server, "alice").await);
+    }
+}
@@ -0,0 +1,368 @@
+//! A small backtracking regular expression engine for word filter patterns.
+//!
+//! Supports literals, `.`, classes (`[a-z]`, `[^0-9]`), the escapes `\d`,
+//! `\w`, `\s` and `\b`, the anchors `^` and `$`, groups with `|`, and the
+//! quantifiers `*`, `+`, `?` and `{n,m}`. Matching ignores case. Patterns come
+//! from the operator's rules file, so there's no guard against ones that
+//! backtrack badly.
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Digit,
+    Word,
+    Space,
+    Start,
+    End,
+    Boundary,
+    // Alternatives, each a sequence
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+#[derive(Debug)]
+pub(super) struct Pattern {
+    alternatives: Vec<Vec<Node>>,
+}
+
+impl Pattern {
+    pub(super) fn parse(source: &str) -> Result<Pattern, String> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            pos: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        match parser.peek() {
+            None => Ok(Pattern { alternatives }),
+            Some(c) => Err(format!("unexpected `{}` at {}", c, parser.pos)),
+        }
+    }
+
+    // Leftmost, non-overlapping, non-empty matches as byte ranges of `text`
+    pub(super) fn find_all(&self, text: &str) -> Vec<std::ops::Range<usize>> {
+        let offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([text.len()])
+            .collect();
+        // One lowercase char per original char keeps the offsets lined up
+        let chars: Vec<char> = text
+            .chars()
+            .map(|c| c.to_lowercase().next().unwrap_or(c))
+            .collect();
+
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start <= chars.len() {
+            let end = self
+                .alternatives
+                .iter()
+                .find_map(|alternative| match_seq(alternative, &chars, start, &Some));
+            match end {
+                Some(end) if end > start => {
+                    matches.push(offsets[start]..offsets[end]);
+                    start = end;
+                }
+                _ => start += 1,
+            }
+        }
+        matches
+    }
+}
+
+impl Node {
+    // For nodes that consume exactly one char
+    fn matches_char(&self, c: char) -> bool {
+        match self {
+            Node::Char(expected) => c == *expected,
+            Node::Any => c != '\n',
+            Node::Class { ranges, negated } => {
+                ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+            }
+            Node::Digit => c.is_ascii_digit(),
+            Node::Word => is_word_char(c),
+            Node::Space => c.is_whitespace(),
+            _ => false,
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Matches `nodes` at `pos`, then hands the end position to `next`, which
+// decides whether the overall match succeeds
+fn match_seq(
+    nodes: &[Node],
+    text: &[char],
+    pos: usize,
+    next: &dyn Fn(usize) -> Option<usize>,
+) -> Option<usize> {
+    let Some((node, rest)) = nodes.split_first() else {
+        return next(pos);
+    };
+    match node {
+        Node::Start => (pos == 0).then(|| match_seq(rest, text, pos, next))?,
+        Node::End => (pos == text.len()).then(|| match_seq(rest, text, pos, next))?,
+        Node::Boundary => {
+            let before = pos > 0 && is_word_char(text[pos - 1]);
+            let after = pos < text.len() && is_word_char(text[pos]);
+            (before != after).then(|| match_seq(rest, text, pos, next))?
+        }
+        Node::Group(alternatives) => alternatives.iter().find_map(|alternative| {
+            match_seq(alternative, text, pos, &|end| {
+                match_seq(rest, text, end, next)
+            })
+        }),
+        Node::Repeat { node, min, max } => match_repeat(node, *min, *max, 0, rest, text, pos, next),
+        _ => {
+            let c = *text.get(pos)?;
+            if node.matches_char(c) {
+                match_seq(rest, text, pos + 1, next)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// Greedy: tries one more repetition before settling for the rest
+#[allow(clippy::too_many_arguments)]
+fn match_repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    count: usize,
+    rest: &[Node],
+    text: &[char],
+    pos: usize,
+    next: &dyn Fn(usize) -> Option<usize>,
+) -> Option<usize> {
+    if max.is_none_or(|max| count < max) {
+        let more = match_seq(std::slice::from_ref(node), text, pos, &|end| {
+            // An empty repetition would loop forever
+            if end == pos {
+                return None;
+            }
+            match_repeat(node, min, max, count + 1, rest, text, end, next)
+        });
+        if more.is_some() {
+            return more;
+        }
+    }
+    if count >= min {
+        match_seq(rest, text, pos, next)
+    } else {
+        None
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn take(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let at = self.pos;
+        match self.take() {
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('(') => {
+                let alternatives = self.alternatives()?;
+                match self.take() {
+                    Some(')') => Ok(Node::Group(alternatives)),
+                    _ => Err(format!("unclosed `(` at {}", at)),
+                }
+            }
+            Some('[') => self.class(at),
+            Some('\\') => self.escape(),
+            Some(c @ ('*' | '+' | '?' | '{')) => {
+                Err(format!("nothing to repeat before `{}` at {}", c, at))
+            }
+            Some(c) => Ok(Node::Char(lower(c))),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        match self.take() {
+            Some('d') => Ok(Node::Digit),
+            Some('w') => Ok(Node::Word),
+            Some('s') => Ok(Node::Space),
+            Some('b') => Ok(Node::Boundary),
+            Some(c) if c.is_alphanumeric() => Err(format!("unknown escape `\\{}`", c)),
+            Some(c) => Ok(Node::Char(lower(c))),
+            None => Err("trailing `\\`".to_string()),
+        }
+    }
+
+    fn class(&mut self, at: usize) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let low = match self.take() {
+                Some(']') if !ranges.is_empty() => break,
+                Some('\\') => self.take().ok_or("trailing `\\`")?,
+                Some(c) => c,
+                None => return Err(format!("unclosed `[` at {}", at)),
+            };
+            let high = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                match self.take() {
+                    Some('\\') => self.take().ok_or("trailing `\\`")?,
+                    Some(c) => c,
+                    None => return Err(format!("unclosed `[` at {}", at)),
+                }
+            } else {
+                low
+            };
+            let (low, high) = (lower(low), lower(high));
+            if low > high {
+                return Err(format!("backwards range `{}-{}`", low, high));
+            }
+            ranges.push((low, high));
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.pos += 1;
+                return self.counted(atom);
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+        })
+    }
+
+    // `{n}`, `{n,}` or `{n,m}`, after the `{`
+    fn counted(&mut self, atom: Node) -> Result<Node, String> {
+        let mut body = String::new();
+        loop {
+            match self.take() {
+                Some('}') => break,
+                Some(c) => body.push(c),
+                None => return Err("unclosed `{`".to_string()),
+            }
+        }
+        let number = |s: &str| {
+            s.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("bad count `{{{}}}`", body))
+        };
+        let (min, max) = match body.split_once(',') {
+            None => (number(&body)?, Some(number(&body)?)),
+            Some((min, "")) => (number(min)?, None),
+            Some((min, max)) => (number(min)?, Some(number(max)?)),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("bad count `{{{}}}`", body));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+        })
+    }
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> Vec<String> {
+        let pattern = Pattern::parse(pattern).unwrap();
+        pattern
+            .find_all(text)
+            .into_iter()
+            .map(|range| text[range].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn finds_every_match_ignoring_case() {
+        assert_eq!(matches("sp[a4]m+", "SPAM and sp4mmm"), ["SPAM", "sp4mmm"]);
+        assert_eq!(matches("colou?r", "color colour"), ["color", "colour"]);
+        assert_eq!(matches(r"\bcat\b", "cat concat cat."), ["cat", "cat"]);
+        assert_eq!(
+            matches("(free|cheap) pills", "Cheap pills!"),
+            ["Cheap pills"]
+        );
+        assert_eq!(matches(r"\d{3}-\d{4}", "call 555-1234"), ["555-1234"]);
+        assert_eq!(matches("^hi", "hi hi"), ["hi"]);
+        assert!(matches("a+b", "aaa").is_empty());
+    }
+
+    #[test]
+    fn backtracks_into_groups_and_repeats() {
+        assert_eq!(matches("(a|ab)c", "abc"), ["abc"]);
+        assert_eq!(matches("a.*b", "a1b2b3"), ["a1b2b"]);
+        assert_eq!(matches("(ab)*abc", "abababc"), ["abababc"]);
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        for bad in ["(ab", "a)", "[ab", "*a", r"\q", "a{3,1}", "[z-a]"] {
+            assert!(Pattern::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
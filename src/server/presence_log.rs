@@ -0,0 +1,132 @@
+//! A log of joins and leaves for "who was online at" queries.
+
+use crate::server::unix_now;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+
+// Joins and leaves with timestamps, so admins can ask who was online when
+#[derive(Serialize, Deserialize)]
+pub(super) struct PresenceEvent {
+    pub(super) timestamp: u64,
+    pub(super) user: String,
+    pub(super) online: bool,
+}
+
+#[derive(Default)]
+pub(super) struct PresenceLog {
+    pub(super) events: Vec<PresenceEvent>,
+    pub(super) file: Option<std::fs::File>,
+}
+
+impl PresenceLog {
+    // Replays the file, then closes sessions the last run never saw end:
+    // they count as online until this restart
+    pub(super) fn open(path: &str) -> anyhow::Result<PresenceLog> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => anyhow::bail!("reading presence log {}: {}", path, e),
+        };
+        let events = text
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("opening presence log {}: {}", path, e))?;
+
+        let mut log = PresenceLog {
+            events,
+            file: Some(file),
+        };
+        for user in log.online_at(u64::MAX) {
+            log.record(&user, false);
+        }
+        Ok(log)
+    }
+
+    pub(super) fn record(&mut self, user: &str, online: bool) {
+        let event = PresenceEvent {
+            timestamp: unix_now(),
+            user: user.to_string(),
+            online,
+        };
+        if let Some(file) = &mut self.file
+            && let Err(e) = writeln!(file, "{}", serde_json::to_string(&event).unwrap())
+        {
+            eprintln!("presence log write failed: {}", e);
+        }
+        self.events.push(event);
+    }
+
+    pub(super) fn online_at(&self, timestamp: u64) -> Vec<String> {
+        let mut online = HashSet::new();
+        for event in self.events.iter().take_while(|e| e.timestamp <= timestamp) {
+            if event.online {
+                online.insert(event.user.as_str());
+            } else {
+                online.remove(event.user.as_str());
+            }
+        }
+        let mut online: Vec<String> = online.into_iter().map(str::to_string).collect();
+        online.sort();
+        online
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    fn event(timestamp: u64, user: &str, online: bool) -> String {
+        json!({ "timestamp": timestamp, "user": user, "online": online }).to_string()
+    }
+
+    #[test]
+    fn online_state_is_rebuilt_from_the_event_log() {
+        let path = temp_file("presence.log");
+        let lines = [
+            event(100, "alice", true),
+            event(110, "bob", true),
+            event(120, "alice", false),
+            event(130, "carol", true),
+            "not json".to_string(),
+            event(140, "alice", true),
+        ];
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+
+        let log = PresenceLog::open(&path).unwrap();
+        assert!(log.online_at(99).is_empty());
+        assert_eq!(log.online_at(115), ["alice", "bob"]);
+        assert_eq!(log.online_at(125), ["bob"]);
+        assert_eq!(log.online_at(145), ["alice", "bob", "carol"]);
+        // Nobody is left online by the run that wrote the log
+        assert!(log.online_at(u64::MAX).is_empty());
+    }
+
+    #[tokio::test]
+    async fn admins_ask_who_was_online() {
+        let path = temp_file("presence.log");
+        std::fs::write(&path, event(100, "carol", true) + "\n").unwrap();
+        let mut config = config();
+        config.presence_log = Some(path);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+
+        alice
+            .send(json!({ "type": "presence_at", "timestamp": 150 }))
+            .await;
+        let reply = alice.expect_type("presence_at").await;
+        assert_eq!(reply["online"], json!(["carol"]));
+
+        let mut bob = test.login("token-bob").await;
+        bob.send(json!({ "type": "presence_at", "timestamp": 150 }))
+            .await;
+        bob.expect_error("forbidden").await;
+    }
+}
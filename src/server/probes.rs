@@ -0,0 +1,90 @@
+//! Presence probes for half-open connections.
+
+use crate::protocol::ServerFrame;
+use crate::server::broadcast::broadcast_presence;
+use crate::server::routing::take_client;
+use crate::server::{DisconnectReason, Server};
+use rand::seq::IteratorRandom;
+use std::sync::Arc;
+use std::time::Instant;
+
+// A half-open connection never errors on its own; asking a random few each
+// tick finds them without waiting for the OS to give up. SSE and IRC clients
+// can't answer, so they're never asked.
+pub(super) async fn run_prober(server: Arc<Server>) {
+    let config = &server.config;
+    let mut ticker = tokio::time::interval(config.probe_interval);
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+        let mut clients_guard = server.clients.lock().await;
+
+        let silent: Vec<String> = clients_guard
+            .iter()
+            .filter(|(_, client)| {
+                client
+                    .probed_at
+                    .is_some_and(|at| now.duration_since(at) > config.probe_timeout)
+            })
+            .map(|(username, _)| username.clone())
+            .collect();
+        for username in &silent {
+            if let Some(mut client) = take_client(&server, &mut clients_guard, username)
+                && let Some(kick) = client.kick.take()
+            {
+                let _ = kick.send((
+                    DisconnectReason::IdleTimeout,
+                    "No answer to presence probe".into(),
+                ));
+            }
+            println!("{} dropped: no answer to presence probe", username);
+        }
+
+        let candidates = clients_guard.values_mut().filter(|client| {
+            client.probed_at.is_none() && matches!(client.connection.transport, "ws" | "tcp")
+        });
+        for client in candidates.choose_multiple(&mut rand::rng(), config.probe_batch) {
+            client.probed_at = Some(now);
+            client.send_frame(&ServerFrame::PresenceProbe);
+        }
+        drop(clients_guard);
+
+        for username in &silent {
+            broadcast_presence(
+                &server,
+                username,
+                false,
+                Some(DisconnectReason::IdleTimeout),
+            )
+            .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn connections_that_never_answer_are_pruned() {
+        let mut config = config();
+        config.probe_interval = Duration::from_millis(100);
+        config.probe_timeout = Duration::from_millis(200);
+        let test = TestServer::start(config).await;
+        let mut bob = test.login("token-bob").await;
+        let mut charlie = test.login("token-charlie").await;
+        test.wait_online("charlie").await;
+        charlie.expect_type("presence_probe").await;
+
+        // Bob keeps answering while charlie stays silent
+        while test.server.clients.lock().await.contains_key("charlie") {
+            bob.expect_type("presence_probe").await;
+            bob.send(json!({ "type": "probe_reply" })).await;
+        }
+        assert!(test.server.clients.lock().await.contains_key("bob"));
+        let close = charlie.expect_close().await.unwrap();
+        assert_eq!(close.reason, "No answer to presence probe");
+    }
+}
@@ -0,0 +1,169 @@
+//! Per-user quiet hours.
+
+use crate::protocol::{ServerFrame, ServerMessage, Severity};
+use crate::server::{Server, unix_now};
+
+pub(super) struct QuietHours {
+    // Minutes after local midnight; `end` may be before `start` to wrap
+    // past midnight
+    pub(super) start: u32,
+    pub(super) end: u32,
+    // Local time minus UTC, in minutes
+    pub(super) offset: i32,
+    pub(super) mentions: bool,
+}
+
+impl QuietHours {
+    pub(super) fn suppresses(&self, recipient: &str, msg: &ServerMessage, now: u64) -> bool {
+        if self.mentions && msg.content.contains(&format!("@{}", recipient)) {
+            return false;
+        }
+        let local = (now as i64 / 60 + self.offset as i64).rem_euclid(24 * 60) as u32;
+        if self.start <= self.end {
+            self.start <= local && local < self.end
+        } else {
+            self.start <= local || local < self.end
+        }
+    }
+}
+
+pub(super) fn is_quiet_for(server: &Server, recipient: &str, msg: &ServerMessage) -> bool {
+    server
+        .quiet_hours
+        .lock()
+        .unwrap()
+        .get(recipient)
+        .is_some_and(|quiet| quiet.suppresses(recipient, msg, unix_now()))
+}
+
+pub(super) fn set_quiet_hours(
+    server: &Server,
+    username: &str,
+    start: &str,
+    end: &str,
+    tz: &str,
+    mentions: bool,
+) -> Result<ServerFrame, ServerFrame> {
+    let (Some(start_minutes), Some(end_minutes)) = (parse_clock(start), parse_clock(end)) else {
+        return Err(ServerFrame::Error {
+            code: "invalid_quiet_hours".into(),
+            message: "Quiet hours take times like 22:00".into(),
+        });
+    };
+    let Some(offset) = parse_utc_offset(tz) else {
+        return Err(ServerFrame::Error {
+            code: "invalid_timezone".into(),
+            message: format!("Unknown timezone {}; use UTC or an offset like +02:00", tz),
+        });
+    };
+
+    let mut quiet_hours = server.quiet_hours.lock().unwrap();
+    if start_minutes == end_minutes {
+        quiet_hours.remove(username);
+        return Ok(ServerFrame::System {
+            severity: Severity::Info,
+            text: "Quiet hours turned off".into(),
+            context: Some(serde_json::json!({ "event": "quiet_hours", "enabled": false })),
+        });
+    }
+    quiet_hours.insert(
+        username.to_string(),
+        QuietHours {
+            start: start_minutes,
+            end: end_minutes,
+            offset,
+            mentions,
+        },
+    );
+
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: format!("Quiet hours set to {}-{} ({})", start, end, tz),
+        context: Some(serde_json::json!({
+            "event": "quiet_hours",
+            "enabled": true,
+            "start": start,
+            "end": end,
+            "tz": tz,
+        })),
+    })
+}
+
+// `HH:MM` as minutes after midnight
+pub(super) fn parse_clock(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+// `UTC`, `Z` or a fixed offset like `+02:00` or `-05:30`; there's no zone
+// database to resolve names like `Europe/Paris`
+pub(super) fn parse_utc_offset(tz: &str) -> Option<i32> {
+    if tz.eq_ignore_ascii_case("utc") || tz == "Z" {
+        return Some(0);
+    }
+    let (sign, offset) = match tz.split_at_checked(1)? {
+        ("+", offset) => (1, offset),
+        ("-", offset) => (-1, offset),
+        _ => return None,
+    };
+    let minutes = parse_clock(offset).filter(|&minutes| minutes <= 14 * 60)?;
+    Some(sign * minutes as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    fn message(content: &str) -> ServerMessage {
+        serde_json::from_value(json!({ "from": "alice", "to": "bob", "content": content })).unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_wrap_midnight_and_follow_the_offset() {
+        // 22:00-07:00 at UTC+02:00
+        let quiet = QuietHours {
+            start: 22 * 60,
+            end: 7 * 60,
+            offset: 120,
+            mentions: true,
+        };
+        let utc = |hour: u64| hour * 3600;
+        assert!(quiet.suppresses("bob", &message("hi"), utc(21)));
+        assert!(quiet.suppresses("bob", &message("hi"), utc(2)));
+        assert!(!quiet.suppresses("bob", &message("hi"), utc(5)));
+        assert!(!quiet.suppresses("bob", &message("hi"), utc(19)));
+        assert!(!quiet.suppresses("bob", &message("hey @bob"), utc(21)));
+    }
+
+    #[tokio::test]
+    async fn messages_during_quiet_hours_carry_the_suppress_flag() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        let minutes = unix_now() / 60 % (24 * 60);
+        let clock = |m: u64| format!("{:02}:{:02}", m / 60, m % 60);
+        bob.send(json!({
+            "type": "set_quiet_hours",
+            "start": clock(minutes),
+            "end": clock((minutes + 60) % (24 * 60)),
+            "tz": "UTC",
+        }))
+        .await;
+        bob.expect_event("quiet_hours").await;
+
+        alice
+            .send(json!({ "to": "bob", "content": "late night" }))
+            .await;
+        let quiet = bob.expect_message().await;
+        assert_eq!(quiet["suppress_notification"], true, "{}", quiet);
+
+        bob.send(json!({ "to": "alice", "content": "not quiet here" }))
+            .await;
+        let loud = alice.expect_message().await;
+        assert!(loud.get("suppress_notification").is_none(), "{}", loud);
+    }
+}
@@ -0,0 +1,414 @@
+//! Per-user rate limits, flood escalation, duplicate suppression and
+//! the global circuit breaker.
+
+use crate::protocol::{AuditEntry, ServerFrame, Severity};
+use crate::server::audit::record_audit;
+use crate::server::console::console_tail;
+use crate::server::moderation::notify_admins;
+use crate::server::{Client, Server, unix_now};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+// Automatic mutes escalate through these; the level decays after a quiet hour
+pub(super) const FLOOD_MUTE_STEPS: [Duration; 3] = [
+    Duration::from_secs(60),
+    Duration::from_secs(10 * 60),
+    Duration::from_secs(60 * 60),
+];
+pub(super) const FLOOD_LEVEL_DECAY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+pub(super) struct RateState {
+    pub(super) sent: VecDeque<Instant>,
+    pub(super) strikes: VecDeque<Instant>,
+    // Index into FLOOD_MUTE_STEPS for the next automatic mute
+    pub(super) level: usize,
+    pub(super) last_auto_mute: Option<Instant>,
+    // Hashes of recent message contents, for duplicate suppression
+    pub(super) recent: VecDeque<(u64, Instant)>,
+    // Hashes of the `client_id`s of recent messages that got through
+    pub(super) client_ids: VecDeque<(u64, Instant)>,
+}
+
+pub(super) enum RateCheck {
+    Allowed,
+    Limited,
+    // Tripped the limiter too often; mute for this long
+    Flooding(Duration),
+}
+
+pub(super) fn check_rate(server: &Server, username: &str) -> RateCheck {
+    let config = &server.config;
+    if config.breaker_rate > 0 {
+        server.breaker_count.fetch_add(1, Ordering::Relaxed);
+    }
+    let limit = rate_limit(server);
+    if limit == 0 {
+        return RateCheck::Allowed;
+    }
+
+    let now = Instant::now();
+    let mut rate = server.rate.lock().unwrap();
+    let state = rate.entry(username.to_string()).or_default();
+
+    while state
+        .sent
+        .front()
+        .is_some_and(|t| now.duration_since(*t) > config.rate_window)
+    {
+        state.sent.pop_front();
+    }
+    if state.sent.len() < limit {
+        state.sent.push_back(now);
+        return RateCheck::Allowed;
+    }
+
+    while state
+        .strikes
+        .front()
+        .is_some_and(|t| now.duration_since(*t) > config.flood_window)
+    {
+        state.strikes.pop_front();
+    }
+    state.strikes.push_back(now);
+    if config.flood_strikes == 0 || state.strikes.len() < config.flood_strikes {
+        return RateCheck::Limited;
+    }
+
+    if state
+        .last_auto_mute
+        .is_some_and(|t| now.duration_since(t) > FLOOD_LEVEL_DECAY)
+    {
+        state.level = 0;
+    }
+    let duration = FLOOD_MUTE_STEPS[state.level.min(FLOOD_MUTE_STEPS.len() - 1)];
+    state.level += 1;
+    state.last_auto_mute = Some(now);
+    state.strikes.clear();
+    RateCheck::Flooding(duration)
+}
+
+// While the circuit breaker is tripped everyone gets the tighter limit, even
+// with per-user limits otherwise off
+pub(super) fn rate_limit(server: &Server) -> usize {
+    let limit = server.config.rate_limit;
+    if !server.breaker_tripped.load(Ordering::Relaxed) {
+        return limit;
+    }
+    match limit {
+        0 => server.config.breaker_rate_limit,
+        limit => limit.min(server.config.breaker_rate_limit),
+    }
+}
+
+// Counts every message that reaches the rate limiter, rejected ones
+// included, so a flood spread over many users trips it even while their own
+// limits are holding
+pub(super) async fn run_circuit_breaker(server: Arc<Server>) {
+    let config = &server.config;
+    let mut ticker = tokio::time::interval(config.breaker_window);
+    ticker.tick().await;
+    let mut calm_since: Option<Instant> = None;
+
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+        let count = server.breaker_count.swap(0, Ordering::Relaxed);
+        let rate = count as f64 / config.breaker_window.as_secs_f64();
+        let tripped = server.breaker_tripped.load(Ordering::Relaxed);
+
+        let change = if rate > config.breaker_rate as f64 {
+            calm_since = None;
+            (!tripped).then_some(true)
+        } else if tripped {
+            let since = *calm_since.get_or_insert(now);
+            (now.duration_since(since) >= config.breaker_cooldown).then_some(false)
+        } else {
+            None
+        };
+        let Some(trip) = change else {
+            continue;
+        };
+
+        server.breaker_tripped.store(trip, Ordering::Relaxed);
+        calm_since = None;
+        let text = if trip {
+            format!(
+                "Circuit breaker tripped at {:.1} msg/s; rate limits tightened to {} per {}s",
+                rate,
+                config.breaker_rate_limit,
+                config.rate_window.as_secs()
+            )
+        } else {
+            "Circuit breaker relaxed; rate limits are back to normal".to_string()
+        };
+        println!("ALERT: {}", text);
+        console_tail(&server, &format!("[alert] {}", text));
+        let clients_guard = server.clients.lock().await;
+        notify_admins(
+            &server,
+            &clients_guard,
+            &ServerFrame::System {
+                severity: Severity::Warning,
+                text,
+                context: Some(serde_json::json!({
+                    "event": "circuit_breaker",
+                    "tripped": trip,
+                    "msgs_per_sec": rate,
+                })),
+            },
+        );
+    }
+}
+
+// Rejected copies aren't recorded, so the window clears on its own
+pub(super) fn is_duplicate(server: &Server, username: &str, content: &str) -> bool {
+    let config = &server.config;
+    let normalized = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if config.duplicate_limit == 0 || normalized.chars().count() < config.duplicate_min_len {
+        return false;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    normalized.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let now = Instant::now();
+    let mut rate = server.rate.lock().unwrap();
+    let state = rate.entry(username.to_string()).or_default();
+    while state
+        .recent
+        .front()
+        .is_some_and(|(_, t)| now.duration_since(*t) > config.duplicate_window)
+    {
+        state.recent.pop_front();
+    }
+
+    let copies = state.recent.iter().filter(|(h, _)| *h == hash).count();
+    if copies >= config.duplicate_limit {
+        return true;
+    }
+    state.recent.push_back((hash, now));
+    false
+}
+
+// Kept per user rather than per connection, so a send retried after
+// reconnecting is still caught. Only hashes are kept, so long ids cost
+// nothing extra.
+pub(super) fn is_retry(server: &Server, username: &str, client_id: &str) -> bool {
+    let config = &server.config;
+    if config.client_id_window == 0 {
+        return false;
+    }
+    let hash = hash_client_id(client_id);
+    let now = Instant::now();
+    let mut rate = server.rate.lock().unwrap();
+    let Some(state) = rate.get_mut(username) else {
+        return false;
+    };
+    state
+        .client_ids
+        .retain(|(_, t)| now.duration_since(*t) <= config.client_id_ttl);
+    state.client_ids.iter().any(|(h, _)| *h == hash)
+}
+
+pub(super) fn remember_client_id(server: &Server, username: &str, client_id: &str) {
+    let window = server.config.client_id_window;
+    if window == 0 {
+        return;
+    }
+    let mut rate = server.rate.lock().unwrap();
+    let state = rate.entry(username.to_string()).or_default();
+    while state.client_ids.len() >= window {
+        state.client_ids.pop_front();
+    }
+    state
+        .client_ids
+        .push_back((hash_client_id(client_id), Instant::now()));
+}
+
+pub(super) fn hash_client_id(client_id: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    client_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Same mute as the admin command, but issued by the server; called with the
+// clients map already locked
+pub(super) fn auto_mute(
+    server: &Server,
+    clients: &HashMap<String, Client>,
+    username: &str,
+    duration: Duration,
+) {
+    let until_secs = unix_now() + duration.as_secs();
+    server.mutes.lock().unwrap().insert(username, until_secs);
+    println!(
+        "auto-muted {} for {}s (flooding)",
+        username,
+        duration.as_secs()
+    );
+    record_audit(
+        server,
+        AuditEntry {
+            timestamp: unix_now(),
+            actor: "server".into(),
+            action: "mute".into(),
+            target: Some(username.to_string()),
+            params: serde_json::json!({ "seconds": duration.as_secs(), "reason": "flooding" }),
+            outcome: "ok".into(),
+            reason: None,
+        },
+    );
+
+    if let Some(client) = clients.get(username) {
+        client.send_frame(&ServerFrame::System {
+            severity: Severity::Warning,
+            text: format!(
+                "You have been muted for {}s for flooding",
+                duration.as_secs()
+            ),
+            context: Some(serde_json::json!({
+                "event": "mute",
+                "automatic": true,
+                "until": until_secs,
+            })),
+        });
+    }
+    notify_admins(
+        server,
+        clients,
+        &ServerFrame::System {
+            severity: Severity::Warning,
+            text: format!(
+                "{} was muted for {}s for flooding",
+                username,
+                duration.as_secs()
+            ),
+            context: Some(serde_json::json!({
+                "event": "mute",
+                "automatic": true,
+                "user": username,
+                "until": until_secs,
+            })),
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    fn send(content: &str, client_id: &str) -> serde_json::Value {
+        json!({ "content": content, "client_id": client_id })
+    }
+
+    #[tokio::test]
+    async fn a_retry_after_resuming_is_acknowledged_not_delivered() {
+        let mut config = config();
+        config.token_refresh = Duration::from_secs(1);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        alice.send(send("first try", "m1")).await;
+        assert_eq!(bob.expect_message().await["content"], "first try");
+        let refresh = alice.expect_type("token_refresh").await;
+        alice.close().await;
+        test.wait_offline("alice").await;
+
+        let mut alice = test.login(refresh["token"].as_str().unwrap()).await;
+        alice.send(send("first try", "m1")).await;
+        let notice = alice.expect_event("duplicate").await;
+        assert_eq!(notice["context"]["client_id"], "m1");
+        assert_eq!(notice["context"]["duplicate"], true);
+        bob.expect_none(Duration::from_millis(200), |frame| {
+            frame.get("from").is_some()
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn only_the_last_window_of_ids_is_remembered() {
+        let mut config = config();
+        config.client_id_window = 1;
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        for (content, client_id) in [("one", "m1"), ("two", "m2"), ("one again", "m1")] {
+            alice.send(send(content, client_id)).await;
+            assert_eq!(bob.expect_message().await["content"], content);
+        }
+        let state = test.server.rate.lock().unwrap();
+        assert_eq!(state["alice"].client_ids.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn flooding_can_end_the_connection() {
+        let mut config = config();
+        config.rate_limit = 1;
+        config.flood_strikes = 1;
+        config.flood_disconnect = true;
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        bob.send(json!({ "content": "one" })).await;
+        bob.send(json!({ "content": "two" })).await;
+        let mute = bob.expect_event("mute").await;
+        assert_eq!(mute["context"]["automatic"], true);
+        let close = bob.expect_close().await.unwrap();
+        assert_eq!(close.reason, "Flooding");
+
+        let leave = alice.expect_event("leave").await;
+        assert_eq!(leave["context"]["user"], "bob");
+        assert_eq!(leave["context"]["reason"], "rate_limited");
+    }
+
+    #[tokio::test]
+    async fn a_spike_trips_the_breaker_and_calm_relaxes_it() {
+        let mut config = config();
+        config.rate_limit = 0;
+        config.breaker_rate = 20;
+        config.breaker_window = Duration::from_millis(100);
+        config.breaker_cooldown = Duration::from_millis(200);
+        config.breaker_rate_limit = 1;
+        config.rate_window = Duration::from_secs(10);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        for n in 0..10 {
+            bob.send(json!({ "content": format!("spike {}", n) })).await;
+        }
+        let alert = alice.expect_event("circuit_breaker").await;
+        assert_eq!(alert["context"]["tripped"], true);
+        assert_eq!(alert["severity"], "warning");
+
+        // Everyone is held to the tighter limit while it's tripped
+        bob.send(json!({ "content": "first while tripped" })).await;
+        bob.send(json!({ "content": "second while tripped" })).await;
+        bob.expect_error("rate_limited").await;
+
+        let relaxed = alice.expect_event("circuit_breaker").await;
+        assert_eq!(relaxed["context"]["tripped"], false);
+        assert!(!test.server.breaker_tripped.load(Ordering::Relaxed));
+        for n in 0..2 {
+            bob.send(json!({ "content": format!("calm {}", n) })).await;
+        }
+        bob.expect_none(Duration::from_millis(200), |frame| {
+            frame["code"] == "rate_limited"
+        })
+        .await;
+    }
+}
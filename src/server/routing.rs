@@ -0,0 +1,836 @@
+//! Message routing and the per-client frame dispatcher.
+
+use crate::protocol::{
+    ClientEvent, ClientFrame, DEFAULT_CONTENT_TYPE, ServerFrame, ServerMessage, Severity,
+};
+use crate::server::audit::{audit_command, privileged_command, query_audit};
+use crate::server::bans::{ban, unban};
+use crate::server::config::Config;
+use crate::server::contacts::{add_contact, remove_contact};
+use crate::server::content::{normalize_whitespace, strip_html};
+use crate::server::deactivation::deactivate;
+use crate::server::dm_history::{fetch_dm_history, record_dm};
+use crate::server::groups::resolve_conversation;
+use crate::server::history::replay_missed;
+use crate::server::keywords::{keyword_match_frame, subscribe_keywords};
+use crate::server::middleware::Verdict;
+use crate::server::moderation::{
+    announce, ban_many, kick_client, kick_many, mute_until, mute_user, notify_admins,
+    require_admin, set_shadow_ban, set_shadow_mute, unmute_user,
+};
+use crate::server::motd::{motd_frame, set_motd};
+use crate::server::quiet_hours::{is_quiet_for, set_quiet_hours};
+use crate::server::rate_limit::{
+    RateCheck, auto_mute, check_rate, is_duplicate, is_retry, remember_client_id,
+};
+use crate::server::schedule::{cancel_scheduled, list_scheduled, schedule_message};
+use crate::server::signing::check_signature;
+use crate::server::translation::{translate_into, translated_for, translation_targets};
+use crate::server::{Client, DisconnectReason, Server, unix_now, unix_now_ms};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+// Fails with the error code when the message is turned away; the sender,
+// if connected, has been sent the error already
+pub(super) async fn route_message(
+    server: &Server,
+    mut msg: ServerMessage,
+) -> Result<(), &'static str> {
+    let mut clients_guard = server.clients.lock().await;
+
+    if let Some(content_type) = &msg.content_type
+        && !server.config.content_types.contains(content_type)
+    {
+        let message = format!("Unsupported content type {}", content_type);
+        return reject(&clients_guard, &msg.from, "invalid_content_type", message);
+    }
+
+    // The flag vouches for what gets delivered, so it's dropped if anything
+    // below rewrites the signed content
+    let signed = msg.verified.then(|| msg.content.clone());
+
+    let content_type = msg.content_type.as_deref().unwrap_or(DEFAULT_CONTENT_TYPE);
+    if server.config.sanitize_types.contains(content_type) {
+        msg.content = strip_html(&msg.content);
+    }
+    if server.config.normalize_types.contains(content_type) {
+        msg.content = normalize_whitespace(&msg.content);
+    }
+
+    // Acknowledged rather than rejected: the sender only needs to know the
+    // first copy got through
+    if let Some(client_id) = &msg.client_id
+        && is_retry(server, &msg.from, client_id)
+    {
+        if let Some(sender) = clients_guard.get(&msg.from) {
+            sender.send_frame(&ServerFrame::System {
+                severity: Severity::Info,
+                text: "Message already delivered".into(),
+                context: Some(serde_json::json!({
+                    "event": "duplicate",
+                    "client_id": client_id,
+                    "duplicate": true,
+                })),
+            });
+        }
+        return Ok(());
+    }
+
+    if let Some(until) = mute_until(server, &msg.from) {
+        let message = format!(
+            "You are muted until {} (another {}s)",
+            until,
+            until.saturating_sub(unix_now()).max(1)
+        );
+        return reject(&clients_guard, &msg.from, "muted", message);
+    }
+
+    match check_rate(server, &msg.from) {
+        RateCheck::Allowed => {}
+        RateCheck::Limited => {
+            let message = "You are sending messages too fast".to_string();
+            return reject(&clients_guard, &msg.from, "rate_limited", message);
+        }
+        RateCheck::Flooding(duration) => {
+            // The mute notice says why
+            auto_mute(server, &clients_guard, &msg.from, duration);
+            if server.config.flood_disconnect
+                && let Some(kick) = clients_guard
+                    .get_mut(&msg.from)
+                    .and_then(|client| client.kick.take())
+            {
+                let _ = kick.send((DisconnectReason::RateLimited, "Flooding".into()));
+                println!("{} disconnected for flooding", msg.from);
+            }
+            return Err("muted");
+        }
+    }
+
+    if is_duplicate(server, &msg.from, &msg.content) {
+        server.duplicates_suppressed.fetch_add(1, Ordering::Relaxed);
+        let message = "You already sent that message".to_string();
+        return reject(&clients_guard, &msg.from, "duplicate_message", message);
+    }
+
+    let mut flags = Vec::new();
+    for middleware in &server.middleware {
+        match middleware.on_message(server, &mut msg) {
+            Verdict::Pass => {}
+            Verdict::Flag(notice) => flags.push(notice),
+            Verdict::Reject(code, message) => {
+                return reject(&clients_guard, &msg.from, code, message);
+            }
+        }
+    }
+    if signed.is_some_and(|signed| signed != msg.content) {
+        msg.verified = false;
+    }
+    if let Some(client_id) = &msg.client_id {
+        remember_client_id(server, &msg.from, client_id);
+    }
+    for notice in &flags {
+        notify_admins(server, &clients_guard, notice);
+    }
+    server.msgs_total.fetch_add(1, Ordering::Relaxed);
+
+    // Reaches nobody; the echo makes it look delivered
+    if server.shadow_mutes.lock().unwrap().contains(&msg.from) {
+        if let Some(sender) = clients_guard.get(&msg.from) {
+            let _ = sender.tx.send(serde_json::to_string(&msg).unwrap());
+        }
+        return Ok(());
+    }
+
+    // Looks delivered to the sender, but only admins ever see it
+    if server.shadow_bans.lock().unwrap().users.contains(&msg.from) {
+        let frame = ServerFrame::System {
+            severity: Severity::Info,
+            text: format!("Shadow-banned message from {}: {}", msg.from, msg.content),
+            context: Some(serde_json::json!({
+                "event": "shadow",
+                "user": msg.from,
+                "to": msg.to,
+                "shadow": true,
+            })),
+        };
+        notify_admins(server, &clients_guard, &frame);
+        return Ok(());
+    }
+
+    let translations = match &server.translator {
+        Some(translator) => {
+            let wanted = translation_targets(server, &clients_guard, &msg);
+            if wanted.is_empty() {
+                HashMap::new()
+            } else {
+                // Don't hold up everyone else while the translator runs
+                drop(clients_guard);
+                let translations = translate_into(translator.as_ref(), &msg.content, wanted).await;
+                clients_guard = server.clients.lock().await;
+                translations
+            }
+        }
+        None => HashMap::new(),
+    };
+
+    // DMs are addressed to the recipient already
+    let keyword_matches = match &msg.to {
+        Some(_) if msg.members.is_none() => HashMap::new(),
+        _ => server
+            .keywords
+            .lock()
+            .unwrap()
+            .matches(&clients_guard, &msg.from, &msg.content),
+    };
+
+    // Under the clients lock, so a resuming client is either in the map
+    // already or replays this from the log
+    server.history.lock().unwrap().record(&msg);
+
+    if let Some(members) = &msg.members {
+        for member in members.iter().filter(|member| **member != msg.from) {
+            if let Some(client) = clients_guard.get(member) {
+                let delivered = ServerMessage {
+                    suppress_notification: is_quiet_for(server, member, &msg),
+                    translated: translated_for(&translations, client),
+                    ..msg.clone()
+                };
+                let _ = client.tx.send(serde_json::to_string(&delivered).unwrap());
+                if let Some(words) = keyword_matches.get(member) {
+                    client.send_frame(&keyword_match_frame(&msg, words));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    match &msg.to {
+        Some(target) => {
+            if !clients_guard.contains_key(target) {
+                return Ok(());
+            }
+
+            // Cap how many DM threads a user can be in, whichever side
+            // started them; a thread only opens if both are under the cap
+            let cap = server.config.max_dm_partners;
+            let is_full = |user: &str, partner: &str| {
+                clients_guard.get(user).is_some_and(|client| {
+                    !client.dm_partners.contains(partner) && client.dm_partners.len() >= cap
+                })
+            };
+            let message = if is_full(&msg.from, target) {
+                Some(format!("At most {} open DM conversations allowed", cap))
+            } else if is_full(target, &msg.from) {
+                Some(format!("{} has too many open DM conversations", target))
+            } else {
+                None
+            };
+            if let Some(message) = message {
+                return reject(&clients_guard, &msg.from, "too_many_conversations", message);
+            }
+            if let Some(sender) = clients_guard.get_mut(&msg.from) {
+                sender.dm_partners.insert(target.clone());
+            }
+
+            if let Some(recipient) = clients_guard.get_mut(target) {
+                recipient.dm_partners.insert(msg.from.clone());
+                let delivered = ServerMessage {
+                    suppress_notification: is_quiet_for(server, target, &msg),
+                    translated: translated_for(&translations, recipient),
+                    ..msg.clone()
+                };
+                let _ = recipient
+                    .tx
+                    .send(serde_json::to_string(&delivered).unwrap());
+            }
+            record_dm(server, &msg);
+        }
+        None => {
+            // Serialized once per variant: quiet or not, and which
+            // translation, if any
+            let mut variants: HashMap<(bool, Option<&str>), String> = HashMap::new();
+            let quiet_hours = server.quiet_hours.lock().unwrap();
+            let now = unix_now();
+            for (username, client) in clients_guard.iter() {
+                if username == &msg.from {
+                    continue;
+                }
+                let quiet = quiet_hours
+                    .get(username)
+                    .is_some_and(|quiet| quiet.suppresses(username, &msg, now));
+                let lang = client
+                    .lang
+                    .as_deref()
+                    .filter(|lang| translations.contains_key(*lang));
+                let json = variants.entry((quiet, lang)).or_insert_with(|| {
+                    serde_json::to_string(&ServerMessage {
+                        suppress_notification: quiet,
+                        translated: lang.map(|lang| translations[lang].clone()),
+                        ..msg.clone()
+                    })
+                    .unwrap()
+                });
+                let _ = client.tx.send(json.clone());
+                if let Some(words) = keyword_matches.get(username) {
+                    client.send_frame(&keyword_match_frame(&msg, words));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sends the sender, if connected, an error and fails with its code
+pub(super) fn reject(
+    clients: &HashMap<String, Client>,
+    from: &str,
+    code: &'static str,
+    message: String,
+) -> Result<(), &'static str> {
+    if let Some(sender) = clients.get(from) {
+        sender.send_frame(&ServerFrame::Error {
+            code: code.into(),
+            message,
+        });
+    }
+    Err(code)
+}
+
+// Text that isn't UTF-8 is answered with `invalid_utf8` rather than
+// dropping the connection, unless strict UTF-8 is on. False means close.
+pub(super) async fn handle_client_bytes(server: &Server, username: &str, bytes: &[u8]) -> bool {
+    // Anything at all answers a presence probe
+    if !server.config.probe_interval.is_zero()
+        && let Some(client) = server.clients.lock().await.get_mut(username)
+    {
+        client.probed_at = None;
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            handle_client_text(server, username, text).await;
+            true
+        }
+        Err(_) => {
+            reject_invalid_utf8(server, username).await;
+            !server.config.strict_utf8
+        }
+    }
+}
+
+pub(super) async fn reject_invalid_utf8(server: &Server, username: &str) {
+    if let Some(client) = server.clients.lock().await.get(username) {
+        client.send_frame(&ServerFrame::Error {
+            code: "invalid_utf8".into(),
+            message: "Messages must be valid UTF-8".into(),
+        });
+    }
+}
+
+// Everything an authenticated client sends, whatever the transport
+pub(super) async fn handle_client_text(server: &Server, username: &str, text: &str) {
+    match serde_json::from_str::<ClientEvent>(text) {
+        Ok(ClientEvent::Message(parsed)) => {
+            let by_members = parsed.members.is_some();
+            let signing_key = match &parsed.signature {
+                Some(_) => server
+                    .clients
+                    .lock()
+                    .await
+                    .get(username)
+                    .and_then(|client| client.signing_key.clone()),
+                None => None,
+            };
+            let checked = check_line_limits(&server.config, &parsed.content)
+                .and_then(|()| {
+                    check_signature(signing_key.as_deref(), &parsed.content, parsed.signature)
+                })
+                .and_then(|verified| {
+                    resolve_conversation(server, username, parsed.members, parsed.conversation_id)
+                        .map(|conversation| (verified, conversation))
+                });
+            let (verified, conversation) = match checked {
+                Ok(checked) => checked,
+                Err(frame) => {
+                    if let Some(client) = server.clients.lock().await.get(username) {
+                        client.send_frame(&frame);
+                    }
+                    return;
+                }
+            };
+            if by_members
+                && let Some((conversation_id, members)) = &conversation
+                && let Some(client) = server.clients.lock().await.get(username)
+            {
+                client.send_frame(&ServerFrame::Conversation {
+                    conversation_id: conversation_id.clone(),
+                    members: members.clone(),
+                });
+            }
+
+            let (conversation_id, members) = conversation.unzip();
+            // A rejection has already been sent to the sender
+            let _ = route_message(
+                server,
+                ServerMessage {
+                    from: username.to_string(),
+                    // Groups only go to their members
+                    to: if members.is_some() { None } else { parsed.to },
+                    content: parsed.content,
+                    content_type: parsed.content_type,
+                    scheduled: false,
+                    suppress_notification: false,
+                    conversation_id,
+                    members,
+                    translated: None,
+                    verified,
+                    client_id: parsed.client_id,
+                },
+            )
+            .await;
+        }
+        Ok(ClientEvent::Frame(frame)) => handle_client_frame(server, username, frame).await,
+        Err(e) => {
+            eprintln!("Failed to parse message: {}", e);
+        }
+    }
+}
+
+// Every `\n` starts a line, so runs of blank lines count too
+pub(super) fn check_line_limits(config: &Config, content: &str) -> Result<(), ServerFrame> {
+    if config.max_lines > 0 && content.split('\n').count() > config.max_lines {
+        return Err(ServerFrame::Error {
+            code: "too_many_lines".into(),
+            message: format!("Messages can have at most {} lines", config.max_lines),
+        });
+    }
+    if config.max_line_len > 0
+        && content
+            .split('\n')
+            .any(|line| line.chars().count() > config.max_line_len)
+    {
+        return Err(ServerFrame::Error {
+            code: "line_too_long".into(),
+            message: format!("Lines can be at most {} characters", config.max_line_len),
+        });
+    }
+    Ok(())
+}
+
+// JSON is the only encoding; binary ones like MessagePack aren't supported
+pub(super) fn set_encoding(encoding: &str) -> Result<ServerFrame, ServerFrame> {
+    if !encoding.eq_ignore_ascii_case("json") {
+        return Err(ServerFrame::Error {
+            code: "unsupported_encoding".into(),
+            message: format!("Unsupported encoding {}; only json is available", encoding),
+        });
+    }
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: "Encoding set to json".into(),
+        context: Some(serde_json::json!({ "event": "encoding", "encoding": "json" })),
+    })
+}
+
+pub(super) async fn handle_client_frame(server: &Server, username: &str, frame: ClientFrame) {
+    let reply = match run_client_frame(server, username, frame).await {
+        Ok(Some(frame)) | Err(frame) => frame,
+        Ok(None) => return,
+    };
+    if let Some(client) = server.clients.lock().await.get(username) {
+        client.send_frame(&reply);
+    }
+}
+
+// Runs and audits a request. `Ok(None)` when the command already told
+// everyone it concerns.
+pub(super) async fn run_client_frame(
+    server: &Server,
+    username: &str,
+    frame: ClientFrame,
+) -> Result<Option<ServerFrame>, ServerFrame> {
+    let command = privileged_command(&frame);
+
+    let outcome = match frame {
+        ClientFrame::FetchDmHistory { with, limit } => {
+            Ok(Some(fetch_dm_history(server, username, with, limit)))
+        }
+        ClientFrame::Kick { user, reason } => kick_client(server, username, &user, reason)
+            .await
+            .map(|()| None),
+        ClientFrame::Schedule {
+            message,
+            deliver_at,
+        } => schedule_message(server, username, message, deliver_at).map(Some),
+        ClientFrame::ListScheduled => Ok(Some(list_scheduled(server, username))),
+        ClientFrame::Time { client_time_ms } => Ok(Some(ServerFrame::Time {
+            client_time_ms,
+            server_time_ms: unix_now_ms(),
+        })),
+        ClientFrame::AddContact {
+            user,
+            notify_on_online,
+        } => add_contact(server, username, user, notify_on_online)
+            .await
+            .map(Some),
+        ClientFrame::RemoveContact { user } => {
+            remove_contact(server, username, &user).await.map(Some)
+        }
+        ClientFrame::ListContacts => {
+            let clients_guard = server.clients.lock().await;
+            let contacts = server
+                .contacts
+                .lock()
+                .unwrap()
+                .list(username, &clients_guard);
+            Ok(Some(ServerFrame::Contacts { contacts }))
+        }
+        ClientFrame::SubscribeKeywords { words } => {
+            subscribe_keywords(server, username, words).map(Some)
+        }
+        ClientFrame::SetQuietHours {
+            start,
+            end,
+            tz,
+            mentions,
+        } => set_quiet_hours(server, username, &start, &end, &tz, mentions).map(Some),
+        ClientFrame::SetEncoding { encoding } => set_encoding(&encoding).map(Some),
+        ClientFrame::CancelScheduled { id } => cancel_scheduled(server, username, &id).map(Some),
+        ClientFrame::KickMany { users, reason } => {
+            kick_many(server, username, users, reason).await.map(Some)
+        }
+        ClientFrame::BanMany { users, reason } => {
+            ban_many(server, username, users, reason).await.map(Some)
+        }
+        ClientFrame::Mute { user, seconds } => {
+            mute_user(server, username, &user, seconds).await.map(Some)
+        }
+        ClientFrame::Unmute { user } => unmute_user(server, username, &user).await.map(Some),
+        ClientFrame::Ban {
+            user,
+            token,
+            seconds,
+            reason,
+        } => ban(server, username, user, token, seconds, reason)
+            .await
+            .map(Some),
+        ClientFrame::Unban { user, token } => unban(server, username, user, token).map(Some),
+        ClientFrame::Deactivate { user, anonymize } => {
+            deactivate(server, username, &user, anonymize)
+                .await
+                .map(Some)
+        }
+        ClientFrame::ProbeReply => Ok(None),
+        ClientFrame::ListBans => {
+            require_admin(server, username)?;
+            let bans = server.bans.lock().unwrap().active();
+            Ok(Some(ServerFrame::Bans { bans }))
+        }
+        ClientFrame::ShadowBan { user } => set_shadow_ban(server, username, &user, true).map(Some),
+        ClientFrame::ShadowUnban { user } => {
+            set_shadow_ban(server, username, &user, false).map(Some)
+        }
+        ClientFrame::ShadowMute { user } => {
+            set_shadow_mute(server, username, &user, true).map(Some)
+        }
+        ClientFrame::ShadowUnmute { user } => {
+            set_shadow_mute(server, username, &user, false).map(Some)
+        }
+        ClientFrame::Announce { text } => announce(server, username, &text).await.map(|()| None),
+        ClientFrame::SetMotd { text, broadcast } => {
+            set_motd(server, username, &text, broadcast).await.map(Some)
+        }
+        ClientFrame::PresenceAt { timestamp } => require_admin(server, username).map(|()| {
+            Some(ServerFrame::PresenceAt {
+                timestamp,
+                online: server.presence_log.lock().unwrap().online_at(timestamp),
+            })
+        }),
+        ClientFrame::SubscribeMetrics => match require_admin(server, username) {
+            Ok(()) => {
+                if let Some(client) = server.clients.lock().await.get_mut(username) {
+                    client.metrics_subscribed = true;
+                }
+                Ok(None)
+            }
+            Err(frame) => Err(frame),
+        },
+        ClientFrame::Audit {
+            since,
+            until,
+            target,
+            offset,
+            limit,
+        } => require_admin(server, username).map(|()| {
+            Some(query_audit(
+                server,
+                since,
+                until,
+                target.as_deref(),
+                offset,
+                limit,
+            ))
+        }),
+    };
+
+    if let Some(command) = command {
+        audit_command(server, username, command, outcome.as_ref().err());
+    }
+    outcome
+}
+
+pub(super) async fn add_client(server: &Server, username: &str, client: Client) {
+    let mut clients_guard = server.clients.lock().await;
+
+    if client.capabilities.contains("motd")
+        && let Some(frame) = motd_frame(server)
+    {
+        client.send_frame(&frame);
+    }
+
+    if let Some(seq) = client.resume_after {
+        replay_missed(server, username, &client, seq);
+    }
+
+    let slots = server.config.near_capacity_slots;
+    let left = server
+        .config
+        .max_clients
+        .saturating_sub(clients_guard.len() + 1);
+    if slots > 0 && left <= slots {
+        client.send_frame(&ServerFrame::System {
+            severity: Severity::Warning,
+            text: "The server is nearly full".into(),
+            context: Some(serde_json::json!({ "event": "near_capacity", "slots_left": left })),
+        });
+    }
+
+    if let Some(announcement) = &mut *server.announcement.lock().unwrap()
+        && Instant::now() < announcement.until
+        && announcement.seen.insert(username.to_string())
+    {
+        let _ = client.tx.send(announcement.frame.clone());
+    }
+
+    let contacts = server
+        .contacts
+        .lock()
+        .unwrap()
+        .list(username, &clients_guard);
+    if !contacts.is_empty() {
+        client.send_frame(&ServerFrame::Contacts { contacts });
+    }
+
+    if clients_guard.insert(username.to_string(), client).is_none() {
+        server.connected.fetch_add(1, Ordering::Relaxed);
+    }
+    server.keywords.lock().unwrap().presence_changed(username);
+    server.presence_log.lock().unwrap().record(username, true);
+}
+
+// Drops the client entry and closes every DM thread it had open. Only the
+// connection owning `tx` is removed, so a stale connection closing late
+// can't evict the session that replaced it. Returns whether it was removed.
+pub(super) async fn remove_client(
+    server: &Server,
+    username: &str,
+    tx: &mpsc::UnboundedSender<String>,
+) -> bool {
+    let mut clients_guard = server.clients.lock().await;
+    if !clients_guard
+        .get(username)
+        .is_some_and(|client| client.tx.same_channel(tx))
+    {
+        return false;
+    }
+
+    take_client(server, &mut clients_guard, username);
+    true
+}
+
+// The shared way out of the clients map, for disconnects and probe
+// timeouts alike, so the count, DM threads, keyword presence and presence
+// log all see the same leave
+pub(super) fn take_client(
+    server: &Server,
+    clients: &mut HashMap<String, Client>,
+    username: &str,
+) -> Option<Client> {
+    let client = clients.remove(username)?;
+    server.connected.fetch_sub(1, Ordering::Relaxed);
+    let last_seq = server.history.lock().unwrap().last_seq;
+    server.sessions.lock().unwrap().left(username, last_seq);
+    for other in clients.values_mut() {
+        other.dm_partners.remove(username);
+    }
+    server.keywords.lock().unwrap().presence_changed(username);
+    server.presence_log.lock().unwrap().record(username, false);
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn dm(to: &str, content: &str) -> serde_json::Value {
+        json!({ "to": to, "content": content })
+    }
+
+    #[tokio::test]
+    async fn dm_partner_cap_applies_to_both_sides() {
+        let mut config = config();
+        config.max_dm_partners = 1;
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        let mut charlie = test.login("token-charlie").await;
+        test.wait_online("charlie").await;
+
+        alice.send(dm("bob", "hi bob")).await;
+        assert_eq!(bob.expect_message().await["content"], "hi bob");
+
+        // alice is at the cap as the sender
+        alice.send(dm("charlie", "hi charlie")).await;
+        let error = alice.expect_error("too_many_conversations").await;
+        assert!(error["message"].as_str().unwrap().starts_with("At most 1"));
+
+        // bob is at the cap as the recipient
+        charlie.send(dm("bob", "hi bob")).await;
+        let error = charlie.expect_error("too_many_conversations").await;
+        assert_eq!(error["message"], "bob has too many open DM conversations");
+
+        // The existing thread carries on both ways
+        alice.send(dm("bob", "still there?")).await;
+        assert_eq!(bob.expect_message().await["content"], "still there?");
+        bob.send(dm("alice", "yes")).await;
+        assert_eq!(alice.expect_message().await["content"], "yes");
+    }
+
+    #[tokio::test]
+    async fn disconnect_stops_both_tasks_and_empties_the_map() {
+        let test = TestServer::start(config()).await;
+        let bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+        let tx = test.server.clients.lock().await["bob"].tx.clone();
+        assert_eq!(test.server.connected.load(Ordering::Relaxed), 1);
+
+        bob.close().await;
+        test.wait_offline("bob").await;
+        assert_eq!(test.server.connected.load(Ordering::Relaxed), 0);
+        // The writer owned the receiving end; it's gone once the writer is
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while !tx.is_closed() {
+            assert!(Instant::now() < deadline, "writer task still running");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn a_replaced_connection_leaves_the_new_entry_alone() {
+        let test = TestServer::start(config()).await;
+        let old = test.login("token-bob").await;
+        test.wait_online("bob").await;
+        let mut new = test.login("token-bob").await;
+        let mut alice = test.login("token-alice").await;
+        test.wait_online("alice").await;
+
+        old.close().await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(test.server.clients.lock().await.contains_key("bob"));
+        assert_eq!(test.server.connected.load(Ordering::Relaxed), 2);
+
+        alice.send(dm("bob", "still here?")).await;
+        assert_eq!(new.expect_message().await["content"], "still here?");
+    }
+
+    #[tokio::test]
+    async fn only_the_json_encoding_is_accepted() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+
+        alice
+            .send(json!({ "type": "set_encoding", "encoding": "msgpack" }))
+            .await;
+        alice.expect_error("unsupported_encoding").await;
+
+        alice
+            .send(json!({ "type": "set_encoding", "encoding": "json" }))
+            .await;
+        let ack = alice.expect_event("encoding").await;
+        assert_eq!(ack["context"]["encoding"], "json");
+
+        // Frames keep coming as JSON text either way
+        alice.send(dm("alice", "still json")).await;
+        assert_eq!(alice.expect_message().await["content"], "still json");
+    }
+
+    #[tokio::test]
+    async fn content_type_round_trips_and_unknown_types_are_rejected() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        alice
+            .send(json!({ "to": "bob", "content": "# hi", "content_type": "text/markdown" }))
+            .await;
+        assert_eq!(bob.expect_message().await["content_type"], "text/markdown");
+
+        alice
+            .send(json!({ "to": "bob", "content": "<b>", "content_type": "text/html" }))
+            .await;
+        alice.expect_error("invalid_content_type").await;
+
+        alice.send(dm("bob", "plain")).await;
+        let plain = bob.expect_message().await;
+        assert_eq!(plain["content"], "plain");
+        assert!(plain.get("content_type").is_none(), "{}", plain);
+    }
+
+    #[tokio::test]
+    async fn line_count_and_line_length_are_limited() {
+        let mut config = config();
+        config.max_lines = 3;
+        config.max_line_len = 10;
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        alice.send(dm("bob", "a\n\n\nb")).await;
+        alice.expect_error("too_many_lines").await;
+        alice.send(dm("bob", "short\nthis line is too long")).await;
+        alice.expect_error("line_too_long").await;
+
+        alice.send(dm("bob", "one\ntwo\nthree")).await;
+        assert_eq!(bob.expect_message().await["content"], "one\ntwo\nthree");
+    }
+
+    #[tokio::test]
+    async fn time_requests_echo_the_client_timestamp() {
+        let mut config = config();
+        config.rate_limit = 1;
+        let test = TestServer::start(config).await;
+        let mut alice = test.connect().await;
+        alice.send(json!({ "token": "token-alice" })).await;
+        let auth = alice.expect_type("auth_success").await;
+        let auth_time = auth["server_time_ms"].as_u64().unwrap();
+        assert!(auth_time.abs_diff(unix_now_ms()) < 60_000);
+
+        // Rate limits don't apply
+        let stamps = [1_700_000_000_123_u64, 0, u64::MAX];
+        for stamp in stamps {
+            alice
+                .send(json!({ "type": "time", "client_time_ms": stamp }))
+                .await;
+        }
+        for stamp in stamps {
+            let reply = alice.expect_type("time").await;
+            assert_eq!(reply["client_time_ms"].as_u64(), Some(stamp));
+            assert!(reply["server_time_ms"].as_u64().unwrap() >= auth_time);
+        }
+    }
+}
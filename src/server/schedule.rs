@@ -0,0 +1,180 @@
+//! Scheduled messages.
+
+use crate::protocol::{ChatMessage, ScheduledMessage, ServerFrame, ServerMessage, Severity};
+use crate::server::routing::{check_line_limits, route_message};
+use crate::server::{Server, unix_now, write_atomically};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub(super) const MAX_SCHEDULED_PER_USER: usize = 50;
+
+#[derive(Default)]
+pub(super) struct Schedule {
+    pub(super) messages: Vec<ScheduledMessage>,
+    pub(super) file: Option<String>,
+}
+
+impl Schedule {
+    pub(super) fn load(path: &str) -> anyhow::Result<Schedule> {
+        let messages = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("parsing scheduled messages {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => anyhow::bail!("reading scheduled messages {}: {}", path, e),
+        };
+        Ok(Schedule {
+            messages,
+            file: Some(path.to_string()),
+        })
+    }
+
+    pub(super) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_atomically(path, &self.messages) {
+            eprintln!("saving scheduled messages to {} failed: {}", path, e);
+        }
+    }
+
+    pub(super) fn take_due(&mut self, now: u64) -> Vec<ScheduledMessage> {
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition(|message| message.deliver_at <= now);
+        self.messages = pending;
+        if !due.is_empty() {
+            self.save();
+        }
+        due
+    }
+}
+
+pub(super) fn schedule_message(
+    server: &Server,
+    username: &str,
+    message: ChatMessage,
+    deliver_at: u64,
+) -> Result<ServerFrame, ServerFrame> {
+    let now = unix_now();
+    let horizon = server.config.schedule_horizon.as_secs();
+    if deliver_at <= now || deliver_at > now + horizon {
+        return Err(ServerFrame::Error {
+            code: "invalid_schedule".into(),
+            message: format!("Delivery must be within the next {}s", horizon),
+        });
+    }
+    if let Some(content_type) = &message.content_type
+        && !server.config.content_types.contains(content_type)
+    {
+        return Err(ServerFrame::Error {
+            code: "invalid_content_type".into(),
+            message: format!("Unsupported content type {}", content_type),
+        });
+    }
+    check_line_limits(&server.config, &message.content)?;
+    if message.members.is_some() || message.conversation_id.is_some() {
+        return Err(ServerFrame::Error {
+            code: "invalid_schedule".into(),
+            message: "Group messages can't be scheduled".into(),
+        });
+    }
+
+    let mut schedule = server.schedule.lock().unwrap();
+    let pending = schedule
+        .messages
+        .iter()
+        .filter(|scheduled| scheduled.from == username)
+        .count();
+    if pending >= MAX_SCHEDULED_PER_USER {
+        return Err(ServerFrame::Error {
+            code: "too_many_scheduled".into(),
+            message: format!(
+                "You can have at most {} scheduled messages",
+                MAX_SCHEDULED_PER_USER
+            ),
+        });
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    schedule.messages.push(ScheduledMessage {
+        id: id.clone(),
+        from: username.to_string(),
+        to: message.to,
+        content: message.content,
+        content_type: message.content_type,
+        deliver_at,
+    });
+    schedule.save();
+    Ok(ServerFrame::Scheduled { id, deliver_at })
+}
+
+pub(super) fn list_scheduled(server: &Server, username: &str) -> ServerFrame {
+    let mut messages: Vec<ScheduledMessage> = server
+        .schedule
+        .lock()
+        .unwrap()
+        .messages
+        .iter()
+        .filter(|scheduled| scheduled.from == username)
+        .cloned()
+        .collect();
+    messages.sort_by_key(|scheduled| scheduled.deliver_at);
+    ServerFrame::ScheduledList { messages }
+}
+
+// Other users' ids look the same as unknown ones
+pub(super) fn cancel_scheduled(
+    server: &Server,
+    username: &str,
+    id: &str,
+) -> Result<ServerFrame, ServerFrame> {
+    let mut schedule = server.schedule.lock().unwrap();
+    let Some(index) = schedule
+        .messages
+        .iter()
+        .position(|scheduled| scheduled.id == id && scheduled.from == username)
+    else {
+        return Err(ServerFrame::Error {
+            code: "no_such_scheduled_message".into(),
+            message: "No pending scheduled message with that id".into(),
+        });
+    };
+    schedule.messages.remove(index);
+    schedule.save();
+
+    Ok(ServerFrame::System {
+        severity: Severity::Info,
+        text: "Scheduled message cancelled".into(),
+        context: Some(serde_json::json!({ "event": "unschedule", "id": id })),
+    })
+}
+
+// Due messages are removed and saved before they're routed, so a crash in
+// between drops them rather than sending them twice after the restart
+pub(super) async fn run_scheduler(server: Arc<Server>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        let due = server.schedule.lock().unwrap().take_due(unix_now());
+        for scheduled in due {
+            // A rejection has already been sent to the sender
+            let _ = route_message(
+                &server,
+                ServerMessage {
+                    from: scheduled.from,
+                    to: scheduled.to,
+                    content: scheduled.content,
+                    content_type: scheduled.content_type,
+                    scheduled: true,
+                    suppress_notification: false,
+                    conversation_id: None,
+                    members: None,
+                    translated: None,
+                    verified: false,
+                    client_id: None,
+                },
+            )
+            .await;
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Reconnect tokens.
+
+use crate::protocol::ServerFrame;
+use crate::server::{Server, unix_now, write_atomically};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(super) struct SessionToken {
+    pub(super) user: String,
+    // Unix seconds, so it can be saved
+    pub(super) expires: u64,
+    // The newest message history seq the user had been sent
+    pub(super) last_seq: u64,
+}
+
+// Short-lived, single-use reconnect tokens
+#[derive(Default)]
+pub(super) struct SessionRegistry {
+    pub(super) tokens: HashMap<String, SessionToken>,
+    pub(super) file: Option<String>,
+}
+
+impl SessionRegistry {
+    // Tokens that expired while the server was down are dropped
+    pub(super) fn load(path: &str) -> anyhow::Result<SessionRegistry> {
+        let mut tokens: HashMap<String, SessionToken> = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("parsing sessions {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => anyhow::bail!("reading sessions {}: {}", path, e),
+        };
+        let now = unix_now();
+        tokens.retain(|_, session| session.expires > now);
+        Ok(SessionRegistry {
+            tokens,
+            file: Some(path.to_string()),
+        })
+    }
+
+    pub(super) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_atomically(path, &self.tokens) {
+            eprintln!("saving sessions to {} failed: {}", path, e);
+        }
+    }
+
+    // Not saved here; the refresh loop saves once per round
+    pub(super) fn issue(&mut self, username: &str, ttl: Duration, last_seq: u64) -> String {
+        let now = unix_now();
+        self.tokens.retain(|_, session| session.expires > now);
+
+        let token = uuid::Uuid::new_v4().to_string();
+        let session = SessionToken {
+            user: username.to_string(),
+            expires: now + ttl.as_secs(),
+            last_seq,
+        };
+        self.tokens.insert(token.clone(), session);
+        token
+    }
+
+    // Looks without using it up; see `redeem`
+    pub(super) fn peek(&self, token: &str) -> Option<SessionToken> {
+        self.tokens
+            .get(token)
+            .filter(|session| session.expires > unix_now())
+            .cloned()
+    }
+
+    // Saved right away, so a used token can't be replayed after a restart
+    pub(super) fn redeem(&mut self, token: &str) -> Option<SessionToken> {
+        let session = self.tokens.remove(token)?;
+        self.save();
+        (session.expires > unix_now()).then_some(session)
+    }
+
+    // Moves the user's tokens up to `last_seq` when they leave, so resuming
+    // replays only what came after
+    pub(super) fn left(&mut self, username: &str, last_seq: u64) {
+        let mut changed = false;
+        for session in self.tokens.values_mut().filter(|s| s.user == username) {
+            session.last_seq = last_seq;
+            changed = true;
+        }
+        if changed {
+            self.save();
+        }
+    }
+}
+
+pub(super) async fn run_token_refresh(server: Arc<Server>) {
+    let mut ticker = tokio::time::interval(server.config.token_refresh);
+    let ttl = server.config.reconnect_token_ttl;
+
+    loop {
+        ticker.tick().await;
+        let clients_guard = server.clients.lock().await;
+        let last_seq = server.history.lock().unwrap().last_seq;
+        let mut sessions = server.sessions.lock().unwrap();
+        for (username, client) in clients_guard
+            .iter()
+            .filter(|(_, client)| client.capabilities.contains("token_refresh"))
+        {
+            client.send_frame(&ServerFrame::TokenRefresh {
+                token: sessions.issue(username, ttl, last_seq),
+                expires_in: ttl.as_secs(),
+            });
+        }
+        sessions.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn rotated_tokens_reconnect_once_and_expired_ones_fail() {
+        let mut config = config();
+        config.token_refresh = Duration::from_secs(1);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let refresh = alice.expect_type("token_refresh").await;
+        let token = refresh["token"].as_str().unwrap().to_string();
+        assert!(refresh["expires_in"].as_u64().unwrap() > 0);
+        alice.close().await;
+        test.wait_offline("alice").await;
+
+        let alice = test.login(&token).await;
+        test.wait_online("alice").await;
+        alice.close().await;
+        test.wait_offline("alice").await;
+
+        // Single use
+        let mut again = test.connect().await;
+        again.send(json!({ "token": token })).await;
+        assert_eq!(again.recv().await.unwrap()["type"], "auth_failed");
+
+        test.server.sessions.lock().unwrap().tokens.insert(
+            "stale".to_string(),
+            SessionToken {
+                user: "alice".to_string(),
+                expires: unix_now() - 1,
+                last_seq: 0,
+            },
+        );
+        let mut stale = test.connect().await;
+        stale.send(json!({ "token": "stale" })).await;
+        assert_eq!(stale.recv().await.unwrap()["type"], "auth_failed");
+    }
+
+    #[tokio::test]
+    async fn a_refused_login_leaves_the_reconnect_token_usable() {
+        let mut config = config();
+        config.token_refresh = Duration::from_secs(1);
+        config.max_clients = 1;
+        let test = TestServer::start(config).await;
+        let mut bob = test.login("token-bob").await;
+        let refresh = bob.expect_type("token_refresh").await;
+        let token = refresh["token"].as_str().unwrap();
+        bob.close().await;
+        test.wait_offline("bob").await;
+
+        let alice = test.login("token-alice").await;
+        let mut full = test.connect().await;
+        full.send(json!({ "token": token })).await;
+        assert_eq!(full.recv().await.unwrap()["type"], "server_full");
+        alice.close().await;
+        test.wait_offline("alice").await;
+
+        test.login(token).await;
+    }
+}
@@ -0,0 +1,83 @@
+//! Closing every connection when the server is stopped.
+
+use crate::server::{DisconnectReason, Server};
+use std::time::{Duration, Instant};
+
+// How long writers get to flush before the process exits anyway
+pub(super) const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+pub(super) async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// Each connection is closed like a kick, so it leaves with
+// `server_shutdown` and flushes what's queued first
+pub(super) async fn shut_down(server: &Server) {
+    let kicks: Vec<_> = server
+        .clients
+        .lock()
+        .await
+        .values_mut()
+        .filter_map(|client| client.kick.take())
+        .collect();
+    println!("Shutting down, closing {} connections", kicks.len());
+    for kick in kicks {
+        let _ = kick.send((
+            DisconnectReason::ServerShutdown,
+            "Server shutting down".into(),
+        ));
+    }
+
+    let deadline = Instant::now() + SHUTDOWN_GRACE;
+    while !server.clients.lock().await.is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    #[tokio::test]
+    async fn everyone_is_closed_with_server_shutdown() {
+        let test = TestServer::start(config()).await;
+        let mut console = test.connect().await;
+        console
+            .send(json!({ "token": "token-alice", "console": true }))
+            .await;
+        console.expect_type("auth_success").await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+
+        shut_down(&test.server).await;
+        assert!(test.server.clients.lock().await.is_empty());
+        for client in [&mut alice, &mut bob] {
+            let close = client.expect_close().await.unwrap();
+            assert_eq!(close.code, CloseCode::Away);
+            assert_eq!(close.reason, "Server shutting down");
+        }
+        loop {
+            let line = console.recv_text().await.unwrap();
+            if line.starts_with("[presence] bob left") {
+                assert_eq!(line, "[presence] bob left (server_shutdown)");
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! The Server-Sent Events fallback for receive-only clients.
+
+use crate::protocol::AuthReply;
+use crate::server::auth::{resolve_token, spend_reconnect_token};
+use crate::server::bans::ban_message;
+use crate::server::broadcast::broadcast_presence;
+use crate::server::capacity::check_capacity;
+use crate::server::http::{http_response, read_request_head};
+use crate::server::names::{name_collides, normalize_name};
+use crate::server::routing::{add_client, remove_client};
+use crate::server::{Client, ConnectionInfo, DisconnectReason, Server};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+// Receive-only session: the clients map sender feeds the response body as
+// `data:` lines, so routing and presence treat it like any other connection.
+pub(super) async fn handle_sse(stream: TcpStream, server: Arc<Server>) {
+    let peer = stream.peer_addr().ok();
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    let Some((request_line, headers)) = read_request_head(&mut reader).await else {
+        return;
+    };
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    let token = request_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(query_token)
+        .unwrap_or_default();
+    let user = match resolve_token(&server, token) {
+        Some(user) => user,
+        None => {
+            let response = http_response("401 Unauthorized", "Invalid token");
+            let _ = write_half.write_all(response.as_bytes()).await;
+            return;
+        }
+    };
+    let username = normalize_name(&server.config, user.username);
+
+    if let Some(message) = ban_message(&server, token, &username) {
+        let response = http_response("403 Forbidden", &message);
+        let _ = write_half.write_all(response.as_bytes()).await;
+        return;
+    }
+
+    if name_collides(&server, &username).await {
+        let response = http_response("409 Conflict", "Name already in use");
+        let _ = write_half.write_all(response.as_bytes()).await;
+        return;
+    }
+
+    if let Some(retry_after_ms) = check_capacity(&server).await {
+        let body = serde_json::to_string(&AuthReply::ServerFull {
+            message: "Server is full".into(),
+            retry_after_ms,
+        })
+        .unwrap();
+        let response = format!(
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            retry_after_ms.div_ceil(1000),
+            body.len(),
+            body
+        );
+        let _ = write_half.write_all(response.as_bytes()).await;
+        return;
+    }
+
+    if user.reconnect && !spend_reconnect_token(&server, token) {
+        let response = http_response("401 Unauthorized", "Invalid token");
+        let _ = write_half.write_all(response.as_bytes()).await;
+        return;
+    }
+
+    if write_half
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    println!("{} connected (sse)", username);
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (mut client, mut kicked) = Client::new(tx.clone());
+    client.connection = ConnectionInfo {
+        transport: "sse",
+        peer,
+        user_agent: header("user-agent"),
+        origin: header("origin"),
+    };
+    client.tags = user.tags;
+    client.signing_key = user.signing_key;
+    client.resume_after = user.resume_after;
+    client.templates = server.catalog.resolve(None, &server.config.default_lang);
+    add_client(&server, &username, client).await;
+
+    broadcast_presence(&server, &username, true, None).await;
+
+    let mut heartbeat = tokio::time::interval(server.config.sse_heartbeat);
+    let mut scratch = [0u8; 64];
+    let reason = loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                let Some(msg) = msg else { break DisconnectReason::ConnectionLost };
+                let event = format!("data: {}\n\n", msg);
+                if write_half.write_all(event.as_bytes()).await.is_err() {
+                    break DisconnectReason::WriteFailed;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if write_half.write_all(b": heartbeat\n\n").await.is_err() {
+                    break DisconnectReason::WriteFailed;
+                }
+            }
+            kick = &mut kicked => {
+                break kick.map_or(DisconnectReason::Kicked, |(why, _)| why);
+            }
+            read = reader.read(&mut scratch) => {
+                // Nothing is expected from the client; EOF means it went away
+                match read {
+                    Ok(0) => break DisconnectReason::ClientClosed,
+                    Err(_) => break DisconnectReason::ConnectionLost,
+                    Ok(_) => {}
+                }
+            }
+        }
+    };
+
+    println!("{} disconnected ({})", username, reason.as_str());
+    if remove_client(&server, &username, &tx).await {
+        broadcast_presence(&server, &username, false, Some(reason)).await;
+    }
+}
+
+pub(super) fn query_token(target: &str) -> Option<&str> {
+    let (_, query) = target.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "token")
+        .map(|(_, value)| value)
+}
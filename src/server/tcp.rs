@@ -0,0 +1,267 @@
+//! Raw TCP line clients.
+
+use crate::protocol::AuthReply;
+use crate::server::auth::{auth_success, authenticate};
+use crate::server::broadcast::broadcast_presence;
+use crate::server::heartbeat::{heartbeat_frame, heartbeat_timer};
+use crate::server::routing::{add_client, handle_client_bytes, remove_client};
+use crate::server::ws::MAX_WARM_UP_FRAMES;
+use crate::server::{Client, ConnectionInfo, DisconnectReason, Server};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+// For clients that can't speak WebSocket: the first line is the auth
+// message, then the same JSON frames as over the WebSocket, one per line
+pub(super) async fn run_tcp_listener(listener: TcpListener, server: Arc<Server>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(handle_tcp(stream, server.clone()));
+    }
+}
+
+pub(super) async fn handle_tcp(stream: TcpStream, server: Arc<Server>) {
+    let peer = stream.peer_addr().ok();
+    let (read_half, mut write_half) = stream.into_split();
+    // Split on bytes, not `lines()`, so a line that isn't UTF-8 can be
+    // answered instead of ending the session
+    let mut lines = BufReader::new(read_half).split(b'\n');
+
+    let mut attempts = 0;
+    let auth = loop {
+        let Ok(Some(line)) = lines.next_segment().await else {
+            return;
+        };
+        attempts += 1;
+        let (reply, auth) = match authenticate(&server, &String::from_utf8_lossy(&line)).await {
+            Ok(auth) => (auth_success(&auth), Some(auth)),
+            Err(reply) => (reply, None),
+        };
+        if !tcp_send(&mut write_half, &serde_json::to_string(&reply).unwrap()).await {
+            return;
+        }
+        match (auth, reply) {
+            (Some(auth), _) => break auth,
+            (None, AuthReply::AuthRequired { .. }) if attempts < server.config.preauth_attempts => {
+            }
+            (None, _) => return,
+        }
+    };
+    let username = auth.username;
+
+    println!("{} connected over TCP", username);
+
+    let mut early = Vec::new();
+    if !server.config.warm_up.is_zero() {
+        let warm_up = tokio::time::sleep(server.config.warm_up);
+        tokio::pin!(warm_up);
+        loop {
+            tokio::select! {
+                biased;
+                _ = &mut warm_up => break,
+                line = lines.next_segment() => match line {
+                    Ok(Some(line)) if early.len() < MAX_WARM_UP_FRAMES => early.push(line),
+                    Ok(Some(_)) => {
+                        println!("{} sent too much during warm-up", username);
+                        return;
+                    }
+                    Ok(None) | Err(_) => {
+                        println!("{} left during warm-up", username);
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (mut client, mut kicked) = Client::new(tx.clone());
+    client.connection = ConnectionInfo {
+        transport: "tcp",
+        peer,
+        ..ConnectionInfo::default()
+    };
+    client.capabilities = auth.capabilities;
+    client.tags = auth.tags;
+    client.signing_key = auth.signing_key;
+    client.resume_after = auth.resume_after;
+    client.lang = auth.lang.as_deref().map(str::to_lowercase);
+    client.templates = server
+        .catalog
+        .resolve(auth.lang.as_deref(), &server.config.default_lang);
+    let wants_heartbeat = client.capabilities.contains("server_heartbeat");
+    add_client(&server, &username, client).await;
+
+    broadcast_presence(&server, &username, true, None).await;
+
+    // `None` when the channel closed, as for WebSockets. There are no pings
+    // here, so heartbeats carry no latency.
+    let (stop_writer, mut stop) = oneshot::channel::<()>();
+    let writer_server = server.clone();
+    let mut writer = tokio::spawn(async move {
+        let mut kick_pending = true;
+        let mut stop_pending = true;
+        let mut heartbeat = heartbeat_timer(&writer_server.config);
+        loop {
+            tokio::select! {
+                msg = rx.recv() => {
+                    let Some(msg) = msg else { break None };
+                    if !tcp_send(&mut write_half, &msg).await {
+                        break Some(DisconnectReason::WriteFailed);
+                    }
+                }
+                _ = heartbeat.tick(), if wants_heartbeat
+                    && !writer_server.config.heartbeat_interval.is_zero() => {
+                    let frame = heartbeat_frame(&writer_server, None, rx.len());
+                    if !tcp_send(&mut write_half, &frame).await {
+                        break Some(DisconnectReason::WriteFailed);
+                    }
+                }
+                _ = &mut stop, if stop_pending => {
+                    // Flush what's queued but take nothing new, even if a
+                    // sender is still held somewhere
+                    stop_pending = false;
+                    rx.close();
+                }
+                reason = &mut kicked, if kick_pending => {
+                    kick_pending = false;
+                    if let Ok((why, _)) = reason {
+                        break Some(why);
+                    }
+                }
+            }
+        }
+    });
+
+    let reading = async {
+        for line in early.iter().filter(|line| !line.trim_ascii().is_empty()) {
+            if !handle_client_bytes(&server, &username, line).await {
+                return DisconnectReason::ProtocolError;
+            }
+        }
+        loop {
+            match lines.next_segment().await {
+                Ok(Some(line)) => {
+                    if !line.trim_ascii().is_empty()
+                        && !handle_client_bytes(&server, &username, &line).await
+                    {
+                        return DisconnectReason::ProtocolError;
+                    }
+                }
+                Ok(None) => return DisconnectReason::ClientClosed,
+                Err(_) => return DisconnectReason::ConnectionLost,
+            }
+        }
+    };
+    let (reason, writer_done) = tokio::select! {
+        reason = reading => (reason, false),
+        reason = &mut writer => {
+            (reason.ok().flatten().unwrap_or(DisconnectReason::ConnectionLost), true)
+        }
+    };
+
+    // Dropping the map entry and `stop` closes the channel and ends the writer
+    let removed = remove_client(&server, &username, &tx).await;
+    drop(stop_writer);
+    if !writer_done {
+        let _ = writer.await;
+    }
+    println!("{} disconnected ({})", username, reason.as_str());
+    if removed {
+        broadcast_presence(&server, &username, false, Some(reason)).await;
+    }
+}
+
+pub(super) async fn tcp_send(write: &mut tokio::net::tcp::OwnedWriteHalf, line: &str) -> bool {
+    write
+        .write_all(format!("{}\n", line).as_bytes())
+        .await
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::{Value, json};
+    use std::time::Duration;
+    use tokio::io::Lines;
+    use tokio::net::tcp::OwnedReadHalf;
+
+    struct TcpClient {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        write: tokio::net::tcp::OwnedWriteHalf,
+    }
+
+    impl TcpClient {
+        async fn connect(addr: std::net::SocketAddr) -> TcpClient {
+            let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+            TcpClient {
+                lines: BufReader::new(read).lines(),
+                write,
+            }
+        }
+
+        async fn send(&mut self, frame: Value) {
+            assert!(tcp_send(&mut self.write, &frame.to_string()).await);
+        }
+
+        async fn expect(&mut self, matches: impl Fn(&Value) -> bool) -> Value {
+            loop {
+                let line = tokio::time::timeout(Duration::from_secs(3), self.lines.next_line())
+                    .await
+                    .expect("no line in time")
+                    .unwrap()
+                    .expect("connection closed");
+                let frame: Value = serde_json::from_str(&line).unwrap();
+                if matches(&frame) {
+                    return frame;
+                }
+            }
+        }
+    }
+
+    async fn start() -> (TestServer, std::net::SocketAddr) {
+        let test = TestServer::start(config()).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run_tcp_listener(listener, test.server.clone()));
+        (test, addr)
+    }
+
+    #[tokio::test]
+    async fn tcp_and_websocket_clients_talk_to_each_other() {
+        let (test, tcp_addr) = start().await;
+        let mut bob = TcpClient::connect(tcp_addr).await;
+        bob.send(json!({ "token": "token-bob" })).await;
+        bob.expect(|frame| frame["type"] == "auth_success").await;
+        let mut alice = test.login("token-alice").await;
+        test.wait_online("bob").await;
+
+        alice
+            .send(json!({ "to": "bob", "content": "over ws" }))
+            .await;
+        let msg = bob.expect(|frame| frame.get("from").is_some()).await;
+        assert_eq!(msg["from"], "alice");
+        assert_eq!(msg["content"], "over ws");
+
+        bob.send(json!({ "to": null, "content": "over tcp" })).await;
+        let msg = alice.expect_message().await;
+        assert_eq!(msg["from"], "bob");
+        assert_eq!(msg["content"], "over tcp");
+    }
+
+    #[tokio::test]
+    async fn tcp_clients_get_the_same_errors() {
+        let (_test, tcp_addr) = start().await;
+        let mut bob = TcpClient::connect(tcp_addr).await;
+        bob.send(json!({ "token": "token-nobody" })).await;
+        bob.expect(|frame| frame["type"] == "auth_failed").await;
+
+        let mut bob = TcpClient::connect(tcp_addr).await;
+        bob.send(json!({ "token": "token-bob" })).await;
+        bob.expect(|frame| frame["type"] == "auth_success").await;
+        bob.send(json!({ "type": "kick", "user": "alice" })).await;
+        bob.expect(|frame| frame["code"] == "forbidden").await;
+    }
+}
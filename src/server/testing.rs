@@ -0,0 +1,213 @@
+//! Test helpers: a server on an ephemeral port and a WebSocket client that
+//! talks to it like a real one.
+
+use crate::server::config::Config;
+use crate::server::{Server, serve};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+
+// How long a test waits for a frame before failing
+const RECV_TIMEOUT: Duration = Duration::from_secs(3);
+
+// The environment's config with alice as the only admin and nothing bound
+// to fixed ports
+pub(super) fn config() -> Config {
+    let mut config = Config::from_env();
+    config.addr = "127.0.0.1:0".to_string();
+    config.admins = HashSet::from(["alice".to_string()]);
+    config.tcp_addr = None;
+    #[cfg(feature = "irc")]
+    {
+        config.irc_addr = "127.0.0.1:0".to_string();
+    }
+    config
+}
+
+// A path in a fresh temporary directory, for state files
+pub(super) fn temp_file(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("push-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name).to_string_lossy().into_owned()
+}
+
+pub(super) struct TestServer {
+    pub(super) addr: std::net::SocketAddr,
+    pub(super) server: Arc<Server>,
+}
+
+impl TestServer {
+    pub(super) async fn start(config: Config) -> TestServer {
+        TestServer::start_with(Server::new(config).unwrap()).await
+    }
+
+    // For tests that set up state before any connection comes in
+    pub(super) async fn start_with(server: Server) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(server);
+        tokio::spawn(serve(listener, server.clone()));
+        TestServer { addr, server }
+    }
+
+    pub(super) async fn connect(&self) -> TestClient {
+        let (ws, _) = connect_async(format!("ws://{}", self.addr)).await.unwrap();
+        TestClient { ws }
+    }
+
+    // Connects and authenticates, failing the test unless it succeeds
+    pub(super) async fn login(&self, token: &str) -> TestClient {
+        self.login_with(json!({ "token": token })).await
+    }
+
+    pub(super) async fn login_with(&self, auth: Value) -> TestClient {
+        let mut client = self.connect().await;
+        client.send(auth).await;
+        let reply = client.recv().await.expect("no auth reply");
+        assert_eq!(reply["type"], "auth_success", "{}", reply);
+        client
+    }
+
+    // Sends a raw HTTP request and returns the whole response
+    pub(super) async fn http(&self, request: &str) -> String {
+        let mut stream = TcpStream::connect(self.addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(RECV_TIMEOUT, stream.read_to_string(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        response
+    }
+
+    // Waits until `user` is in the roster, or fails the test
+    pub(super) async fn wait_online(&self, user: &str) {
+        let deadline = Instant::now() + RECV_TIMEOUT;
+        while !self.server.clients.lock().await.contains_key(user) {
+            assert!(Instant::now() < deadline, "{} never came online", user);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    // Waits until `user` has left the roster, or fails the test
+    pub(super) async fn wait_offline(&self, user: &str) {
+        let deadline = Instant::now() + RECV_TIMEOUT;
+        while self.server.clients.lock().await.contains_key(user) {
+            assert!(Instant::now() < deadline, "{} never went offline", user);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+pub(super) struct TestClient {
+    pub(super) ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl TestClient {
+    pub(super) async fn send(&mut self, frame: Value) {
+        self.ws
+            .send(Message::Text(frame.to_string().into()))
+            .await
+            .unwrap();
+    }
+
+    // The next text frame; `None` once the connection closed or nothing
+    // arrived in time
+    pub(super) async fn recv(&mut self) -> Option<Value> {
+        loop {
+            match tokio::time::timeout(RECV_TIMEOUT, self.ws.next()).await {
+                Ok(Some(Ok(Message::Text(text)))) => {
+                    return Some(serde_json::from_str(text.as_ref()).unwrap());
+                }
+                Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) | Err(_) => {
+                    return None;
+                }
+                Ok(Some(Ok(_))) => {}
+            }
+        }
+    }
+
+    // The next text frame as is, for the plain-text console
+    pub(super) async fn recv_text(&mut self) -> Option<String> {
+        loop {
+            match tokio::time::timeout(RECV_TIMEOUT, self.ws.next()).await {
+                Ok(Some(Ok(Message::Text(text)))) => return Some(text.to_string()),
+                Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) | Err(_) => {
+                    return None;
+                }
+                Ok(Some(Ok(_))) => {}
+            }
+        }
+    }
+
+    // Skips frames until one matches, failing the test if none does
+    pub(super) async fn expect(&mut self, matches: impl Fn(&Value) -> bool) -> Value {
+        loop {
+            match self.recv().await {
+                Some(frame) if matches(&frame) => return frame,
+                Some(_) => {}
+                None => panic!("connection ended without the expected frame"),
+            }
+        }
+    }
+
+    // A chat message, which carries no `type`
+    pub(super) async fn expect_message(&mut self) -> Value {
+        self.expect(|frame| frame.get("type").is_none() && frame.get("from").is_some())
+            .await
+    }
+
+    pub(super) async fn expect_type(&mut self, kind: &str) -> Value {
+        self.expect(|frame| frame["type"] == kind).await
+    }
+
+    pub(super) async fn expect_error(&mut self, code: &str) -> Value {
+        self.expect(|frame| frame["type"] == "error" && frame["code"] == code)
+            .await
+    }
+
+    // A `system` notice whose context names `event`
+    pub(super) async fn expect_event(&mut self, event: &str) -> Value {
+        self.expect(|frame| frame["type"] == "system" && frame["context"]["event"] == event)
+            .await
+    }
+
+    // Fails the test if a matching frame arrives within `wait`
+    pub(super) async fn expect_none(&mut self, wait: Duration, matches: impl Fn(&Value) -> bool) {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            match tokio::time::timeout_at(deadline, self.ws.next()).await {
+                Ok(Some(Ok(Message::Text(text)))) => {
+                    let frame: Value = serde_json::from_str(text.as_ref()).unwrap();
+                    assert!(!matches(&frame), "unexpected frame {}", frame);
+                }
+                Ok(Some(Ok(_))) => {}
+                Ok(Some(Err(_))) | Ok(None) | Err(_) => return,
+            }
+        }
+    }
+
+    // Reads until the server closes the connection and returns its close
+    // frame, skipping anything queued before it
+    pub(super) async fn expect_close(&mut self) -> Option<CloseFrame> {
+        loop {
+            match tokio::time::timeout(RECV_TIMEOUT, self.ws.next()).await {
+                Ok(Some(Ok(Message::Close(frame)))) => return frame,
+                Ok(Some(Ok(_))) => {}
+                Ok(Some(Err(_))) | Ok(None) => return None,
+                Err(_) => panic!("connection still open"),
+            }
+        }
+    }
+
+    pub(super) async fn close(mut self) {
+        let _ = self.ws.close(None).await;
+    }
+}
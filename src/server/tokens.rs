@@ -0,0 +1,57 @@
+//! The token store: who each static token belongs to.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Clone)]
+pub(super) struct TokenEntry {
+    pub(super) user: String,
+    #[serde(default)]
+    pub(super) tags: Vec<String>,
+    // Key for the user's signed messages; unset means they can't sign
+    #[serde(default)]
+    pub(super) signing_key: Option<String>,
+}
+
+pub(super) struct TokenStore {
+    pub(super) tokens: HashMap<String, TokenEntry>,
+}
+
+// The demo tokens, used when no tokens file is configured
+impl Default for TokenStore {
+    fn default() -> Self {
+        let tokens = ["alice", "bob", "charlie"]
+            .into_iter()
+            .map(|user| {
+                let entry = TokenEntry {
+                    user: user.to_string(),
+                    tags: Vec::new(),
+                    signing_key: None,
+                };
+                (format!("token-{}", user), entry)
+            })
+            .collect();
+        TokenStore { tokens }
+    }
+}
+
+impl TokenStore {
+    // A JSON object from token to `{"user": ..., "tags": [...],
+    // "signing_key": ...}`; it replaces the demo tokens
+    pub(super) fn load(path: &str) -> anyhow::Result<TokenStore> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading tokens {}: {}", path, e))?;
+        let tokens = serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("parsing tokens {}: {}", path, e))?;
+        Ok(TokenStore { tokens })
+    }
+
+    pub(super) fn get(&self, token: &str) -> Option<&TokenEntry> {
+        self.tokens.get(token)
+    }
+
+    // For reconnect tokens, which only know the username
+    pub(super) fn for_user(&self, username: &str) -> Option<&TokenEntry> {
+        self.tokens.values().find(|entry| entry.user == username)
+    }
+}
@@ -0,0 +1,147 @@
+//! Message translation through an external command.
+
+use crate::protocol::ServerMessage;
+use crate::server::{Client, Server};
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+pub(super) trait Translator: Send + Sync {
+    // `None` when there's no translation; delivery goes ahead without one
+    fn translate<'a>(
+        &'a self,
+        content: &'a str,
+        to: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Option<String>> + Send + 'a>>;
+}
+
+// Runs `program <lang>` per translation and gives up after `timeout`
+pub(super) struct CommandTranslator {
+    pub(super) program: String,
+    pub(super) timeout: Duration,
+}
+
+impl Translator for CommandTranslator {
+    fn translate<'a>(
+        &'a self,
+        content: &'a str,
+        to: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Option<String>> + Send + 'a>> {
+        Box::pin(async move {
+            let run = async {
+                let mut child = tokio::process::Command::new(&self.program)
+                    .arg(to)
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .ok()?;
+                let mut stdin = child.stdin.take()?;
+                stdin.write_all(content.as_bytes()).await.ok()?;
+                drop(stdin);
+                let output = child.wait_with_output().await.ok()?;
+                let text = String::from_utf8(output.stdout).ok()?;
+                let text = text.trim_end();
+                (output.status.success() && !text.is_empty()).then(|| text.to_string())
+            };
+            match tokio::time::timeout(self.timeout, run).await {
+                Ok(translated) => translated,
+                Err(_) => {
+                    eprintln!("translating to {} timed out", to);
+                    None
+                }
+            }
+        })
+    }
+}
+
+// Languages recipients read that differ from the sender's (or the server
+// default, for senders that didn't pick one)
+pub(super) fn translation_targets(
+    server: &Server,
+    clients: &HashMap<String, Client>,
+    msg: &ServerMessage,
+) -> HashSet<String> {
+    let source = clients
+        .get(&msg.from)
+        .and_then(|sender| sender.lang.clone())
+        .unwrap_or_else(|| server.config.default_lang.to_lowercase());
+    let recipients: Vec<&String> = match (&msg.members, &msg.to) {
+        (Some(members), _) => members.iter().collect(),
+        (None, Some(target)) => vec![target],
+        (None, None) => clients.keys().collect(),
+    };
+    recipients
+        .into_iter()
+        .filter(|user| **user != msg.from)
+        .filter_map(|user| clients.get(user)?.lang.clone())
+        .filter(|lang| *lang != source)
+        .collect()
+}
+
+// One call per language, all at once
+pub(super) async fn translate_into(
+    translator: &dyn Translator,
+    content: &str,
+    langs: HashSet<String>,
+) -> HashMap<String, String> {
+    let jobs = langs.into_iter().map(|lang| async move {
+        let translated = translator.translate(content, &lang).await;
+        translated.map(|translated| (lang, translated))
+    });
+    futures_util::future::join_all(jobs)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+pub(super) fn translated_for(
+    translations: &HashMap<String, String>,
+    client: &Client,
+) -> Option<String> {
+    translations.get(client.lang.as_deref()?).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    struct Tagging;
+
+    impl Translator for Tagging {
+        fn translate<'a>(
+            &'a self,
+            content: &'a str,
+            to: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Option<String>> + Send + 'a>> {
+            Box::pin(async move { Some(format!("[{}] {}", to, content)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn recipients_in_another_language_get_a_translation() {
+        let mut server = Server::new(config()).unwrap();
+        server.translator = Some(Box::new(Tagging));
+        let test = TestServer::start_with(server).await;
+        let mut alice = test
+            .login_with(json!({ "token": "token-alice", "lang": "en" }))
+            .await;
+        let mut bob = test
+            .login_with(json!({ "token": "token-bob", "lang": "fr" }))
+            .await;
+        let mut charlie = test
+            .login_with(json!({ "token": "token-charlie", "lang": "en" }))
+            .await;
+
+        alice.send(json!({ "to": null, "content": "hello" })).await;
+        let french = bob.expect_message().await;
+        assert_eq!(french["content"], "hello");
+        assert_eq!(french["translated"], "[fr] hello");
+        let english = charlie.expect_message().await;
+        assert!(english.get("translated").is_none(), "{}", english);
+    }
+}
@@ -0,0 +1,210 @@
+//! Minimum client versions.
+
+use crate::protocol::AuthReply;
+use crate::server::Server;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub(super) struct ClientVersions {
+    // Client name -> oldest version let in
+    pub(super) minimums: HashMap<String, String>,
+}
+
+impl ClientVersions {
+    pub(super) fn load(path: &str) -> anyhow::Result<ClientVersions> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading client versions {}: {}", path, e))?;
+        Ok(ClientVersions::parse(&text))
+    }
+
+    // One `<client name> <min version>` per line; `#` starts a comment
+    pub(super) fn parse(text: &str) -> ClientVersions {
+        let mut minimums = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(char::is_whitespace) {
+                Some((name, version)) if parse_version(version.trim()).is_some() => {
+                    minimums.insert(name.to_string(), version.trim().to_string());
+                }
+                _ => eprintln!(
+                    "client versions line {}: expected `<name> <version>`",
+                    number + 1
+                ),
+            }
+        }
+        ClientVersions { minimums }
+    }
+}
+
+// Checked once the token is known to be good. Clients that don't say what
+// they are count as unknown.
+pub(super) fn check_client_version(
+    server: &Server,
+    name: Option<&str>,
+    version: Option<&str>,
+) -> Result<(), AuthReply> {
+    let versions = server.client_versions.read().unwrap();
+    let Some(minimum) = name.and_then(|name| versions.minimums.get(name)) else {
+        if server.config.reject_unknown_clients {
+            return Err(AuthReply::AuthFailed {
+                message: "Unknown client".into(),
+            });
+        }
+        return Ok(());
+    };
+
+    let current = version.and_then(parse_version);
+    if current.is_some_and(|current| current >= parse_version(minimum).unwrap()) {
+        return Ok(());
+    }
+    Err(AuthReply::UpgradeRequired {
+        message: format!("This server needs version {} or newer", minimum),
+        min_version: minimum.clone(),
+        download_url: server.config.upgrade_url.clone(),
+    })
+}
+
+// Semver precedence: `major.minor.patch`, with missing parts as 0, an
+// optional leading `v` and `+build` ignored. A `-pre` release sorts before
+// its release.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Version {
+    pub(super) core: [u64; 3],
+    // `None` is the release itself, which outranks any pre-release
+    pub(super) pre: std::cmp::Reverse<Option<std::cmp::Reverse<Vec<PreIdentifier>>>>,
+}
+
+// Numeric identifiers sort before alphanumeric ones
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum PreIdentifier {
+    Numeric(u64),
+    Text(String),
+}
+
+pub(super) fn parse_version(text: &str) -> Option<Version> {
+    let text = text.strip_prefix('v').unwrap_or(text);
+    let text = text.split_once('+').map_or(text, |(version, _)| version);
+    let (core_text, pre_text) = match text.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (text, None),
+    };
+
+    let mut core = [0; 3];
+    let parts: Vec<&str> = core_text.split('.').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    for (slot, part) in core.iter_mut().zip(parts) {
+        *slot = part.parse().ok()?;
+    }
+
+    let pre = pre_text.map(|pre| {
+        pre.split('.')
+            .map(|identifier| match identifier.parse() {
+                Ok(number) => PreIdentifier::Numeric(number),
+                Err(_) => PreIdentifier::Text(identifier.to_string()),
+            })
+            .collect()
+    });
+    Some(Version {
+        core,
+        pre: std::cmp::Reverse(pre.map(std::cmp::Reverse)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+
+    #[test]
+    fn versions_follow_semver_precedence() {
+        let order = [
+            "0.9",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "v1.0.0",
+            "1.0.1+build.5",
+            "1.10.0",
+        ];
+        for pair in order.windows(2) {
+            let (older, newer) = (parse_version(pair[0]), parse_version(pair[1]));
+            assert!(older.unwrap() < newer.unwrap(), "{} < {}", pair[0], pair[1]);
+        }
+        assert!(parse_version("1.0.0+a") == parse_version("1"));
+        assert!(parse_version("1.x").is_none());
+        assert!(parse_version("1.2.3.4").is_none());
+    }
+
+    async fn auth(test: &TestServer, name: &str, version: &str) -> TestClient {
+        let mut client = test.connect().await;
+        client
+            .send(json!({
+                "token": "token-bob",
+                "client_name": name,
+                "client_version": version,
+            }))
+            .await;
+        client
+    }
+
+    #[tokio::test]
+    async fn old_clients_are_told_to_upgrade_and_closed() {
+        let path = temp_file("versions.txt");
+        std::fs::write(&path, "# name version\npushterm 1.2.0\n").unwrap();
+        let mut config = config();
+        config.client_versions = Some(path.clone());
+        config.upgrade_url = Some("https://example.com/pushterm".to_string());
+        let test = TestServer::start(config).await;
+
+        let mut old = auth(&test, "pushterm", "1.2.0-rc.1").await;
+        let reply = old.expect_type("upgrade_required").await;
+        assert_eq!(reply["min_version"], "1.2.0");
+        assert_eq!(reply["download_url"], "https://example.com/pushterm");
+        let close = old.expect_close().await.unwrap();
+        assert_eq!(u16::from(close.code), 4426);
+
+        let mut current = auth(&test, "pushterm", "v1.2.0").await;
+        current.expect_type("auth_success").await;
+        current.close().await;
+        test.wait_offline("bob").await;
+        let mut unknown = auth(&test, "other", "0.1").await;
+        unknown.expect_type("auth_success").await;
+        unknown.close().await;
+        test.wait_offline("bob").await;
+
+        // What a SIGHUP reload swaps in applies to the next connection
+        std::fs::write(&path, "pushterm 2.0.0\n").unwrap();
+        *test.server.client_versions.write().unwrap() = ClientVersions::load(&path).unwrap();
+        let mut now_old = auth(&test, "pushterm", "1.9.9").await;
+        let reply = now_old.expect_type("upgrade_required").await;
+        assert_eq!(reply["min_version"], "2.0.0");
+    }
+
+    #[tokio::test]
+    async fn unknown_clients_can_be_refused() {
+        let mut config = config();
+        config.reject_unknown_clients = true;
+        let test = TestServer::start(config).await;
+        test.server
+            .client_versions
+            .write()
+            .unwrap()
+            .minimums
+            .insert("pushterm".to_string(), "1.0.0".to_string());
+
+        let mut unknown = auth(&test, "other", "9.9.9").await;
+        let reply = unknown.expect_type("auth_failed").await;
+        assert_eq!(reply["message"], "Unknown client");
+        let mut known = auth(&test, "pushterm", "1.0.0").await;
+        known.expect_type("auth_success").await;
+    }
+}
@@ -0,0 +1,257 @@
+//! Incoming webhooks.
+
+use crate::protocol::ServerMessage;
+use crate::server::Server;
+use crate::server::config::Config;
+use crate::server::http::{http_response, read_request_head};
+use crate::server::routing::route_message;
+use crate::server::signing::constant_time_eq;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+pub(super) const MAX_WEBHOOK_BODY: usize = 64 * 1024;
+
+// Slack-style incoming webhook payload
+#[derive(Deserialize, Debug)]
+pub(super) struct WebhookPayload {
+    #[serde(default)]
+    pub(super) text: String,
+    pub(super) channel: Option<String>,
+    #[serde(default)]
+    pub(super) attachments: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub(super) blocks: Vec<serde_json::Value>,
+}
+
+// POST /hooks/<secret>: the secret path segment selects the bot identity
+pub(super) async fn handle_webhook(stream: TcpStream, server: Arc<Server>) {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    let Some((request_line, headers)) = read_request_head(&mut reader).await else {
+        return;
+    };
+
+    let response = match receive_webhook(&mut reader, &request_line, &headers, &server.config).await
+    {
+        Ok(msg) => match route_message(&server, msg).await {
+            Ok(()) => http_response("200 OK", "ok"),
+            Err(code) => http_response(rejection_status(code), code),
+        },
+        Err((status, reason)) => http_response(status, reason),
+    };
+
+    let _ = write_half.write_all(response.as_bytes()).await;
+}
+
+// Compares against every secret in constant time, so response timing
+// doesn't reveal how much of a guess was right
+pub(super) fn find_hook<'a>(
+    hooks: &'a HashMap<String, String>,
+    secret: &str,
+) -> Option<&'a String> {
+    let mut found = None;
+    for (hook_secret, bot) in hooks {
+        if constant_time_eq(hook_secret, secret) {
+            found = Some(bot);
+        }
+    }
+    found
+}
+
+// Status for a message `route_message` turned away
+pub(super) fn rejection_status(code: &str) -> &'static str {
+    match code {
+        "rate_limited" => "429 Too Many Requests",
+        "duplicate_message" => "409 Conflict",
+        _ => "403 Forbidden",
+    }
+}
+
+pub(super) async fn receive_webhook<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    request_line: &str,
+    headers: &[(String, String)],
+    config: &Config,
+) -> Result<ServerMessage, (&'static str, &'static str)> {
+    let bot = request_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|target| target.strip_prefix("/hooks/"))
+        .and_then(|secret| find_hook(&config.webhooks, secret))
+        .ok_or(("404 Not Found", "no_such_hook"))?;
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_WEBHOOK_BODY {
+        return Err(("413 Payload Too Large", "payload_too_large"));
+    }
+
+    let mut body = vec![0u8; length];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|_| ("400 Bad Request", "invalid_payload"))?;
+
+    let payload: WebhookPayload =
+        serde_json::from_slice(&body).map_err(|_| ("400 Bad Request", "invalid_payload"))?;
+
+    let content = webhook_content(&payload);
+    if content.is_empty() {
+        return Err(("400 Bad Request", "no_text"));
+    }
+
+    // `@user` becomes a DM; there are no rooms yet, so `#channel` (or no
+    // channel at all) is broadcast to everyone
+    let to = payload
+        .channel
+        .as_deref()
+        .and_then(|channel| channel.strip_prefix('@'))
+        .map(str::to_string);
+
+    Ok(ServerMessage {
+        from: bot.clone(),
+        to,
+        content,
+        content_type: None,
+        scheduled: false,
+        suppress_notification: false,
+        conversation_id: None,
+        members: None,
+        translated: None,
+        verified: false,
+        client_id: None,
+    })
+}
+
+// Flattens text, attachments and blocks into plain content; anything
+// without a text representation is dropped
+pub(super) fn webhook_content(payload: &WebhookPayload) -> String {
+    let mut parts = Vec::new();
+
+    if !payload.text.is_empty() {
+        parts.push(payload.text.clone());
+    }
+
+    for attachment in &payload.attachments {
+        for key in ["pretext", "title"] {
+            if let Some(text) = attachment.get(key).and_then(|v| v.as_str()) {
+                parts.push(text.to_string());
+            }
+        }
+        if let Some(text) = attachment
+            .get("text")
+            .or_else(|| attachment.get("fallback"))
+            .and_then(|v| v.as_str())
+        {
+            parts.push(text.to_string());
+        }
+    }
+
+    for block in &payload.blocks {
+        if let Some(text) = block
+            .get("text")
+            .and_then(|text| text.get("text"))
+            .and_then(|v| v.as_str())
+        {
+            parts.push(text.to_string());
+        }
+    }
+
+    parts.retain(|part| !part.trim().is_empty());
+    parts.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use crate::server::unix_now;
+
+    fn post(secret: &str, body: &str) -> String {
+        format!(
+            "POST /hooks/{} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            secret,
+            body.len(),
+            body
+        )
+    }
+
+    async fn start() -> TestServer {
+        let mut config = config();
+        config.webhooks = HashMap::from([("secret".to_string(), "ci-bot".to_string())]);
+        TestServer::start(config).await
+    }
+
+    #[test]
+    fn finds_hooks_by_exact_secret() {
+        let hooks = HashMap::from([("secret".to_string(), "ci-bot".to_string())]);
+        assert_eq!(
+            find_hook(&hooks, "secret").map(String::as_str),
+            Some("ci-bot")
+        );
+        assert_eq!(find_hook(&hooks, "secre"), None);
+        assert_eq!(find_hook(&hooks, "secreT"), None);
+    }
+
+    #[tokio::test]
+    async fn delivers_to_the_channel_user() {
+        let test = start().await;
+        let mut alice = test.login("token-alice").await;
+        test.wait_online("alice").await;
+
+        let response = test
+            .http(&post(
+                "secret",
+                r#"{"text": "deploy done", "channel": "@alice"}"#,
+            ))
+            .await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let msg = alice.expect_message().await;
+        assert_eq!(msg["from"], "ci-bot");
+        assert_eq!(msg["to"], "alice");
+        assert_eq!(msg["content"], "deploy done");
+    }
+
+    #[tokio::test]
+    async fn unknown_secret_is_not_found() {
+        let test = start().await;
+        let response = test.http(&post("guess", r#"{"text": "hi"}"#)).await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn muted_bot_gets_forbidden_with_the_code() {
+        let test = start().await;
+        test.server
+            .mutes
+            .lock()
+            .unwrap()
+            .insert("ci-bot", unix_now() + 60);
+
+        let response = test.http(&post("secret", r#"{"text": "hi"}"#)).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(response.ends_with("muted"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_bot_gets_too_many_requests() {
+        let mut config = config();
+        config.webhooks = HashMap::from([("secret".to_string(), "ci-bot".to_string())]);
+        config.rate_limit = 1;
+        let test = TestServer::start(config).await;
+
+        let first = test.http(&post("secret", r#"{"text": "one"}"#)).await;
+        assert!(first.starts_with("HTTP/1.1 200"), "{}", first);
+        let second = test.http(&post("secret", r#"{"text": "two"}"#)).await;
+        assert!(second.starts_with("HTTP/1.1 429"), "{}", second);
+        assert!(second.ends_with("rate_limited"), "{}", second);
+    }
+}
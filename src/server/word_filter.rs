@@ -0,0 +1,300 @@
+//! The word filter.
+
+use crate::protocol::{ServerFrame, ServerMessage, Severity};
+use crate::server::Server;
+use crate::server::middleware::{Middleware, Verdict};
+use crate::server::pattern::Pattern;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum FilterAction {
+    // Error back to the sender, nothing delivered
+    Reject,
+    // Deliver with the word replaced by asterisks
+    Redact,
+    // Deliver as is and tell the admins
+    Flag,
+}
+
+pub(super) enum FilterMatcher {
+    // Lowercase words that must appear in a row
+    Words(Vec<String>),
+    Pattern(Pattern),
+}
+
+pub(super) struct FilterRule {
+    pub(super) action: FilterAction,
+    // As written in the rules file; hit counts are keyed by it
+    pub(super) word: String,
+    pub(super) matcher: FilterMatcher,
+}
+
+// Literal words and phrases matched whole, plus `/regex/` patterns, all
+// ignoring case
+#[derive(Default)]
+pub(super) struct WordFilter {
+    pub(super) rules: Vec<FilterRule>,
+}
+
+impl WordFilter {
+    pub(super) fn load(path: &str) -> anyhow::Result<WordFilter> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading word filter {}: {}", path, e))?;
+        Ok(WordFilter::parse(&text))
+    }
+
+    // One `<reject|redact|flag> <words or /regex/>` per line; `#` starts a
+    // comment
+    pub(super) fn parse(text: &str) -> WordFilter {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let action = match line.split_once(char::is_whitespace) {
+                Some(("reject", word)) => Some((FilterAction::Reject, word)),
+                Some(("redact", word)) => Some((FilterAction::Redact, word)),
+                Some(("flag", word)) => Some((FilterAction::Flag, word)),
+                _ => None,
+            };
+            let Some((action, word)) = action else {
+                eprintln!(
+                    "word filter line {}: expected `<action> <word>`",
+                    number + 1
+                );
+                continue;
+            };
+            let word = word.trim();
+            let matcher = match word
+                .strip_prefix('/')
+                .and_then(|rest| rest.strip_suffix('/'))
+            {
+                Some(source) => match Pattern::parse(source) {
+                    Ok(pattern) => FilterMatcher::Pattern(pattern),
+                    Err(e) => {
+                        eprintln!("word filter line {}: {}", number + 1, e);
+                        continue;
+                    }
+                },
+                None => {
+                    FilterMatcher::Words(word.split_whitespace().map(str::to_lowercase).collect())
+                }
+            };
+            rules.push(FilterRule {
+                action,
+                word: word.to_string(),
+                matcher,
+            });
+        }
+        WordFilter { rules }
+    }
+
+    // Every rule that matches, with the byte range it covers
+    fn hits(&self, content: &str) -> Vec<(std::ops::Range<usize>, &FilterRule)> {
+        let tokens = tokens(content);
+        let mut hits = Vec::new();
+        for rule in &self.rules {
+            match &rule.matcher {
+                FilterMatcher::Words(words) if !words.is_empty() => {
+                    for run in tokens.windows(words.len()) {
+                        let matched = run
+                            .iter()
+                            .zip(words)
+                            .all(|(token, word)| stretches_to(word, &content[token.clone()]));
+                        if matched {
+                            hits.push((run[0].start..run[run.len() - 1].end, rule));
+                        }
+                    }
+                }
+                FilterMatcher::Words(_) => {}
+                FilterMatcher::Pattern(pattern) => {
+                    for range in pattern.find_all(content) {
+                        hits.push((range, rule));
+                    }
+                }
+            }
+        }
+        hits
+    }
+}
+
+// Byte ranges of the runs of letters and digits
+fn tokens(content: &str) -> Vec<std::ops::Range<usize>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in content.char_indices().chain([(content.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(from)) => {
+                tokens.push(from..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+// Runs of the same character, lowercased
+fn runs(word: &str) -> Vec<(char, usize)> {
+    let mut runs: Vec<(char, usize)> = Vec::new();
+    for c in word.chars().flat_map(char::to_lowercase) {
+        match runs.last_mut() {
+            Some((last, count)) if *last == c => *count += 1,
+            _ => runs.push((c, 1)),
+        }
+    }
+    runs
+}
+
+// Whether `token` is `word`, ignoring case, with letters possibly
+// stretched: `SHIIIP` is `ship`, but `book` isn't `bok` since a doubled
+// letter is just spelling. Only runs of three or more count as stretched.
+pub(super) fn stretches_to(word: &str, token: &str) -> bool {
+    let (word, token) = (runs(word), runs(token));
+    word.len() == token.len()
+        && word.iter().zip(&token).all(|((a, wanted), (b, got))| {
+            a == b && (got == wanted || (*got > *wanted && *got >= 3))
+        })
+}
+
+// Redacts in place and returns the flagging rules, or the rejecting rule
+pub(super) fn filter_content(server: &Server, content: &mut String) -> Result<Vec<String>, String> {
+    let filter = server.word_filter.read().unwrap();
+    if filter.rules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let hits = filter.hits(content);
+    {
+        let mut counts = server.filter_hits.lock().unwrap();
+        for (_, rule) in &hits {
+            *counts.entry(rule.word.clone()).or_default() += 1;
+        }
+    }
+
+    if let Some((_, rule)) = hits.iter().find(|(_, r)| r.action == FilterAction::Reject) {
+        return Err(rule.word.clone());
+    }
+
+    let mut flagged = Vec::new();
+    for (_, rule) in &hits {
+        if rule.action == FilterAction::Flag && !flagged.contains(&rule.word) {
+            flagged.push(rule.word.clone());
+        }
+    }
+    // Hits can overlap, so every char inside any redacted range is starred
+    let redacted: Vec<_> = hits
+        .iter()
+        .filter(|(_, rule)| rule.action == FilterAction::Redact)
+        .map(|(range, _)| range.clone())
+        .collect();
+    if !redacted.is_empty() {
+        *content = content
+            .char_indices()
+            .map(|(i, c)| {
+                if redacted.iter().any(|range| range.contains(&i)) {
+                    '*'
+                } else {
+                    c
+                }
+            })
+            .collect();
+    }
+    Ok(flagged)
+}
+
+// Runs the filter on every chat message before fan-out
+pub(super) struct WordFilterMiddleware;
+
+impl Middleware for WordFilterMiddleware {
+    fn on_message(&self, server: &Server, msg: &mut ServerMessage) -> Verdict {
+        match filter_content(server, &mut msg.content) {
+            Ok(flagged) if flagged.is_empty() => Verdict::Pass,
+            Ok(flagged) => Verdict::Flag(ServerFrame::System {
+                severity: Severity::Warning,
+                text: format!("Flagged message from {}: {}", msg.from, msg.content),
+                context: Some(serde_json::json!({
+                    "event": "flag",
+                    "user": msg.from,
+                    "to": msg.to,
+                    "rules": flagged,
+                })),
+            }),
+            Err(_) => Verdict::Reject(
+                "message_rejected",
+                "Your message was blocked by the word filter".to_string(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn words(filter: &WordFilter, content: &str) -> Vec<String> {
+        filter
+            .hits(content)
+            .into_iter()
+            .map(|(range, rule)| format!("{}={}", rule.word, &content[range]))
+            .collect()
+    }
+
+    #[test]
+    fn stretched_letters_match_but_doubled_ones_are_spelling() {
+        assert!(stretches_to("ship", "SHIIIP"));
+        assert!(stretches_to("ship", "Ship"));
+        assert!(stretches_to("book", "boooook"));
+        assert!(!stretches_to("bok", "book"));
+        assert!(!stretches_to("book", "bok"));
+        assert!(!stretches_to("ship", "shipping"));
+    }
+
+    #[test]
+    fn phrases_and_patterns_match() {
+        let filter = WordFilter::parse(
+            "# comment\nredact bok\nflag free money\nreject /sp[a4]+m/\nnonsense\nreject /(unclosed/\n",
+        );
+        assert_eq!(filter.rules.len(), 3);
+        assert_eq!(words(&filter, "a good book"), Vec::<String>::new());
+        assert_eq!(words(&filter, "BOK!"), ["bok=BOK"]);
+        assert_eq!(
+            words(&filter, "get FREE   money now"),
+            ["free money=FREE   money"]
+        );
+        assert!(words(&filter, "free time, money later").is_empty());
+        assert_eq!(words(&filter, "no Sp4aM"), ["/sp[a4]+m/=Sp4aM"]);
+    }
+
+    #[tokio::test]
+    async fn filter_runs_as_middleware_before_delivery() {
+        let path = temp_file("filter.txt");
+        std::fs::write(&path, "reject /sp[a4]m/\nredact darn it\nflag ship\n").unwrap();
+        let mut config = config();
+        config.word_filter = Some(path);
+        config.metrics_interval = Duration::from_secs(1);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = test.login("token-bob").await;
+
+        bob.send(json!({ "to": "alice", "content": "buy SP4M" }))
+            .await;
+        bob.expect_error("message_rejected").await;
+
+        bob.send(json!({ "to": "alice", "content": "oh darn it, a ship" }))
+            .await;
+        let flag = alice.expect_event("flag").await;
+        assert_eq!(flag["context"]["rules"], json!(["ship"]));
+        let message = alice.expect_message().await;
+        assert_eq!(message["content"], "oh *******, a ship");
+
+        alice.send(json!({ "type": "subscribe_metrics" })).await;
+        let stats = alice.expect_type("metrics").await;
+        assert_eq!(stats["filter_hits"]["/sp[a4]m/"], 1, "{}", stats);
+        assert_eq!(stats["filter_hits"]["darn it"], 1, "{}", stats);
+    }
+}
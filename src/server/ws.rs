@@ -0,0 +1,508 @@
+//! WebSocket connections, and routing plain HTTP requests on the same port.
+
+use crate::protocol::AuthReply;
+use crate::server::admin_http::handle_admin_http;
+use crate::server::auth::{auth_success, authenticate};
+use crate::server::broadcast::broadcast_presence;
+use crate::server::console::{run_console, wants_console};
+use crate::server::heartbeat::{RttStats, heartbeat_frame, heartbeat_timer};
+use crate::server::http::{HttpRoute, http_response, peek_http_route, read_request_head};
+use crate::server::routing::{
+    add_client, handle_client_bytes, handle_client_text, reject_invalid_utf8, remove_client,
+};
+use crate::server::sse::handle_sse;
+use crate::server::webhooks::handle_webhook;
+use crate::server::{Client, ConnectionInfo, DisconnectReason, Server, unix_now_ms};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+use tokio_tungstenite::tungstenite::http::{Response as HttpResponse, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+// What a client may send before its warm-up ends; it's all held in memory
+pub(super) const MAX_WARM_UP_FRAMES: usize = 32;
+
+// Refuses upgrades to any path but the configured one and keeps the
+// User-Agent and Origin headers. A `Callback` impl rather than a closure,
+// since its large error type is fixed by the trait.
+pub(super) struct UpgradeCallback {
+    pub(super) server: Arc<Server>,
+    pub(super) headers: Arc<std::sync::Mutex<(Option<String>, Option<String>)>>,
+}
+
+impl Callback for UpgradeCallback {
+    fn on_request(self, req: &Request, res: Response) -> Result<Response, ErrorResponse> {
+        if let Some(path) = &self.server.config.ws_path
+            && req.uri().path() != path
+        {
+            self.server
+                .wrong_path_upgrades
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(HttpResponse::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Some("Not found".to_string()))
+                .unwrap());
+        }
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        *self.headers.lock().unwrap() = (header("user-agent"), header("origin"));
+        Ok(res)
+    }
+}
+
+pub(super) async fn handle_connection(stream: TcpStream, server: Arc<Server>) {
+    // Plain HTTP routes share the port with WebSockets
+    match peek_http_route(&stream).await {
+        Some(HttpRoute::Events) => {
+            handle_sse(stream, server).await;
+            return;
+        }
+        Some(HttpRoute::Hook) => {
+            handle_webhook(stream, server).await;
+            return;
+        }
+        Some(HttpRoute::Health) => {
+            // Unread request bytes would turn the close into a reset
+            let mut stream = BufReader::new(stream);
+            if read_request_head(&mut stream).await.is_some() {
+                let response = http_response("200 OK", "ok");
+                let _ = stream.get_mut().write_all(response.as_bytes()).await;
+            }
+            return;
+        }
+        Some(HttpRoute::Admin) => {
+            handle_admin_http(stream, server).await;
+            return;
+        }
+        None => {}
+    }
+
+    // Accept WebSocket connection, keeping the headers admins can
+    // see in `clients`
+    let peer = stream.peer_addr().ok();
+    let headers = Arc::new(std::sync::Mutex::new((None, None)));
+    let callback = UpgradeCallback {
+        server: server.clone(),
+        headers: headers.clone(),
+    };
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("WebSocket handshake failed: {}", e);
+            return;
+        }
+    };
+
+    let (mut write, mut read) = ws_stream.split();
+
+    // --- AUTH VIA FIRST MESSAGE ---
+    let mut attempts = 0;
+    let auth = loop {
+        let text = match read.next().await {
+            Some(Ok(Message::Text(text))) if wants_console(text.as_ref()) => {
+                run_console(&server, text.as_ref(), write, read).await;
+                return;
+            }
+            Some(Ok(Message::Text(text))) => text.to_string(),
+            Some(Ok(Message::Binary(_))) => String::new(),
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            _ => return,
+        };
+        attempts += 1;
+        let (reply, auth) = match authenticate(&server, &text).await {
+            Ok(auth) => (auth_success(&auth), Some(auth)),
+            Err(reply) => (reply, None),
+        };
+        let _ = write
+            .send(Message::Text(serde_json::to_string(&reply).unwrap().into()))
+            .await;
+        match (auth, reply) {
+            (Some(auth), _) => break auth,
+            (None, AuthReply::AuthRequired { .. }) if attempts < server.config.preauth_attempts => {
+            }
+            (None, AuthReply::UpgradeRequired { .. }) => {
+                let frame = CloseFrame {
+                    code: CloseCode::Library(4426),
+                    reason: "Upgrade required".into(),
+                };
+                let _ = write.send(Message::Close(Some(frame))).await;
+                return;
+            }
+            (None, _) => return,
+        }
+    };
+    let username = auth.username;
+
+    println!("{} connected", username);
+
+    // --- WARM-UP ---
+    // Anything sent meanwhile is handled once the client has joined
+    let mut early = Vec::new();
+    if !server.config.warm_up.is_zero() {
+        let warm_up = tokio::time::sleep(server.config.warm_up);
+        tokio::pin!(warm_up);
+        loop {
+            tokio::select! {
+                biased;
+                _ = &mut warm_up => break,
+                msg = read.next() => match msg {
+                    Some(Ok(Message::Text(text))) if early.len() < MAX_WARM_UP_FRAMES => {
+                        early.push(text.to_string())
+                    }
+                    Some(Ok(Message::Text(_))) => {
+                        println!("{} sent too much during warm-up", username);
+                        let frame = CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: "Too many messages during warm-up".into(),
+                        };
+                        let _ = write.send(Message::Close(Some(frame))).await;
+                        return;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        println!("{} left during warm-up", username);
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (mut client, mut kicked) = Client::new(tx.clone());
+    // Round trips of answered pings, recorded by the reader
+    let rtt = Arc::new(std::sync::Mutex::new(RttStats::default()));
+    client.rtt = Some(rtt.clone());
+    let (user_agent, origin) = std::mem::take(&mut *headers.lock().unwrap());
+    client.connection = ConnectionInfo {
+        transport: "ws",
+        peer,
+        user_agent,
+        origin,
+    };
+    client.capabilities = auth.capabilities;
+    client.tags = auth.tags;
+    client.signing_key = auth.signing_key;
+    client.resume_after = auth.resume_after;
+    client.lang = auth.lang.as_deref().map(str::to_lowercase);
+    client.templates = server
+        .catalog
+        .resolve(auth.lang.as_deref(), &server.config.default_lang);
+    let wants_heartbeat = client.capabilities.contains("server_heartbeat");
+    add_client(&server, &username, client).await;
+
+    broadcast_presence(&server, &username, true, None).await;
+
+    // --- WRITER TASK ---
+    // `None` when the channel closed, i.e. the reader ended first and
+    // dropped `stop`
+    let (stop_writer, mut stop) = oneshot::channel::<()>();
+    let writer_server = server.clone();
+    let writer_rtt = rtt.clone();
+    let mut writer = tokio::spawn(async move {
+        let mut kick_pending = true;
+        let mut stop_pending = true;
+        let mut ping = heartbeat_timer(&writer_server.config);
+        loop {
+            tokio::select! {
+                msg = rx.recv() => {
+                    let Some(msg) = msg else { break None };
+                    if write.send(Message::Text(msg.into())).await.is_err() {
+                        break Some(DisconnectReason::WriteFailed);
+                    }
+                }
+                _ = ping.tick(), if !writer_server.config.heartbeat_interval.is_zero() => {
+                    let sent = unix_now_ms().to_be_bytes().to_vec();
+                    if write.send(Message::Ping(sent.into())).await.is_err() {
+                        break Some(DisconnectReason::WriteFailed);
+                    }
+                    if wants_heartbeat {
+                        let frame = heartbeat_frame(&writer_server, Some(&writer_rtt), rx.len());
+                        if write.send(Message::Text(frame.into())).await.is_err() {
+                            break Some(DisconnectReason::WriteFailed);
+                        }
+                    }
+                }
+                _ = &mut stop, if stop_pending => {
+                    // Flush what's queued but take nothing new, even if a
+                    // sender is still held somewhere
+                    stop_pending = false;
+                    rx.close();
+                }
+                reason = &mut kicked, if kick_pending => {
+                    kick_pending = false;
+                    // An error only means the entry was dropped; keep flushing
+                    if let Ok((why, reason)) = reason {
+                        // Whatever was queued before the kick, like the
+                        // notice saying why, goes out ahead of the close
+                        while let Ok(msg) = rx.try_recv() {
+                            if write.send(Message::Text(msg.into())).await.is_err() {
+                                break;
+                            }
+                        }
+                        let code = match why {
+                            DisconnectReason::ServerShutdown => CloseCode::Away,
+                            _ => CloseCode::Policy,
+                        };
+                        let frame = CloseFrame {
+                            code,
+                            reason: reason.into(),
+                        };
+                        let _ = write.send(Message::Close(Some(frame))).await;
+                        break Some(why);
+                    }
+                }
+            }
+        }
+    });
+
+    // --- READER TASK ---
+    let reader_server = server.clone();
+    let reader_username = username.clone();
+    let mut reader = tokio::spawn(async move {
+        for text in early {
+            handle_client_text(&reader_server, &reader_username, &text).await;
+        }
+        loop {
+            match read.next().await {
+                Some(Ok(Message::Pong(payload))) => {
+                    if let Ok(sent) = <[u8; 8]>::try_from(&payload[..]) {
+                        let round_trip = unix_now_ms().saturating_sub(u64::from_be_bytes(sent));
+                        rtt.lock().unwrap().record(round_trip);
+                    }
+                }
+                Some(Ok(Message::Close(_))) => break DisconnectReason::ClientClosed,
+                Some(Ok(msg)) => {
+                    if msg.is_text()
+                        && !handle_client_bytes(&reader_server, &reader_username, &msg.into_data())
+                            .await
+                    {
+                        break DisconnectReason::ProtocolError;
+                    }
+                }
+                // tungstenite checks text frames itself and stops reading
+                // after a bad one, so this connection can't carry on
+                Some(Err(WsError::Utf8(_))) => {
+                    reject_invalid_utf8(&reader_server, &reader_username).await;
+                    break DisconnectReason::ProtocolError;
+                }
+                Some(Err(_)) => break DisconnectReason::ConnectionLost,
+                None => break DisconnectReason::ClientClosed,
+            }
+        }
+    });
+
+    // Whichever side stops first ends the connection
+    let (reason, writer_done) = tokio::select! {
+        reason = &mut writer => {
+            reader.abort();
+            (reason.ok().flatten().unwrap_or(DisconnectReason::ConnectionLost), true)
+        }
+        reason = &mut reader => {
+            (reason.unwrap_or(DisconnectReason::ConnectionLost), false)
+        }
+    };
+
+    // With the map entry gone and `stop` dropped the channel closes, so the
+    // writer flushes what's queued and stops
+    let removed = remove_client(&server, &username, &tx).await;
+    drop(stop_writer);
+    if !writer_done {
+        let _ = writer.await;
+    }
+    println!("{} disconnected ({})", username, reason.as_str());
+    if removed {
+        broadcast_presence(&server, &username, false, Some(reason)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio_tungstenite::WebSocketStream;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    #[tokio::test]
+    async fn leaving_during_warm_up_makes_no_presence_noise() {
+        let mut config = config();
+        config.warm_up = Duration::from_millis(300);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        test.wait_online("alice").await;
+
+        let bob = test.login("token-bob").await;
+        bob.close().await;
+        alice
+            .expect_none(Duration::from_millis(600), |frame| {
+                frame["context"]["user"] == "bob"
+            })
+            .await;
+        assert!(!test.server.clients.lock().await.contains_key("bob"));
+
+        let _charlie = test.login("token-charlie").await;
+        let join = alice.expect_event("join").await;
+        assert_eq!(join["context"]["user"], "charlie");
+    }
+
+    #[tokio::test]
+    async fn flooding_during_warm_up_is_closed_with_policy() {
+        let mut config = config();
+        config.warm_up = Duration::from_millis(300);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+        test.wait_online("alice").await;
+
+        let mut bob = test.login("token-bob").await;
+        for i in 0..=MAX_WARM_UP_FRAMES {
+            bob.send(json!({ "content": i.to_string() })).await;
+        }
+        let close = bob.expect_close().await.unwrap();
+        assert_eq!(close.code, CloseCode::Policy);
+        assert_eq!(close.reason, "Too many messages during warm-up");
+        alice
+            .expect_none(Duration::from_millis(500), |frame| {
+                frame["context"]["user"] == "bob" || frame["from"] == "bob"
+            })
+            .await;
+        assert!(!test.server.clients.lock().await.contains_key("bob"));
+    }
+
+    // Upgrades by hand so the test keeps the socket and can write frames
+    // tungstenite would refuse to send
+    async fn raw_client(test: &TestServer) -> WebSocketStream<TcpStream> {
+        let mut stream = TcpStream::connect(test.addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        assert!(head.starts_with(b"HTTP/1.1 101"));
+        WebSocketStream::from_raw_socket(stream, Role::Client, None).await
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_frame_gets_an_error_instead_of_a_panic() {
+        let test = TestServer::start(config()).await;
+        let mut alice = test.login("token-alice").await;
+        let mut bob = raw_client(&test).await;
+        bob.send(Message::Text(r#"{"token":"token-bob"}"#.into()))
+            .await
+            .unwrap();
+        test.wait_online("bob").await;
+
+        // A final, masked text frame whose payload is not UTF-8
+        let payload = [0xff, 0xfe, 0xfd];
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x81, 0x80 | payload.len() as u8];
+        frame.extend(mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        bob.get_mut().write_all(&frame).await.unwrap();
+
+        let error = loop {
+            match bob.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    let frame: serde_json::Value = serde_json::from_str(text.as_ref()).unwrap();
+                    if frame["type"] == "error" {
+                        break frame;
+                    }
+                }
+                other => panic!("no error before {:?}", other),
+            }
+        };
+        assert_eq!(error["code"], "invalid_utf8");
+
+        let leave = alice.expect_event("leave").await;
+        assert_eq!(leave["context"]["user"], "bob");
+        assert_eq!(leave["context"]["reason"], "protocol_error");
+        // The server is still serving everyone else
+        let _charlie = test.login("token-charlie").await;
+    }
+
+    #[tokio::test]
+    async fn upgrades_to_other_paths_get_a_404_and_are_counted() {
+        let mut config = config();
+        config.ws_path = Some("/ws".to_string());
+        let test = TestServer::start(config).await;
+
+        let refused = tokio_tungstenite::connect_async(format!("ws://{}/chat", test.addr)).await;
+        match refused {
+            Err(WsError::Http(response)) => assert_eq!(response.status(), StatusCode::NOT_FOUND),
+            other => panic!("expected a 404, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(test.server.wrong_path_upgrades.load(Ordering::Relaxed), 1);
+
+        let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", test.addr))
+            .await
+            .unwrap();
+        let mut alice = TestClient { ws };
+        alice
+            .send(serde_json::json!({ "token": "token-alice" }))
+            .await;
+        alice.expect_type("auth_success").await;
+
+        let health = test
+            .http("GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await;
+        assert!(health.starts_with("HTTP/1.1 200"), "{}", health);
+        assert_eq!(test.server.wrong_path_upgrades.load(Ordering::Relaxed), 1);
+    }
+
+    // Answers presence probes while waiting, so the observer isn't the one
+    // timed out
+    async fn next_leave(observer: &mut TestClient) -> serde_json::Value {
+        loop {
+            let frame = observer.recv().await.expect("no leave notice");
+            if frame["type"] == "presence_probe" {
+                observer
+                    .send(serde_json::json!({ "type": "probe_reply" }))
+                    .await;
+            } else if frame["context"]["event"] == "leave" {
+                return frame;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn leave_reasons_tell_a_close_from_an_idle_timeout() {
+        let mut config = config();
+        config.probe_interval = Duration::from_millis(100);
+        config.probe_timeout = Duration::from_millis(300);
+        let test = TestServer::start(config).await;
+        let mut alice = test.login("token-alice").await;
+
+        let bob = test.login("token-bob").await;
+        test.wait_online("bob").await;
+        bob.close().await;
+        let leave = next_leave(&mut alice).await;
+        assert_eq!(leave["context"]["user"], "bob");
+        assert_eq!(leave["context"]["reason"], "client_closed");
+
+        let _charlie = test.login("token-charlie").await;
+        let leave = next_leave(&mut alice).await;
+        assert_eq!(leave["context"]["user"], "charlie");
+        assert_eq!(leave["context"]["reason"], "idle_timeout");
+    }
+}
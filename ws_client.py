@@ -23,6 +23,11 @@ async def chat_client(username, token):
             if auth_data.get("type") == "auth_failed":
                 print(f"Authentication failed: {auth_data.get('message')}")
                 return
+
+            if auth_data.get("type") == "server_full":
+                retry_ms = auth_data.get("retry_after_ms", 0)
+                print(f"Server is full, retry in {retry_ms / 1000:.1f}s")
+                return
             
             print(f"Authentication successful!")
             
@@ -31,8 +36,11 @@ async def chat_client(username, token):
                 try:
                     async for message in websocket:
                         data = json.loads(message)
-                        if data.get("from") == "SYSTEM":
-                            print(f"\n[SYSTEM] {data.get('content')}")
+                        if data.get("type") == "error":
+                            print(f"\n[ERROR] {data.get('message')} ({data.get('code')})")
+                        elif data.get("type") == "system":
+                            severity = data.get("severity", "info").upper()
+                            print(f"\n[SYSTEM:{severity}] {data.get('text')}")
                         else:
                             from_user = data.get("from")
                             to_user = data.get("to")